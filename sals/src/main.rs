@@ -7,7 +7,7 @@
 	clippy::doc_markdown
 )]
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use flexstr::SharedStr;
@@ -18,6 +18,7 @@ use semantic_token::*;
 use serde_json::Value;
 use spcasm::cli::Frontend;
 use spcasm::parser::Token;
+use spcasm::sema::instruction::MemoryAddress;
 use spcasm::sema::AssemblyFile;
 use spcasm::{AssemblyCode, AssemblyError, Environment};
 use tower_lsp::jsonrpc::Result;
@@ -39,17 +40,53 @@ struct Backend {
 #[derive(Debug)]
 struct ServerFrontend {
 	collected_diagnostics: Mutex<Vec<AssemblyError>>,
+	include_directories:   RwLock<Vec<PathBuf>>,
+	defines:               RwLock<Vec<(SharedStr, MemoryAddress)>>,
+	relax_branches:        RwLock<bool>,
+	asar:                  RwLock<bool>,
+	warn_unused:           RwLock<bool>,
 }
 
 impl ServerFrontend {
 	pub fn clear_diagnostics(&self) {
 		self.collected_diagnostics.lock().clear();
 	}
+
+	/// Applies the client's initialization options. `includeDirectories` is a list of include directory paths,
+	/// `defines` is an object mapping predefined symbol names to their values, `relaxBranches` enables branch
+	/// relaxation, `asar` enables Asar compatibility mode, and `warnUnused` enables warnings about unused labels,
+	/// macros and unreachable code.
+	pub fn configure(&self, options: &Value) {
+		if let Some(directories) = options.get("includeDirectories").and_then(Value::as_array) {
+			*self.include_directories.write() =
+				directories.iter().filter_map(Value::as_str).map(PathBuf::from).collect();
+		}
+		if let Some(defines) = options.get("defines").and_then(Value::as_object) {
+			*self.defines.write() =
+				defines.iter().filter_map(|(name, value)| Some((name.as_str().into(), value.as_i64()?))).collect();
+		}
+		if let Some(relax_branches) = options.get("relaxBranches").and_then(Value::as_bool) {
+			*self.relax_branches.write() = relax_branches;
+		}
+		if let Some(asar) = options.get("asar").and_then(Value::as_bool) {
+			*self.asar.write() = asar;
+		}
+		if let Some(warn_unused) = options.get("warnUnused").and_then(Value::as_bool) {
+			*self.warn_unused.write() = warn_unused;
+		}
+	}
 }
 
 impl Default for ServerFrontend {
 	fn default() -> Self {
-		Self { collected_diagnostics: Mutex::new(Vec::new()) }
+		Self {
+			collected_diagnostics: Mutex::new(Vec::new()),
+			include_directories:   RwLock::new(Vec::new()),
+			defines:               RwLock::new(Vec::new()),
+			relax_branches:        RwLock::new(false),
+			asar:                  RwLock::new(false),
+			warn_unused:           RwLock::new(false),
+		}
 	}
 }
 
@@ -70,6 +107,30 @@ impl Frontend for ServerFrontend {
 		100
 	}
 
+	fn predefined_symbols(&self) -> Vec<(SharedStr, MemoryAddress)> {
+		self.defines.read().clone()
+	}
+
+	fn include_directories(&self) -> Vec<PathBuf> {
+		self.include_directories.read().clone()
+	}
+
+	fn relax_branches(&self) -> bool {
+		*self.relax_branches.read()
+	}
+
+	fn asar_compatibility(&self) -> bool {
+		*self.asar.read()
+	}
+
+	fn warn_unused(&self) -> bool {
+		*self.warn_unused.read()
+	}
+
+	fn report_file_dependency(&self, _file: &Path) {
+		// Files are tracked by the language client.
+	}
+
 	fn report_diagnostic_impl(&self, diagnostic: AssemblyError) {
 		self.collected_diagnostics.lock().push(diagnostic);
 	}
@@ -77,7 +138,10 @@ impl Frontend for ServerFrontend {
 
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
-	async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+	async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+		if let Some(options) = params.initialization_options {
+			self.frontend.configure(&options);
+		}
 		self.client
 			.log_message(
 				MessageType::INFO,
@@ -385,12 +449,13 @@ impl Backend {
 			let source_code = Arc::new(AssemblyCode::new_from_path(&text, path));
 
 			// Reset relevant parts of the environment: This file as well as the global list (to avoid redefinition
-			// errors). Do this in a separate block to ensure the borrow checker that environment does not leak into the
-			// returned `Future`.
+			// errors). The predefined symbols are then added back by resetting the frontend. Do this in a separate
+			// block to ensure the borrow checker that environment does not leak into the returned `Future`.
 			{
 				let mut environment = self.environment.write();
 				environment.files.remove(&source_code.name);
 				environment.globals.clear();
+				environment.set_error_options(self.frontend.clone());
 			}
 
 			let result = try {
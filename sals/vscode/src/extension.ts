@@ -72,6 +72,13 @@ async function startServer() {
   const clientOptions: LanguageClientOptions = {
     documentSelector: [{ scheme: "file", language: "spc700" }],
     outputChannel: clientOutputChannel,
+    initializationOptions: {
+      includeDirectories: settings.get("includeDirectories"),
+      defines: settings.get("defines"),
+      relaxBranches: settings.get("relaxBranches"),
+      asar: settings.get("asar"),
+      warnUnused: settings.get("warnUnused"),
+    },
   };
 
   // Create the language client and start the client.
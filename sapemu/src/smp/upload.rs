@@ -4,7 +4,7 @@
 
 use anyhow::Result;
 use log::{debug, info};
-use object::read::elf::ElfFile32;
+use object::read::elf::{ElfFile32, ProgramHeader};
 use object::{Object, ObjectSegment};
 
 use super::CpuIOPorts;
@@ -107,7 +107,9 @@ impl Uploader {
 		let mut this = Self::new().with_entry_point(entry_point);
 
 		for segment in file.segments() {
-			let start_address = segment.address() as u16;
+			// The virtual address is the logical address that the segment was assembled for, which differs from the
+			// physical load address after `base`.
+			let start_address = segment.elf_program_header().p_paddr(file.endian()) as u16;
 			let data = segment.data()?;
 			let (blocks, last_block) = data.as_chunks::<256>();
 			this = blocks.iter().enumerate().fold(this, |this, (i, block)| {
@@ -14,8 +14,11 @@
 
 #![allow(unused)]
 
+use std::sync::Arc;
+
 use bitflags::Flags;
 use log::info;
+use object::read::elf::ElfFile32;
 use rstest::rstest;
 use serde::Deserialize;
 use time::macros::format_description;
@@ -24,6 +27,7 @@ use crate::dsp::registers::DspRegisters;
 use crate::dsp::Dsp;
 use crate::memory::Memory;
 use crate::smp::peripherals::{ControlRegister, CpuIOPorts, ProgramStatusWord, TestRegister};
+use crate::smp::upload::Uploader;
 use crate::smp::{
 	Smp, CONTROL, CPUIO0, CPUIO1, CPUIO2, CPUIO3, DSPADDR, DSPDATA, T0DIV, T0OUT, T1DIV, T1OUT, T2DIV, T2OUT, TEST,
 };
@@ -313,3 +317,39 @@ fn single_instruction(
 		},
 	}
 }
+
+/// ELF segments after `base` are assembled for a different address than the one they are loaded to, so the uploader
+/// has to use the physical load address.
+#[test]
+fn elf_upload_uses_load_address() {
+	let code = Arc::new(spcasm::AssemblyCode::new(
+		"org $0400\n\tmov a, #copy_source\ncopy_source:\nbase $80\nroutine:\n\tmov a, routine\n\tbra routine\nbase \
+		 off\n\tjmp $0400\n",
+		&"base.s".to_owned(),
+	));
+	let (_, segments, _) =
+		spcasm::run_assembler_into_segments(&code, spcasm::cli::default_backend_options()).expect("assembly failed");
+	let mut elf = Vec::new();
+	spcasm::elf::write_to_elf(&mut elf, segments.clone(), 0x0400).expect("couldn't write ELF");
+
+	let mut memory = Box::new(Memory::new());
+	let mut smp = Smp::new(&mut memory);
+	let mut dsp = DspRegisters::default();
+	let mut uploader = Uploader::from_elf(&ElfFile32::parse(elf.as_slice()).expect("invalid ELF")).unwrap();
+	// The upload of a few bytes takes far less than a million cycles. Afterwards, the boot ROM jumps to the entry
+	// point, which loops forever.
+	for _ in 0 .. 1_000_000 {
+		if !uploader.is_finished() {
+			uploader.perform_step(&mut smp.ports);
+		}
+		smp.tick(&mut memory, &mut dsp);
+	}
+	assert!(uploader.is_finished(), "upload did not finish");
+	assert!((0x0400 .. 0x0409).contains(&smp.pc), "entry point not executed, PC is {:04X}", smp.pc);
+
+	assert!(segments.segments.len() > 1);
+	for (start, data) in segments.segments {
+		let start = usize::try_from(start).unwrap();
+		assert_eq!(&memory.ram[start .. start + data.len()], data.as_slice(), "segment at {start:04X} not uploaded");
+	}
+}
@@ -1,5 +1,17 @@
 "use strict";
 
+// Parses a list of symbol definitions like "DEBUG LEVEL=$10" into an object of names and values.
+function parseDefines(text) {
+  const defines = {};
+  for (const definition of text.split(/[\s,]+/)) {
+    if (definition.length === 0) continue;
+    const [name, valueText = "1"] = definition.split("=", 2);
+    const value = Number(valueText.replace(/^\$/, "0x").replace(/^%/, "0b"));
+    if (Number.isInteger(value)) defines[name] = value;
+  }
+  return defines;
+}
+
 // Creates an options object from the UI input.
 function createOptions() {
   return {
@@ -11,6 +23,10 @@ function createOptions() {
     max_macro_expansion_depth: Number(
       document.querySelector("#max-macro-expansion-depth").value,
     ),
+    defines: parseDefines(document.querySelector("#defines").value),
+    relax_branches: document.querySelector("#relax-branches").checked,
+    asar: document.querySelector("#asar").checked,
+    warn_unused: document.querySelector("#warn-unused").checked,
   };
 }
 
@@ -18,6 +34,10 @@ const updatingObjects = [
   document.querySelector("code.assembly-source"),
   document.querySelector("#max-reference-resolution-passes"),
   document.querySelector("#max-macro-expansion-depth"),
+  document.querySelector("#defines"),
+  document.querySelector("#relax-branches"),
+  document.querySelector("#asar"),
+  document.querySelector("#warn-unused"),
 ];
 
 for (const object of updatingObjects) {
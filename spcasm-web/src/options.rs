@@ -1,10 +1,14 @@
 //! User-defined options specified through the web interface.
 
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::RwLock;
 
+use flexstr::SharedStr;
 use miette::{Diagnostic, Severity};
 use serde::{Deserialize, Serialize};
 use spcasm::cli::Frontend;
+use spcasm::sema::instruction::MemoryAddress;
 use spcasm::AssemblyError;
 
 #[allow(clippy::module_name_repetitions)]
@@ -14,6 +18,14 @@ pub struct WebOptions {
 	pub silenced:                        Vec<String>,
 	pub max_reference_resolution_passes: usize,
 	pub max_macro_expansion_depth:       usize,
+	#[serde(default)]
+	pub defines:                         HashMap<String, MemoryAddress>,
+	#[serde(default)]
+	pub relax_branches:                  bool,
+	#[serde(default)]
+	pub asar:                            bool,
+	#[serde(default)]
+	pub warn_unused:                     bool,
 	#[serde(skip)]
 	pub diagnostics:                     RwLock<Vec<AssemblyError>>,
 }
@@ -36,6 +48,31 @@ impl Frontend for WebOptions {
 		self.max_macro_expansion_depth
 	}
 
+	fn predefined_symbols(&self) -> Vec<(SharedStr, MemoryAddress)> {
+		self.defines.iter().map(|(name, value)| (name.as_str().into(), *value)).collect()
+	}
+
+	fn include_directories(&self) -> Vec<PathBuf> {
+		// There is no file system to include from on the web.
+		Vec::new()
+	}
+
+	fn relax_branches(&self) -> bool {
+		self.relax_branches
+	}
+
+	fn asar_compatibility(&self) -> bool {
+		self.asar
+	}
+
+	fn warn_unused(&self) -> bool {
+		self.warn_unused
+	}
+
+	fn report_file_dependency(&self, _file: &Path) {
+		// noop
+	}
+
 	fn report_diagnostic_impl(&self, diagnostic: AssemblyError) {
 		super::log!("pushed new diagnostic: {:?}", diagnostic);
 		self.diagnostics.write().unwrap().push(diagnostic);
@@ -0,0 +1,242 @@
+//! Direct page analysis, which tracks the state of the P flag through the program and warns about direct page operands
+//! that refer to labels in the page which is not currently selected as the direct page.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+#[allow(unused)]
+use flexstr::{shared_str, IntoSharedStr, SharedStr, ToSharedStr};
+use miette::SourceSpan;
+
+use super::AssembledData;
+use crate::directive::DirectiveValue;
+use crate::error::AssemblyError;
+use crate::sema::instruction::{Instruction, Mnemonic};
+use crate::sema::reference::Reference;
+use crate::sema::{AddressingMode, AssemblyTimeValue, ProgramElement, Register};
+use crate::Segments;
+
+/// What is known about the P flag at some point in the program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PFlag {
+	/// This point is never reached by falling through or jumping, so nothing is known (and nothing needs to be known).
+	Unreachable,
+	/// The P flag is known to select the given direct page.
+	Known {
+		/// The direct page, 0 or 1.
+		page:   u8,
+		/// The instruction or directive that selected the direct page.
+		origin: SourceSpan,
+	},
+	/// The P flag may select either direct page.
+	Unknown,
+}
+
+impl PFlag {
+	/// Combines the states of two paths that lead to the same point in the program.
+	const fn merge(self, other: Self) -> Self {
+		match (self, other) {
+			(Self::Unreachable, other) => other,
+			(this, Self::Unreachable) => this,
+			(this @ Self::Known { page, .. }, Self::Known { page: other_page, .. }) if page == other_page => this,
+			_ => Self::Unknown,
+		}
+	}
+}
+
+/// Key for identifying labels by identity.
+type LabelId = usize;
+
+/// Returns the label that this value refers to directly, if any.
+fn direct_label(value: &AssemblyTimeValue) -> Option<LabelId> {
+	match value {
+		AssemblyTimeValue::Reference(Reference::Label(label), ..) => Some(Arc::as_ptr(label) as LabelId),
+		_ => None,
+	}
+}
+
+/// Returns the source operand holding the jump target of the instruction, if it has a jump target that the analysis
+/// can follow. Indirect jumps can't be followed.
+fn jump_target(instruction: &Instruction) -> Option<&AddressingMode> {
+	if !instruction.opcode.mnemonic.has_jump_target() {
+		return None;
+	}
+	instruction
+		.source_operands
+		.1
+		.as_ref()
+		.or(instruction.source_operands.0.as_ref())
+		.filter(|operand| matches!(operand, AddressingMode::Address(..) | AddressingMode::DirectPage(..)))
+}
+
+/// Appends all program elements that are assembled to the list, descending into the assembled block of conditionals.
+fn flatten_elements<'a>(elements: &'a [ProgramElement], flattened: &mut Vec<&'a ProgramElement>) {
+	for element in elements {
+		match element {
+			ProgramElement::Directive(directive) => match &directive.value {
+				DirectiveValue::Conditional { condition, true_block, false_block } =>
+					flatten_elements(if condition.is_truthy() { true_block } else { false_block }, flattened),
+				_ => flattened.push(element),
+			},
+			_ => flattened.push(element),
+		}
+	}
+}
+
+impl AssembledData {
+	/// Tracks the P flag through straight-line code and known branch targets, and warns about direct page operands that
+	/// refer to a label in the other page. Only labels that are exclusively targeted by direct jumps and branches keep
+	/// the state of the P flag, since any other use of a label might lead to code with an arbitrary P flag.
+	pub(super) fn check_direct_page_usage(&self, segments: &Segments<ProgramElement>) {
+		let segments: Vec<Vec<&ProgramElement>> = segments
+			.segments
+			.values()
+			.map(|elements| {
+				let mut flattened = Vec::new();
+				flatten_elements(elements, &mut flattened);
+				flattened
+			})
+			.collect();
+
+		// Count the direct jumps to each label, so that labels used in other ways can be detected.
+		let mut jump_counts: HashMap<LabelId, usize> = HashMap::new();
+		for instruction in segments.iter().flatten().filter_map(|element| match element {
+			ProgramElement::Instruction(instruction) => Some(instruction),
+			_ => None,
+		}) {
+			if let Some(label) = jump_target(instruction).and_then(AddressingMode::number_ref).and_then(direct_label) {
+				*jump_counts.entry(label).or_default() += 1;
+			}
+		}
+
+		// The state of the P flag for all jumps to a label only grows less precise, so this terminates quickly.
+		let mut incoming = HashMap::new();
+		loop {
+			let previous = incoming.clone();
+			for segment in &segments {
+				self.analyze_direct_page_segment(segment, &jump_counts, &mut incoming, false);
+			}
+			if previous == incoming {
+				break;
+			}
+		}
+		for segment in &segments {
+			self.analyze_direct_page_segment(segment, &jump_counts, &mut incoming, true);
+		}
+	}
+
+	/// Runs the P flag analysis on a single segment, merging the states at jumps into the incoming states of the
+	/// targeted labels. If `report` is set, wrong direct page accesses are reported.
+	fn analyze_direct_page_segment(
+		&self,
+		segment: &[&ProgramElement],
+		jump_counts: &HashMap<LabelId, usize>,
+		incoming: &mut HashMap<LabelId, PFlag>,
+		report: bool,
+	) {
+		// Nothing is known about the state of the P flag at the start of a segment.
+		let mut state = PFlag::Unknown;
+		for element in segment {
+			match element {
+				ProgramElement::Label(Reference::Label(label)) => {
+					let id = Arc::as_ptr(label) as LabelId;
+					let jumps = jump_counts.get(&id).copied().unwrap_or_default();
+					// The same usage may be recorded more than once, so only distinct usages are counted.
+					let usages = label.read().usage_spans.iter().collect::<HashSet<_>>().len();
+					state = if usages > jumps {
+						PFlag::Unknown
+					} else {
+						state.merge(incoming.get(&id).copied().unwrap_or(PFlag::Unreachable))
+					};
+				},
+				ProgramElement::Label(_) => state = PFlag::Unknown,
+				ProgramElement::Directive(directive) =>
+					if let DirectiveValue::DirectPage { page } = directive.value {
+						state = PFlag::Known { page, origin: directive.span };
+					},
+				ProgramElement::Instruction(instruction) => {
+					let target = jump_target(instruction);
+					if report && let PFlag::Known { page, origin } = state {
+						for (operand, source_operand) in [
+							(&instruction.opcode.first_operand, &instruction.source_operands.0),
+							(&instruction.opcode.second_operand, &instruction.source_operands.1),
+						]
+						.into_iter()
+						.filter_map(|(operand, source_operand)| Some((operand.as_ref()?, source_operand.as_ref()?)))
+						.filter(|(_, source_operand)| {
+							!target.is_some_and(|target| std::ptr::eq(target, *source_operand))
+						}) {
+							self.check_direct_page_operand(operand, source_operand, page, origin);
+						}
+					}
+
+					if let Some(label) = target.and_then(AddressingMode::number_ref).and_then(direct_label) {
+						let label_state = incoming.entry(label).or_insert(PFlag::Unreachable);
+						*label_state = label_state.merge(state);
+					}
+
+					let mnemonic = instruction.opcode.mnemonic;
+					state = match mnemonic {
+						Mnemonic::Setp => PFlag::Known { page: 1, origin: instruction.span },
+						Mnemonic::Clrp => PFlag::Known { page: 0, origin: instruction.span },
+						Mnemonic::Pop
+							if matches!(
+								instruction.opcode.first_operand,
+								Some(AddressingMode::Register(Register::PSW | Register::P))
+							) =>
+							PFlag::Unknown,
+						_ if mnemonic.ends_control_flow() => PFlag::Unreachable,
+						// Subroutines are assumed to preserve the P flag.
+						_ => state,
+					};
+				},
+				ProgramElement::IncludeSource { .. } | ProgramElement::UserDefinedMacroCall { .. } => {},
+			}
+		}
+	}
+
+	/// Reports a direct page operand that refers to a label outside of the currently selected direct page. The label is
+	/// looked up in the operand as written in the source code, since semantic analysis may have resolved it already.
+	fn check_direct_page_operand(
+		&self,
+		operand: &AddressingMode,
+		source_operand: &AddressingMode,
+		page: u8,
+		page_location: SourceSpan,
+	) {
+		if !matches!(
+			operand,
+			AddressingMode::DirectPage(..)
+				| AddressingMode::DirectPageXIndexed(..)
+				| AddressingMode::DirectPageYIndexed(..)
+				| AddressingMode::DirectPageXIndexedIndirect(..)
+				| AddressingMode::DirectPageIndirectYIndexed(..)
+				| AddressingMode::DirectPageBit(..)
+		) {
+			return;
+		}
+		let Some(value) = source_operand.number_ref() else {
+			return;
+		};
+		let Some(reference) =
+			value.references().into_iter().find(|reference| matches!(reference, Reference::Label(..)))
+		else {
+			return;
+		};
+		let Some(address) = value.value_using_resolver(&|_| None) else {
+			return;
+		};
+		let label_page = address >> 8;
+		if (label_page == 0 || label_page == 1) && label_page != i64::from(page) {
+			self.options.report_diagnostic(AssemblyError::WrongDirectPage {
+				reference: reference.to_string().into(),
+				address,
+				accessed_address: (i64::from(page) << 8) | (address & 0xFF),
+				page,
+				location: value.source_span(),
+				page_location,
+				src: self.source_code.clone(),
+			});
+		}
+	}
+}
@@ -10,9 +10,9 @@ use num_traits::{FromPrimitive, ToPrimitive};
 
 use super::{resolve_file, AssembledData, ClearLabels};
 use crate::brr::wav;
-use crate::directive::{symbolic_directives, DirectiveValue, FillOperation};
+use crate::directive::{symbolic_directives, DirectiveValue, FillOperation, PrintPart, TableLayout};
 use crate::sema::instruction::MemoryAddress;
-use crate::sema::reference::Reference;
+use crate::sema::reference::{Reference, Resolvable};
 use crate::sema::value::{Size, SizedAssemblyTimeValue};
 use crate::sema::AssemblyTimeValue;
 use crate::{brr, AssemblyError, Directive};
@@ -26,16 +26,23 @@ impl AssembledData {
 	///
 	/// # Panics
 	/// All panics are programming bugs.
-	#[allow(clippy::unnecessary_wraps)]
+	#[allow(clippy::unnecessary_wraps, clippy::too_many_lines)]
 	pub(super) fn assemble_directive(
 		&mut self,
 		directive: &mut Directive,
 		current_labels: &[Reference],
 	) -> Result<ClearLabels, Box<AssemblyError>> {
 		match directive.value {
-			// Symbolic directives should not be around anymore.
-			symbolic_directives!() => unreachable!(),
-			DirectiveValue::Table { ref values } => {
+			// Symbolic directives should not be around anymore. The exception are assignments and character map changes
+			// within conditional blocks, which are not split into segments; they were already performed in semantic
+			// analysis.
+			symbolic_directives!() =>
+				if matches!(directive.value, DirectiveValue::AssignReference { .. } | DirectiveValue::Charmap(_)) {
+					Ok(ClearLabels::No)
+				} else {
+					unreachable!()
+				},
+			DirectiveValue::Table { ref values, layout: TableLayout::LittleEndian } => {
 				let mut is_first = true;
 				for value in values {
 					self.append_sized_unresolved(
@@ -47,32 +54,41 @@ impl AssembledData {
 				}
 				Ok(ClearLabels::Yes)
 			},
-			DirectiveValue::Brr { ref file, range, auto_trim, .. } => {
-				self.assemble_brr(directive, file, range, auto_trim, current_labels)?;
+			DirectiveValue::Table { ref values, layout } => {
+				self.assemble_reordered_table(values, layout, current_labels, directive.span)?;
+				Ok(ClearLabels::Yes)
+			},
+			DirectiveValue::Brr { ref file, range, auto_trim, ref conversion, .. } => {
+				self.assemble_brr(directive, file, range, auto_trim, conversion, current_labels)?;
 				Ok(ClearLabels::Yes)
 			},
-			DirectiveValue::String { ref text, has_null_terminator } => {
-				self.append_bytes(text.clone(), current_labels, directive.span)?;
+			DirectiveValue::String { ref encoded, has_null_terminator, .. } => {
+				self.append_bytes(encoded.clone(), current_labels, directive.span)?;
 				if has_null_terminator {
-					self.append(0, if text.is_empty() { current_labels } else { Self::DEFAULT_VEC }, directive.span)?;
+					self.append(
+						0,
+						if encoded.is_empty() { current_labels } else { Self::DEFAULT_VEC },
+						directive.span,
+					)?;
 				}
 				Ok(ClearLabels::Yes)
 			},
 			DirectiveValue::Include { ref file, range } => {
-				let binary_file = resolve_file(&self.source_code, file);
-				let mut binary_data = std::fs::read(binary_file).map_err(|os_error| AssemblyError::FileNotFound {
+				let binary_file = resolve_file(&self.source_code, &self.options.include_directories(), file);
+				let mut binary_data = std::fs::read(&binary_file).map_err(|os_error| AssemblyError::FileNotFound {
 					os_error:  Arc::new(os_error),
 					file_name: file.clone(),
 					src:       self.source_code.clone(),
 					location:  directive.span,
 				})?;
+				self.options.report_file_dependency(&binary_file);
 
 				binary_data = self.slice_data_if_necessary(file, directive.span, binary_data, range)?;
 				self.append_bytes(binary_data, current_labels, directive.span)?;
 				Ok(ClearLabels::Yes)
 			},
 			DirectiveValue::SampleTable { auto_align } => {
-				let current_address = self.segments.current_location().unwrap();
+				let current_address = self.segments.current_logical_location().unwrap();
 				if auto_align {
 					if current_address & 0xff != 0 {
 						let target_address = (current_address + 0x100) & !0xff;
@@ -92,7 +108,7 @@ impl AssembledData {
 				Ok(ClearLabels::Yes)
 			},
 			DirectiveValue::Fill { ref operation, ref parameter, ref value } => {
-				let current_address = self.segments.current_location().unwrap();
+				let current_address = self.segments.current_logical_location().unwrap();
 				self.assemble_fill(
 					operation,
 					parameter,
@@ -112,8 +128,10 @@ impl AssembledData {
 				}?;
 				Ok(ClearLabels::Yes)
 			},
+			// Definition conditionals were replaced by their taken block in semantic analysis.
+			DirectiveValue::DefinitionConditional { .. } => unreachable!(),
 			DirectiveValue::Startpos => {
-				let current = self.segments.current_location().map_err(|()| AssemblyError::MissingSegment {
+				let current = self.segments.current_logical_location().map_err(|()| AssemblyError::MissingSegment {
 					location: directive.span,
 					src:      self.source_code.clone(),
 				})?;
@@ -129,7 +147,150 @@ impl AssembledData {
 					Ok(ClearLabels::No)
 				}
 			},
+			DirectiveValue::Skip { ref amount } => {
+				let current = self.segments.current_location().map_err(|()| AssemblyError::MissingSegment {
+					location: directive.span,
+					src:      self.source_code.clone(),
+				})?;
+				let logical_current = self.segments.current_logical_location().unwrap();
+				let amount = amount.try_value(directive.span, &self.source_code)?;
+				// The labels refer to the skipped memory, not to whatever comes after it.
+				for label in current_labels {
+					label.clone().resolve_to(logical_current, directive.span, self.source_code.clone())?;
+				}
+				if amount != 0 {
+					self.segments.new_segment(current + amount);
+					// Skipping doesn't end a `base` block.
+					if logical_current != current {
+						self.segments.logical_starts.insert(current + amount, logical_current + amount);
+					}
+				}
+				Ok(ClearLabels::Yes)
+			},
+			DirectiveValue::Base { logical_address } => {
+				let current = self.segments.current_logical_location().map_err(|()| AssemblyError::MissingSegment {
+					location: directive.span,
+					src:      self.source_code.clone(),
+				})?;
+				// The labels belong to the code before `base`, so they keep the previous logical address.
+				for label in current_labels {
+					label.clone().resolve_to(current, directive.span, self.source_code.clone())?;
+				}
+				self.segments.new_logical_segment(logical_address).unwrap();
+				Ok(ClearLabels::Yes)
+			},
+			// The direct page annotation only informs the direct page analysis and doesn't produce any data.
+			DirectiveValue::DirectPage { .. } | DirectiveValue::EndSpcBlock { execute: None } => Ok(ClearLabels::No),
+			// These directives may refer to labels that are defined later, so they are handled once all references are
+			// resolved.
+			DirectiveValue::WarnPc { .. }
+			| DirectiveValue::Print { .. }
+			| DirectiveValue::Assert { .. }
+			| DirectiveValue::EndSpcBlock { .. } => {
+				let current = self.segments.current_logical_location().map_err(|()| AssemblyError::MissingSegment {
+					location: directive.span,
+					src:      self.source_code.clone(),
+				})?;
+				self.deferred_directives.push((directive.clone(), current));
+				Ok(ClearLabels::No)
+			},
+		}
+	}
+
+	/// Performs the directives that were deferred until all references are resolved. These directives don't produce any
+	/// data, but they check the assembled program, print messages, or set the entry point.
+	///
+	/// # Errors
+	/// If a check fails, or if a directive's argument cannot be resolved.
+	pub(super) fn perform_deferred_directives(&mut self) -> Result<(), Box<AssemblyError>> {
+		for (directive, pc) in std::mem::take(&mut self.deferred_directives) {
+			match directive.value {
+				DirectiveValue::WarnPc { limit } => {
+					let limit = limit.try_value(directive.span, &self.source_code)?;
+					if pc > limit {
+						return Err(AssemblyError::PcExceedsLimit {
+							pc,
+							limit,
+							location: directive.span,
+							src: self.source_code.clone(),
+						}
+						.into());
+					}
+				},
+				DirectiveValue::Assert { condition, message } =>
+					if condition.try_value(directive.span, &self.source_code)? == 0 {
+						return Err(AssemblyError::AssertionFailed {
+							message,
+							location: condition.source_span(),
+							src: self.source_code.clone(),
+						}
+						.into());
+					},
+				DirectiveValue::Print { parts } => {
+					let message = parts
+						.iter()
+						.map(|part| match part {
+							PrintPart::Text(text) => Ok(text.to_string()),
+							PrintPart::Value { value, radix } =>
+								value.try_value(directive.span, &self.source_code).map(|value| match radix {
+									2 => format!("{value:b}"),
+									16 => format!("{value:X}"),
+									_ => value.to_string(),
+								}),
+						})
+						.try_collect::<String>()?;
+					self.options.report_diagnostic(AssemblyError::UserPrint {
+						message:  message.into(),
+						location: directive.span,
+						src:      self.source_code.clone(),
+					});
+				},
+				DirectiveValue::EndSpcBlock { execute: Some(execute) } => {
+					let execute = execute.try_value(directive.span, &self.source_code)?;
+					if self.entry_point.is_some() {
+						return Err(AssemblyError::DuplicateStartpos {
+							src:      self.source_code.clone(),
+							location: directive.span,
+						}
+						.into());
+					}
+					self.entry_point = Some(execute);
+				},
+				_ => unreachable!(),
+			}
+		}
+		Ok(())
+	}
+
+	/// Assembles a big endian or split table. Since the bytes of an entry are not adjacent in a split table, every byte
+	/// is appended separately. Little endian tables are assembled entry by entry instead.
+	pub(super) fn assemble_reordered_table(
+		&mut self,
+		values: &[SizedAssemblyTimeValue],
+		layout: TableLayout,
+		current_labels: &[Reference],
+		span: SourceSpan,
+	) -> Result<(), Box<AssemblyError>> {
+		// All entries of a table have the same size.
+		let size = values.first().and_then(|value| value.size.to_u8()).unwrap_or(0);
+		let bytes = if layout == TableLayout::Split {
+			(0 .. size).flat_map(|byte| values.iter().map(move |value| (value, byte))).collect::<Vec<_>>()
+		} else {
+			values.iter().flat_map(|value| (0 .. size).rev().map(move |byte| (value, byte))).collect()
+		};
+
+		let mut is_first = true;
+		for (value, byte) in bytes {
+			self.append_8_bits_unresolved(
+				value.value.clone(),
+				byte,
+				byte + 1 == size,
+				if is_first { current_labels } else { Self::DEFAULT_VEC },
+				span,
+			)?;
+			is_first = false;
 		}
+		Ok(())
 	}
 
 	pub(super) fn assemble_brr(
@@ -138,18 +299,20 @@ impl AssembledData {
 		file_name: &str,
 		range: Option<SourceSpan>,
 		auto_trim: bool,
+		conversion: &wav::ConversionOptions,
 		current_labels: &[Reference],
 	) -> Result<(), Box<AssemblyError>> {
-		// Resolve the audio file's path relative to the source file.
-		let actual_path = resolve_file(&self.source_code, file_name);
-		let file = File::open(actual_path).map_err(|os_error| AssemblyError::FileNotFound {
+		// Resolve the audio file's path relative to the source file or the include directories.
+		let actual_path = resolve_file(&self.source_code, &self.options.include_directories(), file_name);
+		let file = File::open(&actual_path).map_err(|os_error| AssemblyError::FileNotFound {
 			os_error:  Arc::new(os_error),
 			file_name: file_name.to_string().into(),
 			src:       self.source_code.clone(),
 			location:  directive.span,
 		})?;
-		let mut sample_data =
-			wav::read_wav_for_brr(file).map_err(|error_text| AssemblyError::AudioProcessingError {
+		self.options.report_file_dependency(&actual_path);
+		let mut sample_data = wav::read_audio_for_brr(file, wav::AudioFormat::from_extension(&actual_path), conversion)
+			.map_err(|error_text| AssemblyError::AudioProcessingError {
 				error_text,
 				file_name: file_name.to_string().into(),
 				src: self.source_code.clone(),
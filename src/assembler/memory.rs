@@ -95,6 +95,16 @@ pub enum MemoryValue {
 	/// An (unresolved) number. The resolved memory value will be the difference between this memory value's location
 	/// plus one and the number's location.
 	NumberRelative(AssemblyTimeValue),
+	/// An (unresolved) number that is the target of a branch instruction which may be relaxed. This resolves like
+	/// [`Self::NumberRelative`] as long as the relative offset is in range. Otherwise, the value stays unresolved, and
+	/// the branch instruction, which is `instruction_length` bytes long and ends with this memory value, is relaxed
+	/// into a longer instruction sequence during reference resolution.
+	RelaxableNumberRelative {
+		/// The unresolved branch target.
+		value:              AssemblyTimeValue,
+		/// Length of the branch instruction in bytes.
+		instruction_length: u8,
+	},
 	/// An (unresolved) number. From the number, the high byte is used, and the higher bits that are used for the bit
 	/// index are discarded. The upper three bits are used for the bit index value which can range from 0 to 7. This is
 	/// used for most absolute bit addressing modes.
@@ -150,6 +160,18 @@ impl MemoryValue {
 				},
 				resolved => Self::NumberRelative(resolved),
 			},
+			Self::RelaxableNumberRelative { value, instruction_length } => match value.try_resolve() {
+				resolved @ AssemblyTimeValue::Literal(reference_memory_address, ..) => {
+					let relative_offset = reference_memory_address - (own_memory_address + 1);
+					// Out of range branches are not reported, since they are relaxed by the assembler. The resolved
+					// target is kept so that the assembler recognizes the branch as out of range.
+					<MemoryAddress as TryInto<i8>>::try_into(relative_offset)
+						.map_or(Self::RelaxableNumberRelative { value: resolved, instruction_length }, |byte| {
+							Self::Resolved(byte as u8)
+						})
+				},
+				resolved => Self::RelaxableNumberRelative { value: resolved, instruction_length },
+			},
 			Self::NumberHighByteWithContainedBitIndex(ref number, bit_index) => match number.clone().try_resolve() {
 				AssemblyTimeValue::Literal(reference_memory_address, ..) => {
 					if reference_memory_address > 0x1FFF {
@@ -175,11 +197,17 @@ impl MemoryValue {
 		source_code: &Arc<AssemblyCode>,
 		frontend: &dyn Frontend,
 	) -> Result<u8, AssemblyTimeValue> {
-		match self.clone().try_resolve(own_memory_address, source_code, frontend) {
+		// Branches that were not relaxed at this point will never be, so their out of range offset must be reported.
+		let value = match self {
+			Self::RelaxableNumberRelative { value, .. } => Self::NumberRelative(value.clone()),
+			_ => self.clone(),
+		};
+		match value.try_resolve(own_memory_address, source_code, frontend) {
 			Self::Resolved(value) => Ok(value),
 			Self::Number { value: number, .. }
 			| Self::NumberHighByteWithContainedBitIndex(number, ..)
-			| Self::NumberRelative(number) => Err(number),
+			| Self::NumberRelative(number)
+			| Self::RelaxableNumberRelative { value: number, .. } => Err(number),
 		}
 	}
 }
@@ -11,19 +11,22 @@ use miette::{Result, SourceSpan};
 use crate::change::Change;
 use crate::cli::{default_backend_options, Frontend};
 use crate::error::AssemblyError;
-use crate::sema::instruction::{Instruction, MemoryAddress, Opcode};
+use crate::sema::instruction::{AddressingModeOptimization, Instruction, MemoryAddress, Opcode};
 use crate::sema::reference::{Reference, Resolvable};
 use crate::sema::value::{BinaryOperator, Size, SizedAssemblyTimeValue};
 use crate::sema::{AddressingMode, AssemblyTimeValue, ProgramElement, Register};
-use crate::{pretty_hex, AssemblyCode, Segments};
+use crate::{pretty_hex, AssemblyCode, Directive, Segments};
 
+mod direct_page;
 mod directive;
 mod memory;
+mod pseudo_instruction;
+mod relaxation;
 pub(crate) mod sample_table;
 mod table;
 
 pub use table::ASSEMBLY_TABLE;
-use table::{EntryOrFirstOperandTable, EntryOrSecondOperandTable, TwoOperandEntry};
+use table::{table_opcode, EntryOrFirstOperandTable, EntryOrSecondOperandTable, TwoOperandEntry};
 
 use self::memory::{LabeledMemoryValue, MemoryValue};
 
@@ -65,6 +68,41 @@ pub fn assemble_inside_segments(
 	Ok((data.resolve_segments()?, data.entry_point))
 }
 
+/// Combine already resolved segments into one binary stream. The result has correct memory addresses, so the first
+/// byte is memory address 0 etc.
+///
+/// # Errors
+/// If the segments contain overlapping data, errors are returned.
+#[allow(clippy::cast_possible_wrap)]
+pub fn combine_resolved_segments(
+	segments: &Segments<u8>,
+	source_code: &Arc<AssemblyCode>,
+) -> Result<Vec<u8>, Box<AssemblyError>> {
+	let mut all_data = Vec::new();
+
+	// The iteration is sorted
+	for (&starting_address, segment_data) in &segments.segments {
+		if starting_address < all_data.len() as MemoryAddress {
+			return Err(AssemblyError::SegmentMismatch {
+				src:           Arc::new(AssemblyCode {
+					text:         pretty_hex(&all_data, Some(starting_address as usize)),
+					name:         source_code.name.clone(),
+					include_path: Vec::new(),
+				}),
+				// TODO: This location is wrong, it ignores newlines.
+				location:      (starting_address as usize * 3 + 1, 2).into(),
+				segment_start: starting_address,
+				segment_end:   all_data.len() as MemoryAddress,
+			}
+			.into());
+		}
+		all_data.resize(starting_address as usize, 0);
+		all_data.extend_from_slice(segment_data);
+	}
+
+	Ok(all_data)
+}
+
 /// Assembles a [`ProgramElement`] inside a loop.
 #[macro_export]
 macro_rules! assemble_element {
@@ -114,22 +152,50 @@ fn assemble_to_data(
 		}
 	}
 
+	if data.options.relax_branches() {
+		data.unresolved_segments = Some(data.segments.clone());
+	}
+
 	let mut pass_count = 0;
-	while pass_count < maximum_reference_resolution_passes
-		&& data.execute_reference_resolution_pass() == Change::Modified
-	{
+	while pass_count < maximum_reference_resolution_passes {
+		let change = data.execute_reference_resolution_pass();
+		// Relaxation changes the memory layout, so reference resolution has to start over.
+		if data.relax_out_of_range_branches() == Change::Modified {
+			pass_count = 0;
+			continue;
+		}
+		if change == Change::Unmodified {
+			break;
+		}
 		pass_count += 1;
 	}
+	data.perform_deferred_directives()?;
+	data.check_direct_page_usage(segments);
 	Ok(data)
 }
 
-pub(crate) fn resolve_file(source_code: &Arc<AssemblyCode>, target_file: &str) -> PathBuf {
-	source_code
+/// Resolves a file path used within the given source code. The path is first resolved relative to the directory of
+/// the source code's file. If no such file exists, the include directories are searched in order. If the file can't be
+/// found anywhere, the path relative to the source code's file is returned, so that errors refer to that path.
+pub(crate) fn resolve_file(
+	source_code: &Arc<AssemblyCode>,
+	include_directories: &[PathBuf],
+	target_file: &str,
+) -> PathBuf {
+	let relative_to_source = source_code
 		.name
 		.clone()
 		.parent()
 		.map(|directory| directory.to_owned().join(target_file))
-		.expect("file path was root, this makes no sense")
+		.expect("file path was root, this makes no sense");
+	if relative_to_source.exists() {
+		return relative_to_source;
+	}
+	include_directories
+		.iter()
+		.map(|directory| directory.join(target_file))
+		.find(|candidate| candidate.exists())
+		.unwrap_or(relative_to_source)
 }
 
 /// Entry point specification.
@@ -139,15 +205,24 @@ pub type EntryPoint = Option<MemoryAddress>;
 #[derive(Debug)]
 pub struct AssembledData {
 	/// The segment data.
-	pub segments:    Segments<LabeledMemoryValue>,
+	pub segments:          Segments<LabeledMemoryValue>,
 	/// The source code behind this assembled data
-	pub source_code: Arc<AssemblyCode>,
+	pub source_code:       Arc<AssemblyCode>,
 	/// Assembler subroutines use this as a flag to signal an end of assembly as soon as possible.
-	should_stop:     bool,
+	should_stop:           bool,
 	/// Execution entry point of the code after being loaded.
-	pub entry_point: EntryPoint,
+	pub entry_point:       EntryPoint,
 	/// Options that command line received; used for determining what to do with warnings.
-	options:         Arc<dyn Frontend>,
+	options:               Arc<dyn Frontend>,
+	/// If branch relaxation is enabled, a copy of the segments before any reference resolution took place. Relaxation
+	/// modifies this copy, since resolution has to start over with the new memory layout.
+	unresolved_segments:   Option<Segments<LabeledMemoryValue>>,
+	/// Branches that were found to be out of range in the last reference resolution pass, as the start of their
+	/// segment, the offset of their relative target within the segment, and their instruction length.
+	out_of_range_branches: Vec<(MemoryAddress, usize, u8)>,
+	/// Directives that are performed after all references were resolved, together with the memory address where they
+	/// are located.
+	deferred_directives:   Vec<(Directive, MemoryAddress)>,
 }
 
 impl AssembledData {
@@ -157,32 +232,8 @@ impl AssembledData {
 	/// memory address 0 etc.
 	/// # Errors
 	/// If the segments contain overlapping data, errors are returned.
-	#[allow(clippy::cast_possible_wrap)]
 	pub fn combine_segments(&self) -> Result<Vec<u8>, Box<AssemblyError>> {
-		let mut all_data = Vec::new();
-		let segments = self.resolve_segments()?;
-
-		// The iteration is sorted
-		for (starting_address, segment_data) in segments.segments {
-			if starting_address < all_data.len() as MemoryAddress {
-				return Err(AssemblyError::SegmentMismatch {
-					src:           Arc::new(AssemblyCode {
-						text:         pretty_hex(&all_data, Some(starting_address as usize)),
-						name:         self.source_code.name.clone(),
-						include_path: Vec::new(),
-					}),
-					// TODO: This location is wrong, it ignores newlines.
-					location:      (starting_address as usize * 3 + 1, 2).into(),
-					segment_start: starting_address,
-					segment_end:   all_data.len() as MemoryAddress,
-				}
-				.into());
-			}
-			all_data.resize(starting_address as usize, 0);
-			all_data.extend_from_slice(&segment_data);
-		}
-
-		Ok(all_data)
+		combine_resolved_segments(&self.resolve_segments()?, &self.source_code)
 	}
 
 	/// Resolve the assembled data's segments by resolving individual memory values. This yields the final data segments
@@ -193,12 +244,13 @@ impl AssembledData {
 	#[allow(clippy::missing_panics_doc)]
 	pub fn resolve_segments(&self) -> Result<Segments<u8>, Box<AssemblyError>> {
 		self.segments.clone().try_map_segments(|start_address, elements| {
+			let logical_start = self.segments.logical_start(start_address);
 			elements
 				.iter()
 				.enumerate()
 				.map(|(address, lmv)| {
 					lmv.try_as_resolved(
-						MemoryAddress::try_from(address).unwrap() + start_address,
+						MemoryAddress::try_from(address).unwrap() + logical_start,
 						&self.source_code,
 						&*self.options,
 					)
@@ -217,6 +269,9 @@ impl AssembledData {
 			should_stop: false,
 			entry_point: None,
 			options: default_backend_options(),
+			unresolved_segments: None,
+			out_of_range_branches: Vec::new(),
+			deferred_directives: Vec::new(),
 		}
 	}
 
@@ -228,7 +283,31 @@ impl AssembledData {
 
 	pub(super) fn assemble_all_from_list(&mut self, list: &mut Vec<ProgramElement>) -> Result<(), Box<AssemblyError>> {
 		let mut current_labels = Vec::default();
+		// The last unconditional control flow instruction, and whether the code after it was already reported as
+		// unreachable. Labels and directives may make the following code reachable or turn it into data.
+		let mut last_jump = None;
+		let mut in_unreachable_code = false;
 		for program_element in list {
+			if let ProgramElement::Instruction(instruction) = program_element {
+				if let Some(mnemonic) = last_jump
+					&& !in_unreachable_code
+					&& self.options.warn_unused()
+				{
+					self.options.report_diagnostic(AssemblyError::UnreachableCode {
+						mnemonic,
+						location: instruction.span,
+						src:      self.source_code.clone(),
+					});
+					in_unreachable_code = true;
+				}
+				if instruction.opcode.mnemonic.ends_control_flow() {
+					last_jump = Some(instruction.opcode.mnemonic);
+				}
+			} else {
+				last_jump = None;
+				in_unreachable_code = false;
+			}
+
 			assemble_element!(self, program_element, current_labels)?;
 			if self.should_stop {
 				break;
@@ -238,13 +317,86 @@ impl AssembledData {
 		Ok(())
 	}
 
-	/// Assemble a single instruction. This function uses the codegen table
-	/// [`table::ASSEMBLY_TABLE`].
-	#[allow(clippy::unnecessary_wraps, clippy::too_many_lines)]
+	/// Assemble a single instruction. Pseudo-instructions are expanded, and relative branches are marked for relaxation
+	/// if that is enabled.
 	fn assemble_instruction(
 		&mut self,
 		instruction: &mut Instruction,
 		current_labels: &[Reference],
+	) -> Result<(), Box<AssemblyError>> {
+		self.check_forced_width(instruction)?;
+		if let Some(pseudo_instruction) = instruction.opcode.pseudo_instruction() {
+			return self.assemble_pseudo_instruction(
+				pseudo_instruction,
+				&instruction.opcode,
+				instruction.span,
+				current_labels,
+			);
+		}
+
+		let start_length = self.segments.current_segment_mut().map_or(0, |segment| segment.len());
+		self.assemble_table_instruction(instruction, current_labels)?;
+
+		if self.options.relax_branches()
+			&& let Ok(segment) = self.segments.current_segment_mut()
+		{
+			let instruction_length = u8::try_from(segment.len() - start_length).unwrap();
+			// Relative branch targets are always the last memory value of the instruction.
+			if let Some(last) = segment.last_mut()
+				&& let MemoryValue::NumberRelative(value) = &last.value
+			{
+				last.value = MemoryValue::RelaxableNumberRelative { value: value.clone(), instruction_length };
+			}
+		}
+		Ok(())
+	}
+
+	/// Checks that an instruction with an explicitly forced addressing mode width is actually encoded differently than
+	/// with the other width. Otherwise, the forced width is either impossible for the instruction, or it has no effect
+	/// because the instruction only has one encoding for both widths, like `jmp` or the branches.
+	fn check_forced_width(&self, instruction: &Instruction) -> Result<(), Box<AssemblyError>> {
+		let opcode = &instruction.opcode;
+		let (width, other_width): (_, fn(AddressingMode) -> AddressingMode) =
+			match opcode.addressing_mode_optimization {
+				AddressingModeOptimization::Automatic => return Ok(()),
+				AddressingModeOptimization::ForceDirectPage => ("direct page", AddressingMode::force_to_wide_addressing),
+				AddressingModeOptimization::PreventDirectPage =>
+					("absolute", AddressingMode::force_to_direct_page_addressing),
+			};
+		let other_opcode = Opcode {
+			first_operand:  opcode.first_operand.clone().map(other_width),
+			second_operand: opcode.second_operand.clone().map(other_width),
+			..opcode.clone()
+		};
+		// Pseudo-instructions don't have an opcode of their own, so they are distinguished by their kind.
+		let encoding = |opcode: &Opcode| {
+			(
+				opcode.pseudo_instruction(),
+				table_opcode(opcode.mnemonic, opcode.first_operand.as_ref(), opcode.second_operand.as_ref()),
+			)
+		};
+		let (forced_encoding, other_encoding) = (encoding(opcode), encoding(&other_opcode));
+		let is_encodable = |encoding: &(Option<_>, Option<_>)| encoding.0.is_some() || encoding.1.is_some();
+		// If neither width is valid, the operands are wrong regardless of the width, which is reported later.
+		if is_encodable(&other_encoding) && (!is_encodable(&forced_encoding) || forced_encoding == other_encoding) {
+			Err(AssemblyError::ImpossibleAddressingWidth {
+				mnemonic: opcode.mnemonic,
+				width:    width.into(),
+				location: instruction.span,
+				src:      self.source_code.clone(),
+			}
+			.into())
+		} else {
+			Ok(())
+		}
+	}
+
+	/// Assemble a single real instruction. This function uses the codegen table [`table::ASSEMBLY_TABLE`].
+	#[allow(clippy::unnecessary_wraps, clippy::too_many_lines)]
+	fn assemble_table_instruction(
+		&mut self,
+		instruction: &mut Instruction,
+		current_labels: &[Reference],
 	) -> Result<(), Box<AssemblyError>> {
 		// Because the actions always expect to get a value, we need a fallback dummy value if there is none in the
 		// addressing mode. This is fine, since we control the codegen table and we can make sure that we never use a
@@ -655,10 +807,13 @@ impl AssembledData {
 	#[allow(clippy::missing_panics_doc, clippy::cast_possible_wrap)]
 	fn execute_reference_resolution_pass(&mut self) -> Change {
 		let mut had_modifications = Change::Unmodified;
+		self.out_of_range_branches.clear();
 		for (segment_start, segment_data) in &mut self.segments.segments {
+			// Labels and relative references use the logical address, which differs from the physical one after `base`.
+			let logical_start = self.segments.logical_starts.get(segment_start).copied().unwrap_or(*segment_start);
 			let mut current_global_label = None;
 			for (offset, datum) in segment_data.iter_mut().enumerate() {
-				let memory_address = segment_start + offset as MemoryAddress;
+				let memory_address = logical_start + offset as MemoryAddress;
 				current_global_label = datum
 					.labels
 					.last()
@@ -699,8 +854,52 @@ impl AssembledData {
 					.for_each(|err| self.options.report_diagnostic(*err));
 				// Resolve a reference used as a memory address, e.g. in an instruction operand like a jump target.
 				had_modifications |= datum.try_resolve(memory_address, &self.source_code, &*self.options);
+				// A relaxable branch whose target is known, but which stays unresolved, is out of range.
+				if let MemoryValue::RelaxableNumberRelative {
+					value: AssemblyTimeValue::Literal(..),
+					instruction_length,
+				} = datum.value
+				{
+					self.out_of_range_branches.push((*segment_start, offset, instruction_length));
+				}
 			}
 		}
 		had_modifications
 	}
+
+	/// Relaxes all branches that were found to be out of range in the last reference resolution pass. The relaxed
+	/// instructions are spliced into the unresolved segments, which then replace the current segments, and all labels
+	/// lose their memory location, since it may have changed.
+	///
+	/// # Returns
+	/// Whether any branches were relaxed.
+	#[allow(clippy::missing_panics_doc)]
+	fn relax_out_of_range_branches(&mut self) -> Change {
+		let Some(unresolved_segments) = &mut self.unresolved_segments else {
+			return Change::Unmodified;
+		};
+		if self.out_of_range_branches.is_empty() {
+			return Change::Unmodified;
+		}
+
+		// Relax from back to front, so that the offsets of the remaining branches are not affected.
+		self.out_of_range_branches.sort_unstable();
+		for (segment_start, offset, instruction_length) in self.out_of_range_branches.drain(..).rev() {
+			let segment = unresolved_segments.segments.get_mut(&segment_start).unwrap();
+			let instruction_range = offset + 1 - usize::from(instruction_length) ..= offset;
+			let relaxed = relaxation::relax_branch(&segment[instruction_range.clone()]);
+			segment.splice(instruction_range, relaxed);
+		}
+
+		for reference in unresolved_segments.segments.values_mut().flatten().flat_map(|datum| &mut datum.labels) {
+			match reference {
+				Reference::Label(label) => label.write().location = None,
+				Reference::Relative { value, .. } | Reference::UnresolvedLabel { value, .. } => *value = None,
+				// Macro arguments are not memory locations, so their value doesn't depend on the memory layout.
+				Reference::MacroArgument { .. } | Reference::MacroGlobal { .. } => {},
+			}
+		}
+		self.segments = unresolved_segments.clone();
+		Change::Modified
+	}
 }
@@ -0,0 +1,102 @@
+//! Pseudo-instruction assembly functions.
+
+#[allow(unused)]
+use flexstr::{shared_str, IntoSharedStr, SharedStr, ToSharedStr};
+use miette::SourceSpan;
+
+use super::AssembledData;
+use crate::sema::instruction::{Opcode, PseudoInstruction};
+use crate::sema::reference::Reference;
+use crate::sema::value::BinaryOperator;
+use crate::sema::{AddressingMode, AssemblyTimeValue};
+use crate::AssemblyError;
+
+/// `mov dp, #imm`
+const MOV_DIRECT_PAGE_IMMEDIATE: u8 = 0x8F;
+/// `mov a, #imm`
+const MOV_A_IMMEDIATE: u8 = 0xE8;
+/// `mov y, #imm`
+const MOV_Y_IMMEDIATE: u8 = 0x8D;
+/// `inc addr`
+const INC_ADDRESS: u8 = 0xAC;
+/// `dec addr`
+const DEC_ADDRESS: u8 = 0x8C;
+/// `bne rel`
+const BNE: u8 = 0xD0;
+/// Size of an instruction with an absolute address operand, used for skipping over it with a branch.
+const ADDRESS_INSTRUCTION_SIZE: u8 = 3;
+
+impl AssembledData {
+	/// Assemble a pseudo-instruction into the sequence of real instructions that it stands for.
+	///
+	/// # Errors
+	/// If there is no segment currently.
+	///
+	/// # Panics
+	/// All panics are programming bugs.
+	pub(super) fn assemble_pseudo_instruction(
+		&mut self,
+		pseudo_instruction: PseudoInstruction,
+		opcode: &Opcode,
+		span: SourceSpan,
+		current_labels: &[Reference],
+	) -> Result<(), Box<AssemblyError>> {
+		let first_operand = opcode.first_operand.as_ref().and_then(AddressingMode::number);
+		let second_operand = opcode.second_operand.as_ref().and_then(AddressingMode::number);
+
+		match pseudo_instruction {
+			PseudoInstruction::MovwDirectPageImmediate => {
+				let address = first_operand.unwrap();
+				let value = second_operand.unwrap();
+				self.append(MOV_DIRECT_PAGE_IMMEDIATE, current_labels, span)?;
+				self.append_8_bits_unresolved(value.clone(), 0, false, Self::DEFAULT_VEC, span)?;
+				self.append_8_bits_unresolved(address.clone(), 0, true, Self::DEFAULT_VEC, span)?;
+				self.append(MOV_DIRECT_PAGE_IMMEDIATE, Self::DEFAULT_VEC, span)?;
+				self.append_8_bits_unresolved(value, 1, true, Self::DEFAULT_VEC, span)?;
+				self.append_8_bits_unresolved(Self::next_address(address, span), 0, true, Self::DEFAULT_VEC, span)
+			},
+			PseudoInstruction::MovwYaImmediate => {
+				let value = second_operand.unwrap();
+				self.append(MOV_A_IMMEDIATE, current_labels, span)?;
+				self.append_8_bits_unresolved(value.clone(), 0, false, Self::DEFAULT_VEC, span)?;
+				self.append(MOV_Y_IMMEDIATE, Self::DEFAULT_VEC, span)?;
+				self.append_8_bits_unresolved(value, 1, true, Self::DEFAULT_VEC, span)
+			},
+			PseudoInstruction::IncwAddress => {
+				let address = first_operand.unwrap();
+				self.append(INC_ADDRESS, current_labels, span)?;
+				self.append_16_bits_unresolved(address.clone(), Self::DEFAULT_VEC, span)?;
+				// Only increment the high byte if the low byte overflowed to zero.
+				self.append(BNE, Self::DEFAULT_VEC, span)?;
+				self.append(ADDRESS_INSTRUCTION_SIZE, Self::DEFAULT_VEC, span)?;
+				self.append(INC_ADDRESS, Self::DEFAULT_VEC, span)?;
+				self.append_16_bits_unresolved(Self::next_address(address, span), Self::DEFAULT_VEC, span)
+			},
+			PseudoInstruction::DecwAddress => {
+				let address = first_operand.unwrap();
+				// Incrementing and decrementing the low byte leaves it unchanged, but sets the zero flag if it is about
+				// to underflow.
+				self.append(INC_ADDRESS, current_labels, span)?;
+				self.append_16_bits_unresolved(address.clone(), Self::DEFAULT_VEC, span)?;
+				self.append(DEC_ADDRESS, Self::DEFAULT_VEC, span)?;
+				self.append_16_bits_unresolved(address.clone(), Self::DEFAULT_VEC, span)?;
+				self.append(BNE, Self::DEFAULT_VEC, span)?;
+				self.append(ADDRESS_INSTRUCTION_SIZE, Self::DEFAULT_VEC, span)?;
+				self.append(DEC_ADDRESS, Self::DEFAULT_VEC, span)?;
+				self.append_16_bits_unresolved(Self::next_address(address.clone(), span), Self::DEFAULT_VEC, span)?;
+				self.append(DEC_ADDRESS, Self::DEFAULT_VEC, span)?;
+				self.append_16_bits_unresolved(address, Self::DEFAULT_VEC, span)
+			},
+		}
+	}
+
+	/// Synthesizes the address after the given address, which holds the high byte of a word.
+	fn next_address(address: AssemblyTimeValue, span: SourceSpan) -> AssemblyTimeValue {
+		AssemblyTimeValue::BinaryOperation {
+			lhs: address.into(),
+			rhs: AssemblyTimeValue::Literal(1, span).into(),
+			operator: BinaryOperator::Add,
+			span,
+		}
+	}
+}
@@ -0,0 +1,57 @@
+//! Branch relaxation, which replaces relative branches with out of range targets by longer instruction sequences.
+
+#[allow(unused)]
+use flexstr::{shared_str, IntoSharedStr, SharedStr, ToSharedStr};
+
+use super::memory::{LabeledMemoryValue, MemoryValue};
+
+/// `bra rel`
+const BRA: u8 = 0x2F;
+/// `jmp addr`
+const JMP_ADDRESS: u8 = 0x5F;
+/// Size of the `jmp addr` instruction that jumps to the actual branch target.
+const JMP_SIZE: u8 = 3;
+/// Size of the `bra` instruction that skips over the `jmp` in relaxed instructions with operands.
+const BRA_SIZE: u8 = 2;
+
+/// Relaxes a single branch instruction, whose relative target is always its last memory value. The labels of the
+/// instruction stay with the first memory value of the relaxed instruction sequence.
+///
+/// - `bra target` becomes `jmp target`.
+/// - Conditional branches like `beq target` are inverted to skip over a jump: `bne +3 ; jmp target`.
+/// - All other branches (`cbne`, `dbnz`, `bbs`, `bbc`) keep their condition, but branch to a jump instead, which is
+///   skipped by a `bra` otherwise: `cbne dp, +2 ; bra +3 ; jmp target`.
+///
+/// # Panics
+/// If the given instruction isn't a relaxable branch instruction; this is a programming bug.
+pub(super) fn relax_branch(instruction: &[LabeledMemoryValue]) -> Vec<LabeledMemoryValue> {
+	let (last, instruction_bytes) = instruction.split_last().expect("empty branch instruction");
+	let MemoryValue::RelaxableNumberRelative { ref value, .. } = last.value else {
+		panic!("instruction {instruction:?} is not a relaxable branch, this is a bug");
+	};
+	let span = last.instruction_location;
+	let labels = instruction.first().map(|first| first.labels.clone()).unwrap_or_default();
+	let new_value = |value| LabeledMemoryValue { labels: Vec::new(), value, instruction_location: span };
+
+	let mut relaxed = match instruction_bytes.first().map(|first| &first.value) {
+		Some(MemoryValue::Resolved(BRA)) => Vec::new(),
+		// All 8 conditional branches have the opcode x0 with an odd x. Inverting the condition flips bit 5.
+		Some(MemoryValue::Resolved(opcode)) if opcode & 0x1F == 0x10 =>
+			vec![new_value(MemoryValue::Resolved(opcode ^ 0x20)), new_value(MemoryValue::Resolved(JMP_SIZE))],
+		_ => {
+			let mut relaxed = instruction_bytes.to_vec();
+			relaxed.push(new_value(MemoryValue::Resolved(BRA_SIZE)));
+			relaxed.push(new_value(MemoryValue::Resolved(BRA)));
+			relaxed.push(new_value(MemoryValue::Resolved(JMP_SIZE)));
+			relaxed
+		},
+	};
+	relaxed.push(new_value(MemoryValue::Resolved(JMP_ADDRESS)));
+	relaxed.push(new_value(MemoryValue::Number { value: value.clone(), byte_index: 0, is_highest_byte: false }));
+	relaxed.push(new_value(MemoryValue::Number { value: value.clone(), byte_index: 1, is_highest_byte: true }));
+
+	if let Some(first) = relaxed.first_mut() {
+		first.labels = labels;
+	}
+	relaxed
+}
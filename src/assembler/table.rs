@@ -12,7 +12,7 @@ use miette::SourceSpan;
 
 use super::AssembledData;
 use crate::sema::instruction::Mnemonic;
-use crate::sema::{AddressingModeCategory, AssemblyTimeValue};
+use crate::sema::{AddressingMode, AddressingModeCategory, AssemblyTimeValue, Register};
 use crate::AssemblyError;
 
 /// An action of what to do additionally with the segment. This function handles two operands, such as addresses.
@@ -1138,6 +1138,37 @@ pub static ASSEMBLY_TABLE: LazyLock<Table> = LazyLock::new(|| {
 	table
 });
 
+/// Looks up the opcode for the mnemonic with the given operands, if the table contains that combination.
+pub fn table_opcode(
+	mnemonic: Mnemonic,
+	first_operand: Option<&AddressingMode>,
+	second_operand: Option<&AddressingMode>,
+) -> Option<u8> {
+	match (ASSEMBLY_TABLE.get(&mnemonic)?, first_operand) {
+		(EntryOrFirstOperandTable::Entry(opcode), None) => second_operand.is_none().then_some(*opcode),
+		(EntryOrFirstOperandTable::Table(first_operand_table), Some(first_operand)) =>
+			match (first_operand_table.get(&first_operand.into())?, second_operand) {
+				(
+					EntryOrSecondOperandTable::Entry(opcode, _)
+					| EntryOrSecondOperandTable::ImplicitAEntry(opcode, _)
+					| EntryOrSecondOperandTable::BitEntry(opcode, _)
+					| EntryOrSecondOperandTable::TcallEntry(opcode),
+					None,
+				)
+				| (
+					EntryOrSecondOperandTable::ImplicitAEntry(opcode, _),
+					Some(AddressingMode::Register(Register::A)),
+				) => Some(*opcode),
+				(EntryOrSecondOperandTable::Table(second_operand_table), Some(second_operand)) =>
+					match second_operand_table.get(&second_operand.into())? {
+						TwoOperandEntry::Entry(opcode, _) | TwoOperandEntry::BitEntry(opcode, _) => Some(*opcode),
+					},
+				_ => None,
+			},
+		_ => None,
+	}
+}
+
 fn one_operand_nop(
 	_data: &mut AssembledData,
 	_span: SourceSpan,
@@ -3,13 +3,16 @@
 
 use std::fs::File;
 use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
 use clap::{Parser, Subcommand, ValueEnum};
 #[allow(unused)]
 use flexstr::{shared_str, IntoSharedStr, SharedStr, ToSharedStr};
-use hound::WavWriter;
 use num_traits::cast::FromPrimitive;
+use serde_json::json;
+use spcasm::brr::directory::{extract_samples, DirectorySample, SampleDirectory};
 #[allow(clippy::wildcard_imports)]
 use spcasm::brr::*;
 
@@ -77,30 +80,30 @@ enum Command {
 		warm_up: Option<Vec<DecodedSample>>,
 	},
 
-	#[command(about = "Encode a WAV file into a BRR file")]
+	#[command(about = "Encode an audio file into a BRR file")]
 	Encode {
 		#[arg(
-			help = "The WAV file to encode",
-			long_help = "The WAV file to encode. Only uncompressed WAV (integer or float) is supported. Sample rate \
-			             is not converted, so in order for audio to not be pitch-shifted, the input has to be at \
-			             32kHz, matching the SNES DSP sample rate."
+			help = "The audio file to encode",
+			long_help = "The audio file to encode. Uncompressed WAV (integer or float), AIFF, FLAC and raw PCM are \
+			             supported. Sample rate is not converted, so in order for audio to not be pitch-shifted, the \
+			             input has to be at 32kHz, matching the SNES DSP sample rate."
 		)]
-		input:       PathBuf,
+		input:        PathBuf,
 		#[arg(
 			help = "Output BRR file to write",
 			long_help = "Output BRR file to write. By default, a file with the same name but a `.brr` extension is \
 			             used as output."
 		)]
-		output:      Option<PathBuf>,
+		output:       Option<PathBuf>,
 		#[arg(
 			value_parser = |string: &str| string.parse().map_err(|err: std::num::ParseIntError| err.to_string().into()).and_then(|int| CompressionLevel::from_u8(int).ok_or("compression level out of range".to_string())),
 			default_value = "2",
 			long,
 			short,
 			help = "Compression level to use",
-			long_help = "Compression level to use; higher levels mean better audio fidelity. 0: Only use filter 0, 1: Use all filters with non-wrapping optimal shift, 2: Use all filters with optimal shift."
+			long_help = "Compression level to use; higher levels mean better audio fidelity. 0: Only use filter 0, 1: Use all filters with non-wrapping optimal shift, 2: Use all filters with optimal shift, 3: Search for the best combination of filters and shifts across blocks, 4: Like 3, but with perceptual error weighting that allows more noise in loud passages."
 		)]
-		compression: CompressionLevel,
+		compression:  CompressionLevel,
 		#[arg(
 			long,
 			short,
@@ -119,9 +122,76 @@ enum Command {
 			required = false,
 			help = "Loop point",
 			long_help = "Set the sample's loop point. The ending block has its flags set to signal a looping sample, \
-			             and the loop start block uses filter 0 to prevent glitches."
+			             and the loop start block is re-encoded so that it decodes accurately both in linear playback \
+			             and after looping back."
 		)]
-		loop_point:  Option<usize>,
+		loop_point:   Option<usize>,
+		#[arg(
+			long,
+			short,
+			help = "Number of threads to encode with",
+			long_help = "Number of threads to encode with. By default, all available processor cores are used. Long \
+			             samples are split into regions which are encoded in parallel. The output is the same for any \
+			             number of threads."
+		)]
+		jobs:         Option<NonZeroUsize>,
+		#[arg(
+			long,
+			short,
+			value_enum,
+			help = "Format of the input file",
+			long_help = "Format of the input file. By default, the format is detected from the file extension: .aif, \
+			             .aiff and .aifc files are AIFF, .flac files are FLAC, .raw and .pcm files are raw PCM, and \
+			             all other files are WAV."
+		)]
+		input_format: Option<InputFormat>,
+		#[arg(
+			long,
+			conflicts_with = "mix",
+			help = "Only encode one channel of the input",
+			long_help = "Only encode the channel with this index, starting at 0, instead of mixing all channels down \
+			             to mono. This avoids cancellation in stereo audio whose channels are out of phase."
+		)]
+		channel:      Option<u16>,
+		#[arg(
+			long,
+			value_delimiter = ',',
+			allow_negative_numbers = true,
+			value_name = "WEIGHTS",
+			help = "Weights for mixing the channels down to mono",
+			long_help = "Comma-separated weights for mixing the channels down to mono, one for each channel. A weight \
+			             of 1 keeps the channel's level, and a negative weight inverts the channel's phase. By \
+			             default, all channels are averaged."
+		)]
+		mix:          Option<Vec<f64>>,
+		#[arg(
+			long,
+			conflicts_with = "rms",
+			allow_negative_numbers = true,
+			value_name = "DBFS",
+			help = "Normalize the peak level",
+			long_help = "Normalize the audio so that its highest peak is at this level in dBFS, for example -1. The \
+			             level is measured over the entire file after mixing down to mono."
+		)]
+		peak:         Option<f64>,
+		#[arg(
+			long,
+			allow_negative_numbers = true,
+			value_name = "DBFS",
+			help = "Normalize the RMS level",
+			long_help = "Normalize the audio so that its RMS level is at this level in dBFS, for example -16. The \
+			             level is measured over the entire file after mixing down to mono. Peaks that exceed full \
+			             scale after normalizing are clipped."
+		)]
+		rms:          Option<f64>,
+		#[arg(
+			long,
+			help = "Dither when converting to 16 bits",
+			long_help = "Add triangular dither noise when converting the audio to 16 bits. This avoids quantization \
+			             distortion when reducing 24-bit or 32-bit audio or after normalizing. The noise is the same \
+			             every time, so encoding is still reproducible."
+		)]
+		dither:       bool,
 	},
 
 	#[command(about = "Decode a BRR file into a WAV file")]
@@ -130,13 +200,13 @@ enum Command {
 			help = "The BRR file to decode",
 			long_help = "The BRR file to decode. Only raw BRR files are supported right now."
 		)]
-		input:  PathBuf,
+		input:      PathBuf,
 		#[arg(
 			help = "Output WAV file to write",
 			long_help = "Output WAV file to write. The format is always mono 16-bit signed integer with a sample rate \
 			             of 32kHz, matching the SNES DSP."
 		)]
-		output: Option<PathBuf>,
+		output:     Option<PathBuf>,
 		#[arg(
 			long,
 			short,
@@ -147,10 +217,186 @@ enum Command {
 			             not shifted. The emulation helps recover audio data the way it would have been heard on \
 			             original hardware."
 		)]
-		filter: bool,
+		filter:     bool,
+		#[arg(
+			long,
+			short,
+			help = "Decode the sample like the DSP plays it back",
+			long_help = "Decode the sample like the DSP plays it back. Playback stops after the block with the end \
+			             flag, or continues at the loop point if that block also has the loop flag. The loop region \
+			             is stored in the WAV file's `smpl` chunk, so that samplers and audio editors can play the \
+			             loop exactly like the DSP does."
+		)]
+		playback:   bool,
+		#[arg(
+			long,
+			requires = "playback",
+			help = "Loop point for playback",
+			long_help = "Set the sample's loop point for playback, in samples. Like the loop address of the DSP, the \
+			             loop point is always at the start of a block, so it is rounded down to a multiple of 16. By \
+			             default, the loop point is at the start of the sample."
+		)]
+		loop_point: Option<usize>,
+		#[arg(
+			long,
+			requires = "playback",
+			conflicts_with = "duration",
+			help = "Number of loop repetitions to play back",
+			long_help = "Number of times that playback repeats the loop after playing the sample once. By default, \
+			             the loop is repeated once."
+		)]
+		loops:      Option<usize>,
+		#[arg(
+			long,
+			requires = "playback",
+			help = "Duration of the playback in seconds",
+			long_help = "Duration of the playback in seconds. The loop is repeated as often as necessary, but \
+			             playback may end earlier if the sample doesn't loop."
+		)]
+		duration:   Option<f64>,
+	},
+
+	#[command(
+		about = "Encode many audio files listed in a manifest into a sample directory",
+		long_about = "Encode many audio files listed in a manifest into a sample directory. The manifest is a TOML \
+		              file which lists the samples together with their encoding options. All samples are encoded and \
+		              placed after a sample directory in one binary file, and an spcasm include file with labels for \
+		              the directory and the samples is written as well."
+	)]
+	Batch {
+		#[arg(help = "The manifest file", long_help = "The TOML manifest file that lists the samples to encode.")]
+		manifest: PathBuf,
+	},
+
+	#[command(
+		about = "Inspect all blocks of a BRR file and check it for problems",
+		long_about = "Inspect all blocks of a BRR file and check it for problems. For every block, the filter, shift \
+		              (as stored in the header), flags and the number of samples affected by hardware glitches are \
+		              shown. Problems with the file, like a missing end flag, data after the end block, loop flags on \
+		              blocks without the end flag, or invalid shifts, are reported afterwards, and the exit code is 1 \
+		              if any problems were found."
+	)]
+	Inspect {
+		#[arg(
+			help = "The BRR file to inspect",
+			long_help = "The BRR file to inspect. Only raw BRR files are supported."
+		)]
+		input:  PathBuf,
+		#[arg(long, value_enum, default_value_t = InspectFormat::Table, help = "Output format")]
+		format: InspectFormat,
+	},
+
+	#[command(
+		about = "Extract all BRR samples from an .spc file",
+		long_about = "Extract all BRR samples from an .spc file. The sample directory is found via the DSP's DIR \
+		              register, and every sample that a directory entry points to is written as a raw BRR file and as \
+		              a decoded WAV file with the sample's loop region. The files are named after the .spc file and \
+		              the (hexadecimal) index of the directory entry. Since the number of directory entries is not \
+		              stored anywhere, entries that don't point to a valid BRR sample are skipped; use --verbose to \
+		              see why."
+	)]
+	Extract {
+		#[arg(help = "The .spc file to extract samples from")]
+		input:            PathBuf,
+		#[arg(
+			help = "Directory to write the samples to",
+			long_help = "Directory to write the samples to. By default, the samples are written to the directory of \
+			             the .spc file."
+		)]
+		output_directory: Option<PathBuf>,
+		#[arg(
+			long,
+			short,
+			default_value_t = 256,
+			value_parser = clap::value_parser!(u16).range(1 ..= 256),
+			help = "Number of directory entries to read",
+			long_help = "Number of directory entries to read. If you know how many samples the sound driver uses, \
+			             this avoids extracting garbage from unused entries that happen to look like valid samples."
+		)]
+		entries:          u16,
+	},
+
+	#[command(
+		about = "Render a BRR file the way the DSP plays it back at a given pitch",
+		long_about = "Render a BRR file the way the DSP plays it back at a given pitch. Like on hardware, the sample \
+		              is resampled with Gaussian interpolation, looped, and shaped by an ADSR or GAIN envelope, and \
+		              the voice output at 32kHz is written to a WAV file. This lets you hear the pitched result \
+		              without writing a sound driver."
+	)]
+	Preview {
+		#[arg(
+			help = "The BRR file to render",
+			long_help = "The BRR file to render. Only raw BRR files are supported."
+		)]
+		input:      PathBuf,
+		#[arg(
+			help = "Output WAV file to write",
+			long_help = "Output WAV file to write. The format is always mono 16-bit signed integer with a sample rate \
+			             of 32kHz, matching the SNES DSP."
+		)]
+		output:     Option<PathBuf>,
+		#[arg(
+			long,
+			short,
+			default_value = "0x1000",
+			value_parser = parse_pitch,
+			help = "Pitch (VxPITCH) to play the sample at",
+			long_help = "Pitch to play the sample at, as in the VxPITCH register. $1000 (0x1000) plays the sample at \
+			             32kHz, $2000 one octave higher and $0800 one octave lower. The maximum is $3FFF. Hexadecimal \
+			             values need a `$` or `0x` prefix."
+		)]
+		pitch:      u16,
+		#[arg(
+			long,
+			short,
+			default_value_t = 0,
+			help = "Loop point of the sample",
+			long_help = "Loop point of the sample, in samples. Like the loop address of the DSP, the loop point is \
+			             always at the start of a block, so it is rounded down to a multiple of 16. The sample only \
+			             loops if its end block has the loop flag."
+		)]
+		loop_point: usize,
+		#[arg(
+			long,
+			value_parser = parse_adsr,
+			conflicts_with = "gain",
+			help = "Use an ADSR envelope",
+			long_help = "Use an ADSR envelope, given as the attack rate (0-15), decay rate (0-7), sustain level (0-7) \
+			             and sustain rate (0-31), separated by commas. These are the values of the ADSR1 and ADSR2 \
+			             registers' fields, not times."
+		)]
+		adsr:       Option<voice::Envelope>,
+		#[arg(
+			long,
+			value_parser = parse_gain,
+			help = "Use a GAIN envelope",
+			long_help = "Use a GAIN envelope, given as the value of the GAIN register. Values below $80 set a fixed \
+			             envelope level, the others select a linear or exponential increase or decrease mode. By \
+			             default, the envelope is fixed at full volume ($7F)."
+		)]
+		gain:       Option<u8>,
+		#[arg(
+			long,
+			help = "Key the voice off after this many seconds",
+			long_help = "Key the voice off after this many seconds, which starts the envelope's release. By default, \
+			             the voice is never keyed off, so a looping sample plays until the end of the preview."
+		)]
+		key_off:    Option<f64>,
+		#[arg(
+			long,
+			short,
+			default_value_t = 5.0,
+			help = "Maximum duration of the preview in seconds",
+			long_help = "Maximum duration of the preview in seconds. The preview ends earlier once the voice is \
+			             silent."
+		)]
+		duration:   f64,
 	},
 }
 
+/// Index of the DSP's DIR register, which holds the page of the sample directory.
+const DIR_REGISTER: usize = 0x5D;
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
 enum PreEmphasisFilter {
 	/// A precise treble filter that inverts the hardware Gaussian filter exactly.
@@ -160,6 +406,497 @@ enum PreEmphasisFilter {
 	Brrtools,
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum InputFormat {
+	/// Uncompressed WAV, with integer or float samples.
+	Wav,
+	/// Uncompressed AIFF or AIFF-C, with integer samples.
+	Aiff,
+	/// FLAC.
+	Flac,
+	/// Headerless mono 16-bit signed little-endian PCM at 32kHz.
+	Raw,
+}
+
+impl From<InputFormat> for wav::AudioFormat {
+	fn from(format: InputFormat) -> Self {
+		match format {
+			InputFormat::Wav => Self::Wav,
+			InputFormat::Aiff => Self::Aiff,
+			InputFormat::Flac => Self::Flac,
+			InputFormat::Raw => Self::Raw,
+		}
+	}
+}
+
+/// Returns the format of the given input file, which is detected from the file extension if not given explicitly.
+fn input_format(path: &Path, format: Option<InputFormat>) -> wav::AudioFormat {
+	format.map_or_else(|| wav::AudioFormat::from_extension(path), Into::into)
+}
+
+/// Returns the options for converting the input audio to 16-bit mono. Conflicting options are rejected beforehand.
+fn conversion_options(
+	channel: Option<u16>,
+	mix: Option<Vec<f64>>,
+	peak: Option<f64>,
+	rms: Option<f64>,
+	dither: bool,
+) -> wav::ConversionOptions {
+	wav::ConversionOptions {
+		downmix: channel.map(wav::Downmix::Channel).or_else(|| mix.map(wav::Downmix::Weights)).unwrap_or_default(),
+		normalization: peak.map(wav::Normalization::Peak).or_else(|| rms.map(wav::Normalization::Rms)),
+		dither,
+	}
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+enum InspectFormat {
+	/// A human-readable table.
+	#[default]
+	Table,
+	/// A JSON object with the blocks and problems.
+	Json,
+}
+
+/// Prints the result of inspecting a BRR file in the given format.
+fn print_inspection(blocks: &[BlockInspection], problems: &[StreamProblem], format: InspectFormat) {
+	match format {
+		InspectFormat::Table => {
+			println!("block  offset  filter  shift  flags      glitches");
+			for (index, BlockInspection { block, hardware_glitches }) in blocks.iter().enumerate() {
+				println!(
+					"{index:>5}  {:#06x}  {:>6}  {:>5}  {:<9}  {hardware_glitches:>8}",
+					index * 9,
+					block.header.filter as u8,
+					block.header.shift_nibble(),
+					block.header.flags.to_string(),
+				);
+			}
+			if !problems.is_empty() {
+				println!();
+			}
+			for problem in problems {
+				println!("problem: {problem}");
+			}
+		},
+		InspectFormat::Json => {
+			let blocks = blocks
+				.iter()
+				.enumerate()
+				.map(|(index, BlockInspection { block, hardware_glitches })| {
+					json!({
+						"index": index,
+						"offset": index * 9,
+						"filter": block.header.filter as u8,
+						"shift": block.header.shift_nibble(),
+						"end": block.is_end(),
+						"loop": block.header.flags.is_raw_loop(),
+						"hardware_glitches": hardware_glitches,
+					})
+				})
+				.collect::<Vec<_>>();
+			let problems = problems
+				.iter()
+				.map(|problem| json!({ "block": problem.block(), "message": problem.to_string() }))
+				.collect::<Vec<_>>();
+			println!("{}", json!({ "blocks": blocks, "problems": problems }));
+		},
+	}
+}
+
+/// One sample listed in a batch manifest.
+struct BatchSample {
+	name:        SharedStr,
+	file:        PathBuf,
+	loop_point:  Option<usize>,
+	range:       Option<Range<usize>>,
+	filter:      Option<PreEmphasisFilter>,
+	compression: CompressionLevel,
+	rate:        Option<u32>,
+	format:      Option<InputFormat>,
+	conversion:  wav::ConversionOptions,
+}
+
+/// Checks that the table only contains the given keys.
+fn check_manifest_keys(table: &toml::Table, allowed_keys: &[&str], context: &str) -> Result<(), SharedStr> {
+	table.keys().find(|key| !allowed_keys.contains(&key.as_str())).map_or(Ok(()), |key| {
+		Err(format!("unknown key `{key}` in {context}, expected one of: {}", allowed_keys.join(", ")).into())
+	})
+}
+
+/// Returns the value of an optional integer key in a manifest table, converted to the target type.
+fn manifest_integer<T: TryFrom<i64>>(table: &toml::Table, key: &str, context: &str) -> Result<Option<T>, SharedStr> {
+	table
+		.get(key)
+		.map(|value| {
+			value.as_integer().and_then(|integer| T::try_from(integer).ok()).ok_or_else(|| {
+				format!("`{key}` in {context} must be a non-negative integer in range, not {value}").into()
+			})
+		})
+		.transpose()
+}
+
+/// Returns the value of a number in a manifest table. Integers are accepted as well.
+#[allow(clippy::cast_precision_loss)]
+fn manifest_float(value: &toml::Value, key: &str, context: &str) -> Result<f64, SharedStr> {
+	value
+		.as_float()
+		.or_else(|| value.as_integer().map(|integer| integer as f64))
+		.ok_or_else(|| format!("`{key}` in {context} must be a number, not {value}").into())
+}
+
+/// Returns the value of an optional string key in a manifest table.
+fn manifest_string<'a>(table: &'a toml::Table, key: &str, context: &str) -> Result<Option<&'a str>, SharedStr> {
+	table
+		.get(key)
+		.map(|value| value.as_str().ok_or_else(|| format!("`{key}` in {context} must be a string, not {value}").into()))
+		.transpose()
+}
+
+impl BatchSample {
+	/// Reads a sample entry of a batch manifest.
+	fn from_manifest(table: &toml::Table, index: usize) -> Result<Self, SharedStr> {
+		let context = format!("sample {index}");
+		check_manifest_keys(
+			table,
+			&[
+				"name",
+				"file",
+				"loop-point",
+				"range",
+				"filter",
+				"compression",
+				"rate",
+				"format",
+				"channel",
+				"mix",
+				"peak",
+				"rms",
+				"dither",
+			],
+			&context,
+		)?;
+		let name = manifest_string(table, "name", &context)?.ok_or_else(|| format!("{context} is missing a `name`"))?;
+		if !name.starts_with(|character: char| character.is_ascii_alphabetic() || character == '_')
+			|| !name.chars().all(|character| character.is_ascii_alphanumeric() || character == '_')
+		{
+			return Err(format!("sample name `{name}` is not a valid label name").into());
+		}
+		let context = format!("sample `{name}`");
+		let file = manifest_string(table, "file", &context)?.ok_or_else(|| format!("{context} is missing a `file`"))?;
+		let range = table
+			.get("range")
+			.map(|value| {
+				match value.as_array().map(Vec::as_slice) {
+					Some([start, end]) => start
+						.as_integer()
+						.zip(end.as_integer())
+						.and_then(|(start, end)| Some(usize::try_from(start).ok()? .. usize::try_from(end).ok()?))
+						.filter(|range| !range.is_empty()),
+					_ => None,
+				}
+				.ok_or_else(|| format!("`range` in {context} must be a non-empty range [start, end], not {value}"))
+			})
+			.transpose()?;
+		let filter = manifest_string(table, "filter", &context)?
+			.map(|filter| PreEmphasisFilter::from_str(filter, true).map_err(|_| format!("unknown filter `{filter}`")))
+			.transpose()?;
+		let format = manifest_string(table, "format", &context)?
+			.map(|format| InputFormat::from_str(format, true).map_err(|_| format!("unknown format `{format}`")))
+			.transpose()?;
+		let compression = manifest_integer::<u8>(table, "compression", &context)?
+			.map_or(Some(CompressionLevel::Max), CompressionLevel::from_u8)
+			.ok_or("compression level out of range")?;
+		for (first, second) in [("channel", "mix"), ("peak", "rms")] {
+			if table.contains_key(first) && table.contains_key(second) {
+				return Err(format!("`{first}` and `{second}` of {context} can't be used together").into());
+			}
+		}
+		let mix = table
+			.get("mix")
+			.map(|value| {
+				value
+					.as_array()
+					.ok_or_else(|| format!("`mix` in {context} must be an array of weights, not {value}").into())
+					.and_then(|weights| {
+						weights
+							.iter()
+							.map(|weight| manifest_float(weight, "mix", &context))
+							.collect::<Result<Vec<_>, _>>()
+					})
+			})
+			.transpose()?;
+		let peak = table.get("peak").map(|value| manifest_float(value, "peak", &context)).transpose()?;
+		let rms = table.get("rms").map(|value| manifest_float(value, "rms", &context)).transpose()?;
+		let dither = table
+			.get("dither")
+			.map(|value| value.as_bool().ok_or_else(|| format!("`dither` in {context} must be a boolean, not {value}")))
+			.transpose()?
+			.unwrap_or(false);
+
+		Ok(Self {
+			name: name.into(),
+			file: file.into(),
+			loop_point: manifest_integer(table, "loop-point", &context)?,
+			range,
+			filter,
+			compression,
+			rate: manifest_integer(table, "rate", &context)?,
+			format,
+			conversion: conversion_options(manifest_integer(table, "channel", &context)?, mix, peak, rms, dither),
+		})
+	}
+
+	/// Reads and encodes the sample.
+	#[allow(clippy::cast_possible_truncation)]
+	fn encode(&self, base_directory: &Path, threads: NonZeroUsize) -> Result<(Vec<u8>, EncodeReport), SharedStr> {
+		let path = base_directory.join(&self.file);
+		let (mut samples, source_rate) = File::open(&path)
+			.map_err(|err| err.to_string().into())
+			.and_then(|file| {
+				wav::read_audio_for_brr_with_rate(file, input_format(&path, self.format), &self.conversion)
+			})
+			.map_err(|err| format!("{}: {err}", path.display()))?;
+
+		let mut loop_point = self.loop_point;
+		if let Some(range) = &self.range {
+			if range.end > samples.len() {
+				return Err(format!(
+					"range {}..{} of sample `{}` exceeds its length of {} samples",
+					range.start,
+					range.end,
+					self.name,
+					samples.len()
+				)
+				.into());
+			}
+			samples = samples[range.clone()].to_vec();
+			loop_point = loop_point
+				.map(|loop_point| {
+					loop_point.checked_sub(range.start).filter(|point| *point < range.len()).ok_or_else(|| {
+						format!("loop point {loop_point} of sample `{}` is outside of its range", self.name)
+					})
+				})
+				.transpose()?;
+		}
+		if let Some(rate) = self.rate {
+			samples = dsp::resample(&samples, source_rate, rate);
+			loop_point = loop_point
+				.map(|loop_point| (loop_point as u64 * u64::from(rate) / u64::from(source_rate.max(1))) as usize);
+		}
+		samples = match self.filter {
+			Some(PreEmphasisFilter::Brrtools) => dsp::apply_brrtools_treble_boost_filter(&samples),
+			Some(PreEmphasisFilter::Treble) => dsp::apply_precise_treble_boost_filter(&samples),
+			None => samples,
+		};
+		Ok(encode_to_brr_parallel(&mut samples, loop_point, self.compression, threads))
+	}
+}
+
+/// Encodes all samples of a batch manifest and writes the sample directory binary and include file.
+fn run_batch(manifest_path: &Path, verbose: bool) -> Result<(), SharedStr> {
+	let manifest = std::fs::read_to_string(manifest_path)
+		.map_err(|err| err.to_string())
+		.and_then(|text| text.parse::<toml::Table>().map_err(|err| err.to_string()))
+		.map_err(|err| format!("{}: {err}", manifest_path.display()))?;
+	check_manifest_keys(&manifest, &["address", "output", "include", "sample"], "manifest")?;
+	let base_directory = manifest_path.parent().unwrap_or_else(|| Path::new("."));
+	let address =
+		manifest_integer::<u16>(&manifest, "address", "manifest")?.ok_or("manifest is missing an `address`")?;
+	let output = manifest_string(&manifest, "output", "manifest")?
+		.map_or_else(|| manifest_path.with_extension("bin"), |output| base_directory.join(output));
+	let include = manifest_string(&manifest, "include", "manifest")?
+		.map_or_else(|| manifest_path.with_extension("s"), |include| base_directory.join(include));
+
+	let samples = match manifest.get("sample") {
+		None => Vec::new(),
+		Some(toml::Value::Array(samples)) => samples
+			.iter()
+			.enumerate()
+			.map(|(index, sample)| {
+				sample
+					.as_table()
+					.ok_or_else(|| format!("sample {index} must be a table").into())
+					.and_then(|sample| BatchSample::from_manifest(sample, index))
+			})
+			.collect::<Result<_, _>>()?,
+		Some(_) => return Err("`sample` must be an array of tables; use `[[sample]]` sections".into()),
+	};
+
+	let threads = std::thread::available_parallelism().unwrap_or(NonZeroUsize::MIN);
+	let mut sample_directory = SampleDirectory::new(address)?;
+	SampleDirectory::check_sample_names(samples.iter().map(|sample| sample.name.as_str()))?;
+	for sample in samples {
+		let (data, report) = sample.encode(base_directory, threads)?;
+		if verbose {
+			println!(
+				"Encoded sample `{}` to {} bytes BRR with a signal-to-noise ratio of {:.2} dB.",
+				sample.name,
+				data.len(),
+				report.signal_to_noise_ratio
+			);
+		}
+		sample_directory.push(DirectorySample { name: sample.name, data, loop_block: report.loop_block });
+	}
+
+	let binary = sample_directory.to_binary()?;
+	std::fs::write(&output, &binary).map_err(|err| format!("error writing {}: {err}", output.display()))?;
+	let source_name = manifest_path
+		.file_name()
+		.map_or_else(|| manifest_path.display().to_string(), |name| name.to_string_lossy().to_string());
+	std::fs::write(&include, sample_directory.to_include(&source_name))
+		.map_err(|err| format!("error writing {}: {err}", include.display()))?;
+	if verbose {
+		println!(
+			"Wrote sample directory with {} samples ({} bytes) at ${address:04X} to {} and {}.",
+			sample_directory.samples().len(),
+			binary.len(),
+			output.display(),
+			include.display()
+		);
+	}
+	Ok(())
+}
+
+/// Extracts all samples from the sample directory of an .spc file and writes them as BRR and WAV files.
+fn run_extract(input: &Path, output_directory: Option<PathBuf>, entries: u16, verbose: bool) -> Result<(), SharedStr> {
+	let data = std::fs::read(input).map_err(|err| format!("{}: {err}", input.display()))?;
+	// The parser's error contains the entire remaining file, which is not useful to print.
+	let spc =
+		spcfile::parser::parse_from_bytes(&data).map_err(|_| format!("{}: not a valid .spc file", input.display()))?;
+	let directory_address = u16::from(spc.memory.dsp_registers[DIR_REGISTER]) << 8;
+	let output_directory =
+		output_directory.unwrap_or_else(|| input.parent().map_or_else(|| PathBuf::from("."), Path::to_path_buf));
+	let stem = input.file_stem().map_or_else(|| "sample".into(), |stem| stem.to_string_lossy());
+
+	let mut extracted_count = 0;
+	for entry in extract_samples(&spc.memory.ram, directory_address, entries) {
+		let sample = match entry {
+			Ok(sample) => sample,
+			Err(invalid) => {
+				if verbose {
+					println!("Skipped directory entry {:02X}: {}.", invalid.index, invalid.reason);
+				}
+				continue;
+			},
+		};
+		let output = output_directory.join(format!("{stem}-{:02X}", sample.index));
+		let brr_output = output.with_extension("brr");
+		std::fs::write(&brr_output, &sample.data)
+			.map_err(|err| format!("error writing {}: {err}", brr_output.display()))?;
+
+		let Playback { samples, loop_region } =
+			decode_playback(&sample.data, sample.loop_block.unwrap_or(0) * 16, PlaybackLength::Loops(1))?;
+		let wav_output = output.with_extension("wav");
+		File::create(&wav_output)
+			.map_err(|err| SharedStr::from(err.to_string()))
+			.and_then(|file| {
+				let mut file = std::io::BufWriter::new(file);
+				wav::write_wav_for_brr(&mut file, &samples, loop_region)?;
+				file.flush().map_err(|err| err.to_string().into())
+			})
+			.map_err(|err| format!("error writing {}: {err}", wav_output.display()))?;
+
+		if verbose {
+			print!("Extracted sample {:02X} at ${:04X} ({} bytes", sample.index, sample.start, sample.data.len());
+			sample.loop_block.map_or_else(
+				|| {
+					println!(").");
+				},
+				|loop_block| {
+					println!(", looping at block {loop_block}).");
+				},
+			);
+		}
+		extracted_count += 1;
+	}
+	if verbose {
+		println!(
+			"Extracted {extracted_count} samples from the sample directory at ${directory_address:04X} to {}.",
+			output_directory.display()
+		);
+	}
+	Ok(())
+}
+
+/// Renders a BRR file like the DSP plays it back and writes the result as a WAV file.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss, clippy::cast_precision_loss)]
+fn run_preview(
+	input: &Path,
+	output: &Path,
+	settings: voice::VoiceSettings,
+	key_off: Option<f64>,
+	duration: f64,
+	verbose: bool,
+) -> Result<(), SharedStr> {
+	let encoded = std::fs::read(input).map_err(|err| format!("{}: {err}", input.display()))?;
+	let sample_rate = f64::from(wav::DSP_SAMPLE_RATE);
+	let settings =
+		voice::VoiceSettings { key_off: key_off.map(|key_off| (key_off.max(0.) * sample_rate) as usize), ..settings };
+	let samples = voice::render_voice(&encoded, settings, (duration.max(0.) * sample_rate) as usize)
+		.map_err(|err| format!("{}: {err}", input.display()))?;
+
+	File::create(output)
+		.map_err(|err| SharedStr::from(err.to_string()))
+		.and_then(|file| {
+			let mut file = std::io::BufWriter::new(file);
+			wav::write_wav_for_brr(&mut file, &samples, None)?;
+			file.flush().map_err(|err| err.to_string().into())
+		})
+		.map_err(|err| format!("error writing {}: {err}", output.display()))?;
+	if verbose {
+		println!(
+			"Rendered {} samples ({:.2} s) at pitch ${:04X} to {}.",
+			samples.len(),
+			samples.len() as f64 / sample_rate,
+			settings.pitch,
+			output.display()
+		);
+	}
+	Ok(())
+}
+
+/// Parse an unsigned number that is either decimal or hexadecimal with a `$` or `0x` prefix.
+fn from_decimal_or_hex(string: &str) -> Result<u16, std::string::String> {
+	string
+		.strip_prefix('$')
+		.or_else(|| string.strip_prefix("0x"))
+		.map_or_else(|| string.parse(), |hex_string| u16::from_str_radix(hex_string, 16))
+		.map_err(|err| err.to_string())
+}
+
+/// Parse a `VxPITCH` value.
+fn parse_pitch(string: &str) -> Result<u16, std::string::String> {
+	from_decimal_or_hex(string)
+		.and_then(|pitch| (pitch <= 0x3FFF).then_some(pitch).ok_or_else(|| "pitch must be at most $3FFF".to_string()))
+}
+
+/// Parse a GAIN register value.
+fn parse_gain(string: &str) -> Result<u8, std::string::String> {
+	from_decimal_or_hex(string).and_then(|gain| u8::try_from(gain).map_err(|_| "GAIN must be at most $FF".to_string()))
+}
+
+/// Parse an ADSR envelope given as comma-separated attack rate, decay rate, sustain level and sustain rate.
+fn parse_adsr(string: &str) -> Result<voice::Envelope, std::string::String> {
+	let values = string
+		.split(',')
+		.map(|value| value.trim().parse::<u8>().map_err(|err| format!("`{}`: {err}", value.trim())))
+		.collect::<Result<Vec<_>, _>>()?;
+	let [attack, decay, sustain_level, sustain_rate] = values[..] else {
+		return Err(format!("expected four values (attack, decay, sustain level, sustain rate), got {}", values.len()));
+	};
+	let ranges = [
+		("attack rate", attack, 15),
+		("decay rate", decay, 7),
+		("sustain level", sustain_level, 7),
+		("sustain rate", sustain_rate, 31),
+	];
+	if let Some((name, value, maximum)) = ranges.into_iter().find(|(_, value, maximum)| value > maximum) {
+		return Err(format!("{name} {value} is out of range (0-{maximum})"));
+	}
+	Ok(voice::Envelope::Adsr { attack, decay, sustain_level, sustain_rate })
+}
+
 /// Parse an i16 while intentionally allowing wrapping and hex numbers.
 #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)] // this is very intentional!
 fn from_lenient_i16(string: &str) -> Result<i16, std::string::String> {
@@ -180,7 +917,7 @@ fn from_lenient_i16(string: &str) -> Result<i16, std::string::String> {
 		.map_err(|err| err.to_string())
 }
 
-#[allow(clippy::too_many_lines)]
+#[allow(clippy::too_many_lines, clippy::cast_possible_truncation, clippy::cast_sign_loss)]
 fn main() {
 	let arguments = Arguments::parse();
 
@@ -238,11 +975,26 @@ fn main() {
 			let (decoded, _) = block.decode(warm_up);
 			println!("Decoded samples: {decoded:?}");
 		},
-		Command::Encode { input, output, compression, filter, loop_point } => {
+		Command::Encode {
+			input,
+			output,
+			compression,
+			filter,
+			loop_point,
+			jobs,
+			input_format: format,
+			channel,
+			mix,
+			peak,
+			rms,
+			dither,
+		} => {
 			let output = output.unwrap_or_else(|| input.with_extension("brr"));
+			let format = input_format(&input, format);
+			let options = conversion_options(channel, mix, peak, rms, dither);
 			let mut samples = File::open(input)
 				.map_err(|err| err.to_string().into())
-				.and_then(wav::read_wav_for_brr)
+				.and_then(|file| wav::read_audio_for_brr(file, format, &options))
 				.unwrap_or_else(|error| {
 					eprintln!("error: {error}");
 					std::process::exit(1);
@@ -254,7 +1006,8 @@ fn main() {
 				Some(PreEmphasisFilter::Treble) => dsp::apply_precise_treble_boost_filter(&samples),
 				None => samples,
 			};
-			let encoded = encode_to_brr(&mut samples, loop_point, compression);
+			let jobs = jobs.or_else(|| std::thread::available_parallelism().ok()).unwrap_or(NonZeroUsize::MIN);
+			let (encoded, report) = encode_to_brr_parallel(&mut samples, loop_point, compression, jobs);
 			let duration = start.elapsed();
 			if arguments.verbose {
 				print!(
@@ -263,14 +1016,18 @@ fn main() {
 					encoded.len(),
 					duration.as_micros(),
 				);
-				loop_point.map_or_else(
+				report.loop_block.map_or_else(
 					|| {
 						println!();
 					},
-					|loop_point| {
-						println!(" Loop point: {}", loop_point & !0b1111);
+					|loop_block| {
+						println!(" Loop block: {loop_block}");
 					},
 				);
+				println!("Signal-to-noise ratio: {:.2} dB", report.signal_to_noise_ratio);
+				if let Some(seam_error) = report.loop_seam_error {
+					println!("Loop seam error: {seam_error}");
+				}
 			}
 			let mut output_file = std::io::BufWriter::new(
 				File::options().write(true).truncate(true).create(true).append(false).open(output).unwrap_or_else(
@@ -285,13 +1042,23 @@ fn main() {
 				std::process::exit(1);
 			});
 		},
-		Command::Decode { input, output, filter } => {
+		Command::Decode { input, output, filter, playback, loop_point, loops, duration } => {
 			let output = output.unwrap_or_else(|| input.with_extension("wav"));
 			let mut encoded = Vec::new();
-			let mut samples = File::open(input)
+			let Playback { mut samples, loop_region } = File::open(input)
 				.and_then(|mut input_file| input_file.read_to_end(&mut encoded))
 				.map_err(|err| err.to_string().into())
-				.and_then(|_| decode_from_brr(&encoded))
+				.and_then(|_| {
+					if playback {
+						let length = duration.map_or_else(
+							|| PlaybackLength::Loops(loops.unwrap_or(1)),
+							|duration| PlaybackLength::Samples((duration * f64::from(wav::DSP_SAMPLE_RATE)) as usize),
+						);
+						decode_playback(&encoded, loop_point.unwrap_or(0), length)
+					} else {
+						decode_from_brr(&encoded).map(|samples| Playback { samples, loop_region: None })
+					}
+				})
 				.unwrap_or_else(|error| {
 					eprintln!("error: {error}");
 					std::process::exit(1);
@@ -300,13 +1067,17 @@ fn main() {
 			if filter {
 				dsp::apply_hardware_gauss_filter(&mut samples);
 			}
-
-			let header = hound::WavSpec {
-				channels:        1,
-				sample_rate:     32_000,
-				bits_per_sample: 16,
-				sample_format:   hound::SampleFormat::Int,
-			};
+			if arguments.verbose {
+				print!("Decoded {} samples.", samples.len());
+				loop_region.as_ref().map_or_else(
+					|| {
+						println!();
+					},
+					|loop_region| {
+						println!(" Loop region: {}-{}", loop_region.start, loop_region.end - 1);
+					},
+				);
+			}
 
 			let mut output_file = std::io::BufWriter::new(
 				File::options().write(true).truncate(true).create(true).append(false).open(output).unwrap_or_else(
@@ -317,18 +1088,44 @@ fn main() {
 				),
 			);
 
-			WavWriter::new(&mut output_file, header)
-				.and_then(|mut writer| {
-					let mut writer = writer.get_i16_writer(samples.len() as u32);
-					for s in samples {
-						writer.write_sample(s);
-					}
-					writer.flush()
-				})
+			wav::write_wav_for_brr(&mut output_file, &samples, loop_region)
+				.and_then(|()| output_file.flush().map_err(|error| error.to_string().into()))
 				.unwrap_or_else(|error| {
 					eprintln!("error writing output: {error}");
 					std::process::exit(1);
 				});
 		},
+		Command::Batch { manifest } => {
+			run_batch(&manifest, arguments.verbose).unwrap_or_else(|error| {
+				eprintln!("error: {error}");
+				std::process::exit(1);
+			});
+		},
+		Command::Extract { input, output_directory, entries } => {
+			run_extract(&input, output_directory, entries, arguments.verbose).unwrap_or_else(|error| {
+				eprintln!("error: {error}");
+				std::process::exit(1);
+			});
+		},
+		Command::Preview { input, output, pitch, loop_point, adsr, gain, key_off, duration } => {
+			let output = output.unwrap_or_else(|| input.with_extension("wav"));
+			let envelope = adsr.or_else(|| gain.map(voice::Envelope::Gain)).unwrap_or_default();
+			let settings = voice::VoiceSettings { pitch, envelope, loop_point, key_off: None };
+			run_preview(&input, &output, settings, key_off, duration, arguments.verbose).unwrap_or_else(|error| {
+				eprintln!("error: {error}");
+				std::process::exit(1);
+			});
+		},
+		Command::Inspect { input, format } => {
+			let encoded = std::fs::read(input).unwrap_or_else(|error| {
+				eprintln!("error: {error}");
+				std::process::exit(1);
+			});
+			let (blocks, problems) = inspect_brr(&encoded);
+			print_inspection(&blocks, &problems, format);
+			if !problems.is_empty() {
+				std::process::exit(1);
+			}
+		},
 	}
 }
@@ -0,0 +1,272 @@
+//! Sample directory generation.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+#[allow(unused)]
+use flexstr::{shared_str, IntoSharedStr, SharedStr, ToSharedStr};
+
+use super::Header;
+
+/// Size of one sample directory entry in bytes.
+const entry_size: usize = 4;
+/// Size of one BRR block in bytes.
+const block_size: usize = 9;
+/// Maximum number of entries in a sample directory, since `SRCN` is an 8-bit register.
+const max_entry_count: usize = 0x100;
+/// Size of the address space that sample directories and samples live in.
+const memory_size: usize = 0x1_0000;
+/// The memory-mapped I/O registers, which never contain sample data.
+const io_registers: std::ops::Range<usize> = 0xF0 .. 0x100;
+/// Labels that the include file defines for the directory itself.
+const directory_labels: [&str; 2] = ["sample_directory", "sample_count"];
+/// Suffixes of the labels that the include file defines for every sample, in addition to the sample's name itself.
+const sample_label_suffixes: [&str; 3] = ["_index", "_loop", "_size"];
+
+/// A sample directory (or "sample table") together with the BRR samples it points to, laid out as one contiguous piece
+/// of memory.
+///
+/// The DSP finds BRR samples via the directory in the memory page selected by the `DIR` register. Each directory entry
+/// consists of the sample's start address and its loop address, both as 16-bit little-endian values. Here, the samples
+/// directly follow the directory, in the order they were added.
+#[derive(Clone, Debug)]
+pub struct SampleDirectory {
+	address: u16,
+	samples: Vec<DirectorySample>,
+}
+
+/// A BRR sample in a [`SampleDirectory`].
+#[derive(Clone, Debug)]
+pub struct DirectorySample {
+	/// The sample's name, which is used for its labels in the include file.
+	pub name:       SharedStr,
+	/// The encoded BRR data.
+	pub data:       Vec<u8>,
+	/// Index of the block that the sample loops back to, if the sample is looped. Samples that don't loop use their
+	/// start address as the loop address.
+	pub loop_block: Option<usize>,
+}
+
+impl SampleDirectory {
+	/// Creates an empty sample directory at the given address.
+	///
+	/// # Errors
+	/// If the address is not aligned to a page boundary, which the hardware requires.
+	pub fn new(address: u16) -> Result<Self, SharedStr> {
+		if address % 0x100 != 0 {
+			return Err(format!("Sample directory address ${address:04X} is not aligned to a page boundary").into());
+		}
+		Ok(Self { address, samples: Vec::new() })
+	}
+
+	/// Checks that samples with the given names can be part of one directory, i.e. all labels that the include file
+	/// defines for them and for the directory are distinct.
+	///
+	/// # Errors
+	/// If two samples have the same name, or if a label of one sample is also a label of another sample or of the
+	/// directory, like for samples named `piano` and `piano_loop`.
+	pub fn check_sample_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Result<(), SharedStr> {
+		// Maps every label to the sample it belongs to, or to nothing for the labels of the directory.
+		let mut labels = directory_labels.map(|label| (label.to_owned(), None)).into_iter().collect::<HashMap<_, _>>();
+		for name in names {
+			let sample_labels = std::iter::once(name.to_owned())
+				.chain(sample_label_suffixes.iter().map(|suffix| format!("{name}{suffix}")));
+			for label in sample_labels {
+				if let Some(owner) = labels.insert(label.clone(), Some(name)) {
+					return Err(match owner {
+						Some(other_name) if other_name == name => format!("duplicate sample name `{name}`"),
+						Some(other_name) =>
+							format!("label `{label}` of sample `{name}` is also a label of sample `{other_name}`"),
+						None => format!("label `{label}` of sample `{name}` is also a label of the sample directory"),
+					}
+					.into());
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Adds a sample to the end of the directory.
+	pub fn push(&mut self, sample: DirectorySample) {
+		self.samples.push(sample);
+	}
+
+	/// Returns the samples in the directory.
+	#[must_use]
+	pub fn samples(&self) -> &[DirectorySample] {
+		&self.samples
+	}
+
+	/// Returns the start and loop address of every sample.
+	fn sample_addresses(&self) -> Vec<(usize, usize)> {
+		let mut start = usize::from(self.address) + self.samples.len() * entry_size;
+		self.samples
+			.iter()
+			.map(|sample| {
+				let addresses = (start, start + sample.loop_block.unwrap_or(0) * block_size);
+				start += sample.data.len();
+				addresses
+			})
+			.collect()
+	}
+
+	/// Returns the directory and all samples as binary data, which is to be loaded at the directory's address.
+	///
+	/// # Errors
+	/// If the data doesn't fit into the 64KB address space, or if it overlaps the I/O registers.
+	pub fn to_binary(&self) -> Result<Vec<u8>, SharedStr> {
+		let addresses = self.sample_addresses();
+		let end = addresses
+			.last()
+			.zip(self.samples.last())
+			.map_or_else(|| usize::from(self.address), |((start, _), sample)| start + sample.data.len());
+		if end > memory_size {
+			return Err(format!(
+				"Sample directory at ${:04X} with {} bytes of samples exceeds the address space by {} bytes",
+				self.address,
+				end - usize::from(self.address),
+				end - memory_size
+			)
+			.into());
+		}
+		if usize::from(self.address) < io_registers.end && io_registers.start < end {
+			return Err(format!(
+				"Sample directory at ${:04X} with {} bytes of samples overlaps the I/O registers at ${:04X}-${:04X}",
+				self.address,
+				end - usize::from(self.address),
+				io_registers.start,
+				io_registers.end - 1
+			)
+			.into());
+		}
+
+		let mut binary = Vec::with_capacity(end - usize::from(self.address));
+		for (start, loop_address) in &addresses {
+			binary.extend_from_slice(&(*start as u16).to_le_bytes());
+			binary.extend_from_slice(&(*loop_address as u16).to_le_bytes());
+		}
+		for sample in &self.samples {
+			binary.extend_from_slice(&sample.data);
+		}
+		Ok(binary)
+	}
+
+	/// Returns an spcasm source file that defines labels for the directory and all samples. For every sample, the file
+	/// defines its start address, its index in the directory (as used by the `SRCN` register), its loop address, and
+	/// its size in bytes. The source name is mentioned in a comment.
+	#[must_use]
+	pub fn to_include(&self, source_name: &str) -> String {
+		let mut include = String::new();
+		// Writing to a string never fails.
+		let _: std::fmt::Result = try {
+			writeln!(include, "; Sample directory generated from {source_name}.")?;
+			writeln!(include, "; Load the directory binary at sample_directory and set DIR to its page.")?;
+			writeln!(include, "sample_directory = ${:04X}", self.address)?;
+			writeln!(include, "sample_count = {}", self.samples.len())?;
+			for (index, (sample, (start, loop_address))) in self.samples.iter().zip(self.sample_addresses()).enumerate()
+			{
+				let name = &sample.name;
+				writeln!(include)?;
+				writeln!(include, "{name} = ${start:04X}")?;
+				writeln!(include, "{name}_index = {index}")?;
+				writeln!(include, "{name}_loop = ${loop_address:04X}")?;
+				writeln!(include, "{name}_size = {}", sample.data.len())?;
+			}
+		};
+		include
+	}
+}
+
+/// A BRR sample that was found via a sample directory in memory; see [`extract_samples`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ExtractedSample {
+	/// Index of the sample's directory entry, as used by the `SRCN` register.
+	pub index:      u8,
+	/// Start address of the sample.
+	pub start:      u16,
+	/// The BRR data, from the start address up to and including the first block with the end flag.
+	pub data:       Vec<u8>,
+	/// Index of the block that the sample loops back to, if the end block has the loop flag.
+	pub loop_block: Option<usize>,
+}
+
+/// A sample directory entry that doesn't point to a valid BRR sample; see [`extract_samples`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InvalidEntry {
+	/// Index of the directory entry.
+	pub index:  u8,
+	/// Why the entry doesn't point to a valid sample.
+	pub reason: SharedStr,
+}
+
+/// Reads the given number of entries of the sample directory at the given address from a memory image, and extracts
+/// the BRR samples they point to.
+///
+/// The hardware doesn't know how many entries a directory has, so unused entries usually contain garbage. Therefore,
+/// every entry is checked for whether it points to a plausible sample: The sample must end with an end flag before the
+/// end of memory, it must not overlap the I/O registers, all its blocks must use valid shift amounts, and if it loops,
+/// the loop address must point to one of its blocks. At most 256 entries are read, and entries past the end of memory
+/// are ignored.
+#[must_use]
+pub fn extract_samples(
+	memory: &[u8; memory_size],
+	address: u16,
+	entry_count: u16,
+) -> Vec<Result<ExtractedSample, InvalidEntry>> {
+	let first_entry = usize::from(address);
+	let entry_count = usize::from(entry_count).min(max_entry_count).min((memory_size - first_entry) / entry_size);
+	(0 .. entry_count)
+		.map(|index| {
+			let entry = first_entry + index * entry_size;
+			let start = u16::from_le_bytes([memory[entry], memory[entry + 1]]);
+			let loop_address = usize::from(u16::from_le_bytes([memory[entry + 2], memory[entry + 3]]));
+			let index = index as u8;
+			extract_sample(memory, start, loop_address)
+				.map(|(data, loop_block)| ExtractedSample { index, start, data, loop_block })
+				.map_err(|reason| InvalidEntry { index, reason })
+		})
+		.collect()
+}
+
+/// Extracts the sample at the given start address and returns its data and loop block.
+fn extract_sample(
+	memory: &[u8; memory_size],
+	start: u16,
+	loop_address: usize,
+) -> Result<(Vec<u8>, Option<usize>), SharedStr> {
+	let start = usize::from(start);
+	let mut end = start;
+	let end_header = loop {
+		if end + block_size > memory_size {
+			return Err("the sample runs past the end of memory without an end flag".into());
+		}
+		if end < io_registers.end && io_registers.start < end + block_size {
+			return Err(format!("block {} overlaps the I/O registers", (end - start) / block_size).into());
+		}
+		let header = Header::from(memory[end]);
+		if !header.has_valid_shift() {
+			return Err(format!(
+				"block {} has the invalid shift {}",
+				(end - start) / block_size,
+				header.shift_nibble()
+			)
+			.into());
+		}
+		end += block_size;
+		if header.flags.is_end() {
+			break header;
+		}
+	};
+
+	let loop_block = if end_header.flags.is_raw_loop() {
+		if !(start .. end).contains(&loop_address) || (loop_address - start) % block_size != 0 {
+			return Err(
+				format!("the loop address ${loop_address:04X} is not the start of one of the sample's blocks").into()
+			);
+		}
+		Some((loop_address - start) / block_size)
+	} else {
+		None
+	};
+	Ok((memory[start .. end].to_vec(), loop_block))
+}
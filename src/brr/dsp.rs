@@ -65,6 +65,50 @@ pub fn apply_precise_treble_boost_filter(samples: &[DecodedSample]) -> Vec<Decod
 	apply_fir_filter(filter, samples)
 }
 
+/// Number of lobes of the Lanczos kernel used by [`resample`].
+const resample_lobes: f64 = 3.;
+
+/// Converts the samples from one sample rate to another, using Lanczos resampling.
+///
+/// When the sample rate is reduced, the kernel is widened accordingly, so that it also acts as a low-pass filter which
+/// prevents aliasing.
+#[must_use]
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss, clippy::cast_precision_loss)]
+pub fn resample(samples: &[DecodedSample], from_rate: u32, to_rate: u32) -> Vec<DecodedSample> {
+	if from_rate == to_rate || samples.is_empty() {
+		return samples.to_vec();
+	}
+	let ratio = f64::from(from_rate) / f64::from(to_rate);
+	let kernel_scale = ratio.max(1.);
+	let radius = resample_lobes * kernel_scale;
+	let output_length = (samples.len() as f64 / ratio).round() as usize;
+
+	(0 .. output_length)
+		.map(|index| {
+			let center = index as f64 * ratio;
+			let first = (center - radius).ceil().max(0.) as usize;
+			let last = ((center + radius).floor() as usize).min(samples.len() - 1);
+			let (sum, weight_sum) = (first ..= last).fold((0., 0.), |(sum, weight_sum), input_index| {
+				let weight = lanczos((input_index as f64 - center) / kernel_scale);
+				(weight.mul_add(f64::from(samples[input_index]), sum), weight_sum + weight)
+			});
+			(sum / weight_sum).round().clamp(f64::from(i16::MIN), f64::from(i16::MAX)) as DecodedSample
+		})
+		.collect()
+}
+
+/// The Lanczos kernel, a windowed sinc function.
+fn lanczos(x: f64) -> f64 {
+	if x.abs() < f64::EPSILON {
+		1.
+	} else if x.abs() >= resample_lobes {
+		0.
+	} else {
+		let pi_x = std::f64::consts::PI * x;
+		resample_lobes * pi_x.sin() * (pi_x / resample_lobes).sin() / (pi_x * pi_x)
+	}
+}
+
 /// Apply an FIR (finite impulse response) filter of arbitrary lookback to the input samples.
 ///
 /// The input filter only provides one half of the filter coefficients; it is mirrored and used for lookahead the same
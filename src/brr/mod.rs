@@ -8,6 +8,9 @@
 )]
 
 use std::convert::TryInto;
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 #[allow(unused)]
 use flexstr::{shared_str, IntoSharedStr, SharedStr, ToSharedStr};
@@ -16,7 +19,9 @@ use num_traits::FromPrimitive;
 
 #[cfg(test)] mod test;
 
+pub mod directory;
 pub mod dsp;
+pub mod voice;
 pub mod wav;
 
 /// Only the lower 4 bits are used.
@@ -46,17 +51,31 @@ pub enum CompressionLevel {
 	EstimateShift = 1,
 	/// Brute-force all filters and shifts.
 	Max = 2,
+	/// Brute-force all filters and shifts, and search for the combination of blocks with the smallest overall error
+	/// instead of choosing each block greedily.
+	Lookahead = 3,
+	/// Like [`CompressionLevel::Lookahead`], but use a perceptually weighted error metric.
+	PerceptualLookahead = 4,
 }
 
 impl CompressionLevel {
 	pub(super) const fn estimates_shift(self) -> bool {
 		match self {
 			Self::EstimateShift | Self::OnlyFilterZero => true,
-			Self::Max => false,
+			Self::Max | Self::Lookahead | Self::PerceptualLookahead => false,
 		}
 	}
 }
 
+/// How many candidate encodings the lookahead encoder keeps around after each block.
+const LOOKAHEAD_BEAM_WIDTH: usize = 8;
+/// The signal power below which the perceptual error metric no longer assumes noise to be masked by the signal. This
+/// corresponds to a sample amplitude of 256, or about -42 dBFS.
+const PERCEPTUAL_MASKING_FLOOR: u128 = 256 * 256;
+/// The number of blocks in each region that the encoder hands to a thread. The regions don't depend on the number of
+/// threads, so that the encoded sample doesn't either.
+const REGION_BLOCKS: usize = 128;
+
 /// Encode the given 16-bit samples as BRR samples. The data may be padded via repetition to fit a multiple of 16; if
 /// you don't want this to happen, provide a multiple of 16 samples.
 ///
@@ -68,10 +87,148 @@ pub fn encode_to_brr(
 	loop_point: Option<usize>,
 	compression: CompressionLevel,
 ) -> Vec<u8> {
+	encode_to_brr_with_report(samples, loop_point, compression).0
+}
+
+/// Information about the result of encoding a sample, see [`encode_to_brr_with_report`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EncodeReport {
+	/// Index of the block that the sample loops back to, if the sample is looped.
+	pub loop_block:            Option<usize>,
+	/// The encoding error of the loop start block when it is played after the loop end, which is what causes
+	/// audible clicks at the loop seam. This is the sum of absolute sample differences, like
+	/// [`Block::total_encode_error`].
+	pub loop_seam_error:       Option<u128>,
+	/// The signal-to-noise ratio of the encoded sample in decibels, i.e. the ratio between the power of the input
+	/// samples and the power of the encoding error. This is infinite for a lossless encoding.
+	pub signal_to_noise_ratio: f64,
+}
+
+/// Encode the given 16-bit samples as BRR samples, like [`encode_to_brr`], and report details about the encoding.
+///
+/// The loop start block is decoded with different previous samples depending on whether it is reached from the
+/// preceding block or by looping back from the sample's end. After the main encoding pass, the loop start block is
+/// therefore re-encoded so that it decodes with minimal error on both paths; see [`optimize_loop_seam`].
+#[must_use]
+#[allow(clippy::module_name_repetitions)]
+pub fn encode_to_brr_with_report(
+	samples: &mut Vec<DecodedSample>,
+	loop_point: Option<usize>,
+	compression: CompressionLevel,
+) -> (Vec<u8>, EncodeReport) {
+	encode_to_brr_parallel(samples, loop_point, compression, NonZeroUsize::MIN)
+}
+
+/// Encode the given 16-bit samples as BRR samples on up to `threads` threads, and report details about the encoding.
+/// Apart from that, this works like [`encode_to_brr_with_report`].
+///
+/// The sample is split into fixed-size regions of consecutive blocks which are encoded independently, each by whichever
+/// thread is free next. Compression levels up to [`CompressionLevel::Max`] encode each block with the input samples
+/// before it as the filter history, so they don't depend on how the previous region was encoded. The lookahead
+/// compression levels depend on the decoded samples of the previous region, so each region instead starts from the
+/// input samples before it, and the first block of each region is re-encoded with the real filter history afterwards.
+/// Since the regions are the same for any number of threads, the result is identical to encoding on a single thread.
+#[must_use]
+#[allow(clippy::missing_panics_doc)]
+pub fn encode_to_brr_parallel(
+	samples: &mut Vec<DecodedSample>,
+	loop_point: Option<usize>,
+	compression: CompressionLevel,
+	threads: NonZeroUsize,
+) -> (Vec<u8>, EncodeReport) {
 	if samples.is_empty() {
-		return Vec::new();
+		return (Vec::new(), EncodeReport::default());
+	}
+
+	let mut padding = 0;
+	if samples.len() % 16 != 0 {
+		padding = 16 - (samples.len() % 16);
+		samples.splice(0 .. 0, [0].repeat(padding));
 	}
 
+	let (sample_chunks, maybe_last_chunk) = samples.as_chunks::<16>();
+	debug_assert!(maybe_last_chunk.is_empty());
+	debug_assert!(!sample_chunks.is_empty());
+
+	let all_chunks = sample_chunks.to_vec();
+	let block_count = all_chunks.len();
+	// The padding is inserted at the start, so it moves the loop point back as well.
+	let loop_block = loop_point.map(|point| ((point + padding) / 16).min(block_count - 1));
+
+	let is_looped = loop_point.is_some();
+	let encode_region = |region: Range<usize>| match compression {
+		CompressionLevel::Lookahead => encode_blocks_with_lookahead(&all_chunks, region, is_looped, false),
+		CompressionLevel::PerceptualLookahead => encode_blocks_with_lookahead(&all_chunks, region, is_looped, true),
+		_ => encode_blocks_greedily(&all_chunks, region, is_looped, compression),
+	};
+	let encode_region = &encode_region;
+
+	let region_starts = (0 .. block_count).step_by(REGION_BLOCKS).collect::<Vec<_>>();
+	let region = |start: usize| start .. (start + REGION_BLOCKS).min(block_count);
+	let thread_count = threads.get().min(region_starts.len());
+	let mut blocks = if thread_count <= 1 {
+		region_starts.iter().flat_map(|&start| encode_region(region(start))).collect::<Vec<_>>()
+	} else {
+		let next_region = AtomicUsize::new(0);
+		let mut encoded_regions = std::thread::scope(|scope| {
+			// All threads have to be spawned before the first one is joined, so that they run at the same time.
+			#[allow(clippy::needless_collect)]
+			let handles = (0 .. thread_count)
+				.map(|_| {
+					scope.spawn(|| {
+						let mut encoded_regions = Vec::new();
+						while let Some(&start) = region_starts.get(next_region.fetch_add(1, Ordering::Relaxed)) {
+							encoded_regions.push((start, encode_region(region(start))));
+						}
+						encoded_regions
+					})
+				})
+				.collect::<Vec<_>>();
+			handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect::<Vec<_>>()
+		});
+		encoded_regions.sort_unstable_by_key(|(start, _)| *start);
+		encoded_regions.into_iter().flat_map(|(_, blocks)| blocks).collect()
+	};
+	if let CompressionLevel::Lookahead | CompressionLevel::PerceptualLookahead = compression {
+		stitch_regions(
+			&mut blocks,
+			&all_chunks,
+			&region_starts,
+			matches!(compression, CompressionLevel::PerceptualLookahead),
+		);
+	}
+
+	let loop_seam_error =
+		loop_block.map(|loop_block| optimize_loop_seam(&mut blocks, &all_chunks, loop_block, compression));
+
+	#[cfg(debug_assertions)]
+	{
+		let mut filter_type_counts: [usize; 4] = [0, 0, 0, 0];
+		for block in &blocks {
+			filter_type_counts[block.header.filter as u8 as usize] += 1;
+		}
+		println!(
+			"Encoded {} blocks (0: {}, 1: {}, 2: {}, 3: {})",
+			filter_type_counts.iter().sum::<usize>(),
+			filter_type_counts[0],
+			filter_type_counts[1],
+			filter_type_counts[2],
+			filter_type_counts[3]
+		);
+	}
+
+	let signal_to_noise_ratio = signal_to_noise_ratio(&blocks, &all_chunks);
+	let result = blocks.into_iter().flat_map(<[u8; 9]>::from).collect();
+	(result, EncodeReport { loop_block, loop_seam_error, signal_to_noise_ratio })
+}
+
+/// Encodes each block of the region on its own with the best parameters that the compression level allows.
+fn encode_blocks_greedily(
+	chunks: &[DecodedBlockSamples],
+	region: Range<usize>,
+	is_looped: bool,
+	compression: CompressionLevel,
+) -> Vec<Block> {
 	let first_block_encoder = if compression.estimates_shift() {
 		Block::encode_with_filter_good_shift
 	} else {
@@ -80,77 +237,241 @@ pub fn encode_to_brr(
 	let main_block_encoder = match compression {
 		CompressionLevel::OnlyFilterZero => Block::encode_with_filter_0_good_shift,
 		CompressionLevel::EstimateShift => Block::encode_with_good_shift,
-		CompressionLevel::Max => Block::encode,
+		CompressionLevel::Max | CompressionLevel::Lookahead | CompressionLevel::PerceptualLookahead => Block::encode,
 	};
 
-	#[cfg(debug_assertions)]
-	let mut filter_type_counts: [usize; 4] = [1, 0, 0, 0];
+	region
+		.map(|index| {
+			let chunk = chunks[index];
+			let flags = LoopEndFlags::new(index == chunks.len() - 1, index == chunks.len() - 1 && is_looped);
+			if index == 0 {
+				// The first chunk must be encoded with filter 0 to prevent glitches.
+				first_block_encoder([0, 0], chunk, LPCFilter::Zero, flags)
+			} else {
+				let previous_chunk = chunks[index - 1];
+				main_block_encoder(
+					[previous_chunk[previous_chunk.len() - 1], previous_chunk[previous_chunk.len() - 2]],
+					chunk,
+					flags,
+				)
+			}
+		})
+		.collect()
+}
 
-	if samples.len() % 16 != 0 {
-		let needed_elements = 16 - (samples.len() % 16);
-		samples.splice(0 .. 0, [0].repeat(needed_elements));
+/// Encodes the blocks of the region with a beam search over all filters and shifts.
+///
+/// The filter history that a block is decoded with depends on how the previous blocks were encoded, so the encoding
+/// with the smallest error for one block may force a larger error onto the following blocks. Instead of committing to
+/// the best encoding of each block right away, this encoder keeps the [`LOOKAHEAD_BEAM_WIDTH`] best encodings of the
+/// sample so far, extends each of them with all possible encodings of the next block, and again keeps the best ones.
+/// Encodings that end with the same filter history decode the same way from then on, so only the best one of those is
+/// kept. The error is the squared difference of the decoded samples, optionally with perceptual weighting (see
+/// [`block_error`]).
+///
+/// The filter history before the region is unknown unless the region starts at the beginning of the sample, so it is
+/// estimated from the input samples.
+fn encode_blocks_with_lookahead(
+	chunks: &[DecodedBlockSamples],
+	region: Range<usize>,
+	is_looped: bool,
+	perceptual: bool,
+) -> Vec<Block> {
+	/// A candidate encoding of the sample so far.
+	#[derive(Clone, Copy)]
+	struct Path {
+		/// Index of the last block in the block arena.
+		last_block: usize,
+		/// Filter history after the last block.
+		warm_up:    WarmUpSamples,
+		/// Total error of all blocks so far.
+		error:      u128,
 	}
 
-	let (sample_chunks, maybe_last_chunk) = samples.as_chunks::<16>();
-	debug_assert!(maybe_last_chunk.is_empty());
-	debug_assert!(!sample_chunks.is_empty());
+	// All blocks that are part of some candidate, together with the arena index of their predecessor.
+	let mut arena: Vec<(Block, Option<usize>)> = Vec::new();
+	let mut beam: Vec<Path> = Vec::new();
+	let all_filters = LPCFilter::all_filters();
+	// The decoder works with 15-bit samples internally, which is what the filter history contains.
+	let initial_warm_up =
+		region.start.checked_sub(1).map_or([0, 0], |previous| [chunks[previous][15] / 2, chunks[previous][14] / 2]);
+
+	for (index, chunk) in chunks.iter().enumerate().take(region.end).skip(region.start) {
+		let is_end = index == chunks.len() - 1;
+		let flags = LoopEndFlags::new(is_end, is_end && is_looped);
+		// The first block must be encoded with filter 0 to prevent glitches.
+		let filters = if index == 0 { &all_filters[.. 1] } else { &all_filters[..] };
+		let previous_paths = if index == region.start { vec![None] } else { beam.iter().copied().map(Some).collect() };
+
+		let mut candidates = previous_paths
+			.into_iter()
+			.flat_map(move |previous: Option<Path>| {
+				let warm_up = previous.map_or(initial_warm_up, |path| path.warm_up);
+				filters.iter().flat_map(move |filter| {
+					Block::encode_with_filter(warm_up, *chunk, *filter, flags).map(move |block| {
+						let (decoded, new_warm_up) = block.decode(warm_up);
+						let error = previous.map_or(0, |path| path.error) + block_error(&decoded, chunk, perceptual);
+						(block, previous.map(|path| path.last_block), new_warm_up, error)
+					})
+				})
+			})
+			.collect::<Vec<_>>();
+		candidates.sort_by_key(|(_, _, _, error)| *error);
+
+		beam.clear();
+		for (block, predecessor, warm_up, error) in candidates {
+			if beam.len() >= LOOKAHEAD_BEAM_WIDTH {
+				break;
+			}
+			if beam.iter().any(|path| path.warm_up == warm_up) {
+				continue;
+			}
+			arena.push((block, predecessor));
+			beam.push(Path { last_block: arena.len() - 1, warm_up, error });
+		}
+	}
 
-	let mut sample_chunks = sample_chunks.to_vec();
-	let first_chunk = sample_chunks.remove(0);
-	let chunk_count = sample_chunks.len();
+	let mut blocks = Vec::with_capacity(region.len());
+	let mut next_block = beam.first().map(|path| path.last_block);
+	while let Some(index) = next_block {
+		let (block, predecessor) = arena[index];
+		blocks.push(block);
+		next_block = predecessor;
+	}
+	blocks.reverse();
+	blocks
+}
 
-	// Determine whether the first chunk is also the last; i.e. there are exactly 16 samples.
-	let first_block_is_end = sample_chunks.len() == 1;
+/// Re-encodes the first block of each region that was encoded with an estimated filter history, using the real filter
+/// history of the preceding blocks instead.
+fn stitch_regions(blocks: &mut [Block], chunks: &[DecodedBlockSamples], region_starts: &[usize], perceptual: bool) {
+	let mut warm_up = [0, 0];
+	for (index, chunk) in chunks.iter().enumerate() {
+		if index != 0 && region_starts.contains(&index) {
+			let original = blocks[index];
+			blocks[index] = LPCFilter::all_filters()
+				.into_iter()
+				.flat_map(|filter| Block::encode_with_filter(warm_up, *chunk, filter, original.header.flags))
+				.chain(std::iter::once(original))
+				.min_by_key(|block| block_error(&block.decode(warm_up).0, chunk, perceptual))
+				.unwrap();
+		}
+		warm_up = blocks[index].decode(warm_up).1;
+	}
+}
 
-	// This should allocate enough so the vector never needs to reallocate.
-	let mut result = Vec::with_capacity(samples.len() / 2 + sample_chunks.len() + 9);
+/// Returns the squared error between decoded and real samples, as used by the lookahead encoder.
+///
+/// With perceptual weighting, the error is divided by the power of the real samples. Noise is much less audible when
+/// the signal itself is loud, so this lets the encoder trade more noise in loud blocks for less noise in quiet blocks.
+fn block_error(decoded: &DecodedBlockSamples, real_samples: &DecodedBlockSamples, perceptual: bool) -> u128 {
+	let squared_error = decoded
+		.iter()
+		.zip(real_samples.iter())
+		.map(|(actual, expected)| i128::from(*actual).abs_diff(i128::from(*expected)).pow(2))
+		.sum::<u128>();
+	if perceptual {
+		let power = real_samples.iter().map(|sample| i128::from(*sample).unsigned_abs().pow(2)).sum::<u128>() / 16;
+		(squared_error << 32) / (power + PERCEPTUAL_MASKING_FLOOR)
+	} else {
+		squared_error
+	}
+}
 
-	// The first chunk must be encoded with filter 0 to prevent glitches.
-	let first_block_data = first_block_encoder(
-		[0, 0],
-		first_chunk,
-		LPCFilter::Zero,
-		LoopEndFlags::new(first_block_is_end, first_block_is_end && loop_point.is_some()),
-	);
-	result.extend_from_slice(&<[u8; 9]>::from(first_block_data));
-	let mut warm_up: WarmUpSamples = [first_chunk[first_chunk.len() - 1], first_chunk[first_chunk.len() - 2]];
-	for (i, chunk) in sample_chunks.into_iter().enumerate() {
-		let flags =
-			if i == chunk_count - 1 { LoopEndFlags::new(true, loop_point.is_some()) } else { LoopEndFlags::Nothing };
-
-		let block_data = if let Some(point) = loop_point
-			&& ((point & !0b1111) == i * 16)
-		{
-			Block::encode_with_filter_0_good_shift(warm_up, chunk, flags)
-		} else {
-			main_block_encoder(warm_up, chunk, flags)
-		};
+/// Calculates the signal-to-noise ratio in decibels of the encoded blocks when decoded linearly.
+#[allow(clippy::cast_precision_loss)]
+fn signal_to_noise_ratio(blocks: &[Block], chunks: &[DecodedBlockSamples]) -> f64 {
+	let mut warm_up = [0, 0];
+	let (mut signal_power, mut noise_power) = (0u128, 0u128);
+	for (block, chunk) in blocks.iter().zip(chunks) {
+		let (decoded, new_warm_up) = block.decode(warm_up);
+		warm_up = new_warm_up;
+		signal_power += chunk.iter().map(|sample| i128::from(*sample).unsigned_abs().pow(2)).sum::<u128>();
+		noise_power += block_error(&decoded, chunk, false);
+	}
+	if noise_power == 0 {
+		f64::INFINITY
+	} else {
+		10. * (signal_power as f64 / noise_power as f64).log10()
+	}
+}
 
-		#[cfg(debug_assertions)]
-		{
-			filter_type_counts[block_data.header.filter as u8 as usize] += 1;
+/// Re-encodes the loop start block of an encoded sample so that it decodes well on both of its entry paths, and
+/// returns the resulting loop seam error.
+///
+/// When playback first reaches the loop start block, the filter history consists of the samples decoded from the
+/// preceding block. After every loop iteration, however, the filter history consists of the samples decoded from the
+/// loop end block. The main encoder only knows about the first path, so a filtered loop start block can decode to very
+/// different samples after looping, which causes an audible click. This pass tries all filters and shifts for the
+/// loop start block, encoded with either filter history, and keeps the one with the smallest combined error of both
+/// paths. Since the loop start block also influences the filter history at the loop end, the error of the entire loop
+/// on the first path is taken into account as well. Filter 0 doesn't use the filter history at all and is always a
+/// candidate, so the pass falls back to it if no filter works well on both paths. The first block of a sample is
+/// always encoded with filter 0, so if the loop starts there, only filter 0 is used. Likewise, only filter 0 is used
+/// with [`CompressionLevel::OnlyFilterZero`].
+///
+/// The samples are given as 16-sample chunks, one per block.
+#[allow(clippy::missing_panics_doc)]
+pub fn optimize_loop_seam(
+	blocks: &mut [Block],
+	chunks: &[DecodedBlockSamples],
+	loop_block: usize,
+	compression: CompressionLevel,
+) -> u128 {
+	debug_assert!(blocks.len() == chunks.len());
+	let linear_warm_up = blocks[.. loop_block].iter().fold([0, 0], |warm_up, block| block.decode(warm_up).1);
+
+	// Returns the error of the entire loop on the first path, as well as the error of the loop start block on the
+	// loop path.
+	let loop_errors = |loop_start: Block| {
+		let mut warm_up = linear_warm_up;
+		let mut linear_error = 0;
+		for (index, chunk) in chunks.iter().enumerate().skip(loop_block) {
+			let block = if index == loop_block { loop_start } else { blocks[index] };
+			let (decoded, new_warm_up) = block.decode(warm_up);
+			linear_error += decode_error(&decoded, chunk);
+			warm_up = new_warm_up;
 		}
+		(linear_error, decode_error(&loop_start.decode(warm_up).0, &chunks[loop_block]))
+	};
 
-		result.extend_from_slice(&<[u8; 9]>::from(block_data));
-		warm_up = [chunk[chunk.len() - 1], chunk[chunk.len() - 2]];
-	}
+	let original = blocks[loop_block];
+	let all_filters = LPCFilter::all_filters();
+	let filters = if loop_block == 0 || matches!(compression, CompressionLevel::OnlyFilterZero) {
+		&all_filters[.. 1]
+	} else {
+		&all_filters[..]
+	};
+	let loop_end_warm_up = blocks[loop_block ..].iter().fold(linear_warm_up, |warm_up, block| block.decode(warm_up).1);
+	let (best_block, (_, seam_error)) = [linear_warm_up, loop_end_warm_up]
+		.into_iter()
+		.flat_map(move |warm_up| {
+			filters.iter().flat_map(move |filter| {
+				Block::encode_with_filter(warm_up, chunks[loop_block], *filter, original.header.flags)
+			})
+		})
+		.map(|candidate| (candidate, loop_errors(candidate)))
+		.min_by_key(|(_, (linear_error, seam_error))| linear_error + seam_error)
+		.unwrap();
 
-	#[cfg(debug_assertions)]
-	println!(
-		"Encoded {} blocks (0: {}, 1: {}, 2: {}, 3: {})",
-		filter_type_counts.iter().sum::<usize>(),
-		filter_type_counts[0],
-		filter_type_counts[1],
-		filter_type_counts[2],
-		filter_type_counts[3]
-	);
+	blocks[loop_block] = best_block;
+	seam_error
+}
 
-	result
+/// Returns the sum of absolute differences between decoded and real samples.
+fn decode_error(decoded: &DecodedBlockSamples, real_samples: &DecodedBlockSamples) -> u128 {
+	decoded
+		.iter()
+		.zip(real_samples.iter())
+		.map(|(actual, expected)| i128::from(*actual).abs_diff(i128::from(*expected)))
+		.sum()
 }
 
 /// Decode samples from a stream of BRR blocks. Note that this discards loop data and any potential end point is
 /// disregarded; this function always decodes all the input data.
 ///
+/// Use [`decode_playback`] to decode a sample the way the DSP plays it back.
+///
 /// # Errors
 /// All possible header bytes are valid on some level, so no errors are thrown because of this. If the encoded data does
 /// not line up with a BRR block however, an error is returned.
@@ -193,6 +514,207 @@ pub fn decode_from_brr(encoded: &[u8]) -> Result<Vec<DecodedSample>, SharedStr>
 	Ok(decoded_samples)
 }
 
+/// How long [`decode_playback`] plays back a looping sample.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PlaybackLength {
+	/// Play the sample once, and then repeat the loop this many times.
+	Loops(usize),
+	/// Play exactly this many samples, repeating the loop as often as necessary.
+	Samples(usize),
+}
+
+/// A BRR sample decoded the way the DSP plays it back.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Playback {
+	/// The played samples.
+	pub samples:     Vec<DecodedSample>,
+	/// The range of played samples that contains one full iteration of the loop, if the sample loops. This is the
+	/// first repetition of the loop, since the first pass through the loop starts with different filter history, or
+	/// the first pass if the loop is not repeated.
+	pub loop_region: Option<Range<usize>>,
+}
+
+/// Decode samples from a stream of BRR blocks the way the DSP plays them back.
+///
+/// Playback stops after the first block with the end flag. If that block also has the loop flag, playback instead
+/// continues at the given loop point, which is a sample index that is rounded down to the start of its block, just like
+/// the DSP's loop address always points to a block. The filter history carries over into the loop, just like on
+/// hardware.
+///
+/// If the data ends without any end flag, playback stops there, even though the DSP would continue reading whatever
+/// data follows the sample in memory.
+///
+/// # Errors
+/// If the encoded data does not line up with a BRR block, or if the loop point is outside the sample.
+pub fn decode_playback(encoded: &[u8], loop_point: usize, length: PlaybackLength) -> Result<Playback, SharedStr> {
+	let (blocks, remainder) = encoded.as_chunks::<9>();
+	if !remainder.is_empty() {
+		return Err(format!("Cut off BRR block (size {}) at the end of the stream", remainder.len()).into());
+	}
+	let loop_block = loop_point / 16;
+	if loop_block >= blocks.len() && !blocks.is_empty() {
+		return Err(
+			format!("Loop point {loop_point} is after the end of the sample ({} samples)", blocks.len() * 16).into()
+		);
+	}
+
+	let mut samples = Vec::with_capacity(blocks.len() * 16);
+	let mut loop_region = None;
+	let mut loop_start = 0;
+	let mut loops_played = 0;
+	let mut warm_up = [0, 0];
+	let mut index = 0;
+	while let Some(raw_block) = blocks.get(index) {
+		if let PlaybackLength::Samples(sample_count) = length
+			&& samples.len() >= sample_count
+		{
+			break;
+		}
+		if index == loop_block {
+			loop_start = samples.len();
+		}
+
+		let block = Block::from(*raw_block);
+		let (decoded, new_warm_up) = block.decode(warm_up);
+		samples.extend_from_slice(&decoded);
+		warm_up = new_warm_up;
+		index += 1;
+
+		if block.is_loop() {
+			let is_cut_off = matches!(length, PlaybackLength::Samples(sample_count) if samples.len() > sample_count);
+			if loops_played <= 1 && !is_cut_off {
+				loop_region = Some(loop_start .. samples.len());
+			}
+			if matches!(length, PlaybackLength::Loops(loop_count) if loops_played >= loop_count) {
+				break;
+			}
+			loops_played += 1;
+			index = loop_block;
+		} else if block.is_end() {
+			break;
+		}
+	}
+
+	if let PlaybackLength::Samples(sample_count) = length {
+		samples.truncate(sample_count);
+	}
+	Ok(Playback { samples, loop_region })
+}
+
+/// Information about one block of a BRR stream, see [`inspect_brr`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BlockInspection {
+	/// The block itself.
+	pub block:             Block,
+	/// The number of samples in the block that are affected by hardware glitches when the stream is decoded linearly.
+	/// See [`Block::hardware_glitch_count`].
+	pub hardware_glitches: usize,
+}
+
+/// A problem with the structure of a BRR stream, as found by [`inspect_brr`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StreamProblem {
+	/// No block has the end flag, so playback continues with whatever data follows the stream in memory.
+	MissingEndFlag,
+	/// More blocks follow the first block with the end flag, which playback never reaches.
+	DataAfterEnd {
+		/// Index of the first block with the end flag.
+		end_block:    usize,
+		/// Number of blocks after the end block.
+		extra_blocks: usize,
+	},
+	/// A block has the loop flag, but not the end flag, so the loop flag has no effect.
+	LoopFlagWithoutEnd {
+		/// Index of the block.
+		block: usize,
+	},
+	/// A block has a shift of 13 to 15, which the hardware decoder doesn't support.
+	InvalidShift {
+		/// Index of the block.
+		block: usize,
+		/// The shift as it is stored in the block header.
+		shift: u8,
+	},
+	/// The stream ends with an incomplete block.
+	CutOffBlock {
+		/// Number of bytes in the incomplete block.
+		bytes: usize,
+	},
+}
+
+impl StreamProblem {
+	/// Returns the index of the block that this problem concerns, if it concerns a specific block.
+	#[must_use]
+	pub const fn block(&self) -> Option<usize> {
+		match self {
+			Self::MissingEndFlag | Self::CutOffBlock { .. } => None,
+			Self::DataAfterEnd { end_block: block, .. }
+			| Self::LoopFlagWithoutEnd { block }
+			| Self::InvalidShift { block, .. } => Some(*block),
+		}
+	}
+}
+
+impl std::fmt::Display for StreamProblem {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::MissingEndFlag =>
+				write!(f, "no block has the end flag, so playback continues past the end of the data"),
+			Self::DataAfterEnd { end_block, extra_blocks } =>
+				write!(f, "block {end_block} ends the sample, but {extra_blocks} more block(s) follow it"),
+			Self::LoopFlagWithoutEnd { block } =>
+				write!(f, "block {block} has the loop flag, which has no effect without the end flag"),
+			Self::InvalidShift { block, shift } => write!(f, "block {block} has the invalid shift {shift}"),
+			Self::CutOffBlock { bytes } => write!(f, "the data ends with a cut-off block of {bytes} byte(s)"),
+		}
+	}
+}
+
+/// Decodes all blocks of a BRR stream and checks the stream for structural problems.
+///
+/// Unlike [`decode_from_brr`], this function accepts any data, since it is intended for validating samples of unknown
+/// origin.
+#[must_use]
+pub fn inspect_brr(encoded: &[u8]) -> (Vec<BlockInspection>, Vec<StreamProblem>) {
+	let (raw_blocks, remainder) = encoded.as_chunks::<9>();
+	let mut warm_up = [0, 0];
+	let blocks = raw_blocks
+		.iter()
+		.map(|raw_block| {
+			let block = Block::from(*raw_block);
+			let hardware_glitches = block.hardware_glitch_count(warm_up);
+			warm_up = block.decode(warm_up).1;
+			BlockInspection { block, hardware_glitches }
+		})
+		.collect::<Vec<_>>();
+
+	let mut problems = Vec::new();
+	match blocks.iter().position(|inspection| inspection.block.is_end()) {
+		None => problems.push(StreamProblem::MissingEndFlag),
+		Some(end_block) if end_block < blocks.len() - 1 =>
+			problems.push(StreamProblem::DataAfterEnd { end_block, extra_blocks: blocks.len() - 1 - end_block }),
+		Some(_) => {},
+	}
+	problems.extend(
+		blocks
+			.iter()
+			.enumerate()
+			.filter(|(_, inspection)| {
+				inspection.block.header.flags.is_raw_loop() && !inspection.block.header.flags.is_end()
+			})
+			.map(|(block, _)| StreamProblem::LoopFlagWithoutEnd { block }),
+	);
+	problems.extend(
+		blocks.iter().enumerate().filter(|(_, inspection)| !inspection.block.header.has_valid_shift()).map(
+			|(block, inspection)| StreamProblem::InvalidShift { block, shift: inspection.block.header.shift_nibble() },
+		),
+	);
+	if !remainder.is_empty() {
+		problems.push(StreamProblem::CutOffBlock { bytes: remainder.len() });
+	}
+	(blocks, problems)
+}
+
 /// A 9-byte encoded BRR block.
 ///
 /// Each BRR block starts with a header byte followed by 8 sample bytes. Each sample byte in turn holds 2 4-bit samples.
@@ -235,12 +757,7 @@ impl Block {
 	/// `real_samples`. This is an important internal utility function for finding the best filter for these samples.
 	#[must_use]
 	pub fn total_encode_error(&self, warm_up_samples: WarmUpSamples, real_samples: &DecodedBlockSamples) -> u128 {
-		let (decoded, _) = self.decode(warm_up_samples);
-		decoded
-			.iter()
-			.zip(real_samples.iter())
-			.map(|(actual, expected)| i128::from(*actual).abs_diff(i128::from(*expected)))
-			.sum()
+		decode_error(&self.decode(warm_up_samples).0, real_samples)
 	}
 
 	/// Encode the given sample block using the given filter. This returns all shift amounts.
@@ -405,6 +922,13 @@ impl Block {
 		self.internal_decode_lpc(warm_up_samples, self.header.filter.coefficient())
 	}
 
+	/// Returns how many samples of this block are changed by the hardware glitches that occur when a decoded sample
+	/// exceeds the 15-bit range of the decoder, given the previous decoded samples.
+	#[must_use]
+	pub fn hardware_glitch_count(&self, warm_up_samples: WarmUpSamples) -> usize {
+		self.decode_counting_glitches(warm_up_samples, self.header.filter.coefficient()).2
+	}
+
 	/// Decode a third of a block, which is what the hardware BRR decoder does at maximum every sample step.
 	#[must_use]
 	pub fn decode_block_third(
@@ -435,10 +959,22 @@ impl Block {
 
 	fn internal_decode_lpc(
 		&self,
-		mut warm_up_samples: WarmUpSamples,
+		warm_up_samples: WarmUpSamples,
 		filter_coefficients: FilterCoefficients,
 	) -> (DecodedBlockSamples, WarmUpSamples) {
+		let (decoded_samples, warm_up_samples, _) = self.decode_counting_glitches(warm_up_samples, filter_coefficients);
+		(decoded_samples, warm_up_samples)
+	}
+
+	/// Decodes this block like [`Self::internal_decode_lpc`], and additionally returns the number of samples that were
+	/// changed by hardware glitches.
+	fn decode_counting_glitches(
+		&self,
+		mut warm_up_samples: WarmUpSamples,
+		filter_coefficients: FilterCoefficients,
+	) -> (DecodedBlockSamples, WarmUpSamples, usize) {
 		let mut decoded_samples: DecodedBlockSamples = [0; 16];
+		let mut glitch_count = 0;
 
 		let shifted_encoded =
 			self.encoded_samples.map(|encoded| self.header.perform_shift(i16::from(((encoded as i8) << 4) >> 4)));
@@ -447,6 +983,9 @@ impl Block {
 				.wrapping_add(filter_coefficients[0](warm_up_samples[0]))
 				.wrapping_add(filter_coefficients[1](warm_up_samples[1]));
 			*decoded = simulate_hardware_glitches(decimal_decoded);
+			if *decoded != decimal_decoded {
+				glitch_count += 1;
+			}
 			// Shift last samples through the buffer
 			warm_up_samples[1] = warm_up_samples[0];
 			warm_up_samples[0] = *decoded;
@@ -455,7 +994,7 @@ impl Block {
 			// back to 16 bits.
 			*decoded = decoded.wrapping_mul(2);
 		}
-		(decoded_samples, warm_up_samples)
+		(decoded_samples, warm_up_samples, glitch_count)
 	}
 }
 
@@ -564,13 +1103,25 @@ impl From<u8> for Header {
 
 impl From<Header> for u8 {
 	fn from(header: Header) -> u8 {
-		((((header.real_shift + 1) as u8) << 4) & 0xf0)
-			| (((header.filter as u8) << 2) & 0b1100)
-			| ((header.flags as u8) & 0b11)
+		((header.shift_nibble() << 4) & 0xf0) | (((header.filter as u8) << 2) & 0b1100) | ((header.flags as u8) & 0b11)
 	}
 }
 
 impl Header {
+	/// Returns the shift as it is stored in the header byte, which is one more than [`Self::real_shift`].
+	#[must_use]
+	#[allow(clippy::cast_sign_loss)]
+	pub const fn shift_nibble(&self) -> u8 {
+		(self.real_shift + 1) as u8
+	}
+
+	/// Returns whether the header's shift is valid. Shifts of 13 to 15 are not supported by the hardware decoder, which
+	/// only keeps the sign of the encoded samples in that case.
+	#[must_use]
+	pub const fn has_valid_shift(&self) -> bool {
+		self.shift_nibble() <= 12
+	}
+
 	/// Executes the shift in this header on the given sample, taking care to shift right by 1 if the shift amount is
 	/// -1.
 	#[inline]
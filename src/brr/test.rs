@@ -5,13 +5,22 @@
 #![allow(overflowing_literals)]
 
 extern crate test;
+use std::num::NonZeroUsize;
+
 #[allow(unused)]
 use flexstr::{shared_str, IntoSharedStr, SharedStr, ToSharedStr};
 use test::Bencher;
 
-use super::wav::read_wav_for_brr;
+use super::directory::{extract_samples, DirectorySample, ExtractedSample, SampleDirectory};
+use super::voice::{render_voice, Envelope, VoiceSettings};
+use super::wav::{
+	read_audio_for_brr, read_audio_for_brr_with_rate, read_wav_for_brr, write_wav_for_brr, AudioFormat,
+	ConversionOptions, Downmix, Normalization,
+};
 use super::{
-	encode_to_brr, Block, CompressionLevel, DecodedBlockSamples, Header, LPCFilter, LoopEndFlags, WarmUpSamples,
+	decode_playback, dsp, encode_to_brr, encode_to_brr_parallel, encode_to_brr_with_report, inspect_brr, Block,
+	CompressionLevel, DecodedBlockSamples, DecodedSample, Header, LPCFilter, LoopEndFlags, PlaybackLength,
+	StreamProblem, WarmUpSamples,
 };
 use crate::brr::decode_from_brr;
 
@@ -167,6 +176,39 @@ fn short_sample_encode(bencher: &mut Bencher) {
 	bencher.iter(|| encode_to_brr(&mut data, None, CompressionLevel::Max));
 }
 
+#[bench]
+#[cfg_attr(miri, ignore)]
+fn short_sample_encode_parallel(bencher: &mut Bencher) {
+	use hound::WavReader;
+
+	let reader = WavReader::new(std::fs::File::open("tests/yoshi.wav").unwrap()).unwrap();
+	let mut data = reader.into_samples::<i16>().try_collect().expect("must be signed 16-bit WAV");
+	let threads = std::thread::available_parallelism().unwrap();
+	bencher.iter(|| encode_to_brr_parallel(&mut data, None, CompressionLevel::Max, threads));
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn parallel_encode() {
+	use hound::WavReader;
+
+	let reader = WavReader::new(std::fs::File::open("tests/yoshi.wav").unwrap()).unwrap();
+	let data: Vec<i16> = reader.into_samples::<i16>().try_collect().expect("must be signed 16-bit WAV");
+	let threads = NonZeroUsize::new(4).unwrap();
+	for compression in [CompressionLevel::OnlyFilterZero, CompressionLevel::EstimateShift, CompressionLevel::Max] {
+		let serial = encode_to_brr(&mut data.clone(), Some(400), compression);
+		let (parallel, _) = encode_to_brr_parallel(&mut data.clone(), Some(400), compression, threads);
+		assert_eq!(serial, parallel);
+	}
+
+	let samples = (0 .. 16 * 300).map(|i| ((i % 24) - 12) * 1000 + (i % 7) * 300).collect::<Vec<_>>();
+	for compression in [CompressionLevel::Lookahead, CompressionLevel::PerceptualLookahead] {
+		let (serial, _) = encode_to_brr_parallel(&mut samples.clone(), None, compression, NonZeroUsize::MIN);
+		let (parallel, _) = encode_to_brr_parallel(&mut samples.clone(), None, compression, threads);
+		assert_eq!(serial, parallel);
+	}
+}
+
 #[test]
 #[cfg_attr(miri, ignore)]
 fn different_compression_levels() {
@@ -180,6 +222,192 @@ fn different_compression_levels() {
 	let _ = decode_from_brr(&bad);
 }
 
+#[test]
+fn playback_follows_loop_flags() {
+	// Filter 0 and no shift; the first block decodes to 2, the second block decodes to 4 and loops.
+	let mut encoded = [[0x10].as_slice(), &[0x11; 8], &[0x13], &[0x22; 8]].concat();
+	let looped = decode_playback(&encoded, 16, PlaybackLength::Loops(2)).unwrap();
+	assert_eq!(looped.samples, [[2; 16], [4; 16], [4; 16], [4; 16]].concat());
+	assert_eq!(looped.loop_region, Some(32 .. 48));
+
+	let cut_off = decode_playback(&encoded, 16, PlaybackLength::Samples(40)).unwrap();
+	assert_eq!(cut_off.samples.len(), 40);
+	assert_eq!(cut_off.loop_region, Some(16 .. 32));
+
+	// Without the loop flag, playback stops after the end block even if more data follows.
+	encoded[0] = 0x11;
+	encoded[9] = 0x10;
+	let ended = decode_playback(&encoded, 0, PlaybackLength::Loops(2)).unwrap();
+	assert_eq!(ended.samples, [2; 16]);
+	assert_eq!(ended.loop_region, None);
+
+	assert!(decode_playback(&encoded, 32, PlaybackLength::Loops(2)).is_err());
+}
+
+#[test]
+fn loop_seam_error() {
+	// A sawtooth whose period doesn't line up with the loop, so the loop start block sees very different filter
+	// history after looping back.
+	let mut samples = (0 .. 128).map(|i| ((i % 24) - 12) * 1000).collect::<Vec<_>>();
+	let (encoded, report) = encode_to_brr_with_report(&mut samples.clone(), Some(56), CompressionLevel::Max);
+	assert_eq!(report.loop_block, Some(3));
+	let seam_error = report.loop_seam_error.unwrap();
+
+	let playback = decode_playback(&encoded, 48, PlaybackLength::Loops(1)).unwrap();
+	let loop_start = playback.loop_region.unwrap().start;
+	let looped_error = playback.samples[loop_start .. loop_start + 16]
+		.iter()
+		.zip(&samples[48 .. 64])
+		.map(|(actual, expected)| i128::from(*actual).abs_diff(i128::from(*expected)))
+		.sum::<u128>();
+	assert_eq!(seam_error, looped_error);
+
+	let (_, unlooped_report) = encode_to_brr_with_report(&mut samples, None, CompressionLevel::Max);
+	assert_eq!(unlooped_report.loop_block, None);
+	assert_eq!(unlooped_report.loop_seam_error, None);
+}
+
+/// Encodes the samples and returns the total squared error of the decoded samples.
+fn total_encode_error(samples: &[DecodedSample], compression: CompressionLevel) -> u128 {
+	// The encoder pads the samples at the start, which the decoded samples then include as well.
+	let mut samples = samples.to_vec();
+	let encoded = encode_to_brr(&mut samples, None, compression);
+	decode_from_brr(&encoded)
+		.unwrap()
+		.iter()
+		.zip(&samples)
+		.map(|(actual, expected)| i128::from(*actual).abs_diff(i128::from(*expected)).pow(2))
+		.sum()
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn lookahead_encode() {
+	use hound::WavReader;
+
+	let reader = WavReader::new(std::fs::File::open("tests/yoshi.wav").unwrap()).unwrap();
+	let yoshi: Vec<i16> = reader.into_samples::<i16>().try_collect().expect("must be signed 16-bit WAV");
+	assert!(
+		total_encode_error(&yoshi, CompressionLevel::Lookahead) < total_encode_error(&yoshi, CompressionLevel::Max)
+	);
+
+	let samples = (0 .. 256).map(|i| ((i % 24) - 12) * 1000 + (i % 7) * 300).collect::<Vec<_>>();
+	assert!(
+		total_encode_error(&samples, CompressionLevel::Lookahead)
+			<= total_encode_error(&samples, CompressionLevel::Max)
+	);
+	for compression in [CompressionLevel::Lookahead, CompressionLevel::PerceptualLookahead] {
+		let (encoded, report) = encode_to_brr_with_report(&mut samples.clone(), None, compression);
+		assert_eq!(encoded.len(), 16 * 9);
+		assert_eq!(Header::from(encoded[0]).filter, LPCFilter::Zero);
+		assert_eq!(Header::from(encoded[15 * 9]).flags, LoopEndFlags::new(true, false));
+
+		let decoded = decode_from_brr(&encoded).unwrap();
+		let signal_power = samples.iter().map(|sample| f64::from(*sample).powi(2)).sum::<f64>();
+		let noise_power = decoded
+			.iter()
+			.zip(&samples)
+			.map(|(actual, expected)| (f64::from(*actual) - f64::from(*expected)).powi(2))
+			.sum::<f64>();
+		let signal_to_noise_ratio = 10. * (signal_power / noise_power).log10();
+		assert!((report.signal_to_noise_ratio - signal_to_noise_ratio).abs() < 1e-6);
+	}
+
+	let (_, silent_report) = encode_to_brr_with_report(&mut vec![0; 32], None, CompressionLevel::Lookahead);
+	assert!(silent_report.signal_to_noise_ratio.is_infinite());
+}
+
+#[test]
+fn sample_directory() {
+	assert!(SampleDirectory::new(0x3c80).is_err());
+
+	let mut directory = SampleDirectory::new(0x3c00).unwrap();
+	directory.push(DirectorySample { name: "first".into(), data: vec![0; 27], loop_block: Some(2) });
+	directory.push(DirectorySample { name: "second".into(), data: vec![1; 9], loop_block: None });
+	let binary = directory.to_binary().unwrap();
+	assert_eq!(binary[.. 8], [0x08, 0x3c, 0x1a, 0x3c, 0x23, 0x3c, 0x23, 0x3c]);
+	assert_eq!(binary.len(), 8 + 27 + 9);
+
+	let include = directory.to_include("test.toml");
+	assert!(include.contains("sample_directory = $3C00\nsample_count = 2\n"));
+	assert!(include.contains("second = $3C23\nsecond_index = 1\nsecond_loop = $3C23\nsecond_size = 9\n"));
+
+	let mut full_directory = SampleDirectory::new(0xff00).unwrap();
+	full_directory.push(DirectorySample { name: "large".into(), data: vec![0; 0x100], loop_block: None });
+	assert!(full_directory.to_binary().is_err());
+
+	let mut zero_page = SampleDirectory::new(0).unwrap();
+	zero_page.push(DirectorySample { name: "small".into(), data: vec![0; 0xE4], loop_block: None });
+	assert!(zero_page.to_binary().is_ok());
+	zero_page.push(DirectorySample { name: "large".into(), data: vec![0; 9], loop_block: None });
+	assert!(zero_page.to_binary().is_err());
+
+	assert!(SampleDirectory::check_sample_names(["a", "b", "a_index_loop"]).is_ok());
+	for names in [["a", "a"], ["a", "a_loop"], ["a_size", "a"], ["sample", "sample_count"], ["b", "sample_directory"]] {
+		assert!(SampleDirectory::check_sample_names(names).is_err(), "{names:?} were accepted");
+	}
+}
+
+#[test]
+fn inspect_stream() {
+	let encoded = encode_to_brr(&mut (0 .. 64).map(|i| i * 100).collect(), None, CompressionLevel::Max);
+	let (blocks, problems) = inspect_brr(&encoded);
+	assert_eq!(blocks.len(), encoded.len() / 9);
+	assert!(blocks.iter().all(|inspection| inspection.hardware_glitches == 0));
+	assert!(problems.is_empty());
+
+	let mut broken = encoded.clone();
+	broken[0] |= 0b10;
+	broken.extend_from_slice(&encoded[.. 13]);
+	let (_, problems) = inspect_brr(&broken);
+	assert_eq!(problems, [
+		StreamProblem::DataAfterEnd { end_block: blocks.len() - 1, extra_blocks: 1 },
+		StreamProblem::LoopFlagWithoutEnd { block: 0 },
+		StreamProblem::CutOffBlock { bytes: 4 }
+	]);
+	assert_eq!(inspect_brr(&[0; 9]).1, [StreamProblem::MissingEndFlag]);
+	assert_eq!(inspect_brr(&[0xd1, 0, 0, 0, 0, 0, 0, 0, 0]).1, [StreamProblem::InvalidShift { block: 0, shift: 13 }]);
+}
+
+#[test]
+fn extract_directory_samples() {
+	let mut memory: Box<[u8; 0x1_0000]> = vec![0; 0x1_0000].into_boxed_slice().try_into().unwrap();
+	memory[0x1000 .. 0x1010]
+		.copy_from_slice(&[0x00, 0x20, 0x09, 0x20, 0x00, 0x30, 0x00, 0x30, 0xf7, 0xff, 0, 0, 0, 0x40, 0, 0x40]);
+	memory[0x2000] = 0xb0;
+	memory[0x2009] = 0xb3;
+	memory[0x3000] = 0xb1;
+	memory[0x4000] = 0xd0;
+
+	let entries = extract_samples(&memory, 0x1000, 256);
+	assert_eq!(entries.len(), 256);
+	assert_eq!(
+		entries[0],
+		Ok(ExtractedSample {
+			index:      0,
+			start:      0x2000,
+			data:       memory[0x2000 .. 0x2012].to_vec(),
+			loop_block: Some(1),
+		})
+	);
+	assert_eq!(entries[1].as_ref().unwrap().loop_block, None);
+	assert!(entries[2].is_err());
+	assert!(entries[3].is_err());
+	assert!(entries[4 ..].iter().all(Result::is_err));
+	assert_eq!(extract_samples(&memory, 0xff00, 256).len(), 64);
+}
+
+#[test]
+fn wav_loop_chunk() {
+	let mut wav = Vec::new();
+	write_wav_for_brr(&mut wav, &[0; 64], Some(16 .. 48)).unwrap();
+	assert_eq!(u32::from_le_bytes(wav[4 .. 8].try_into().unwrap()) as usize, wav.len() - 8);
+	let chunk = &wav[wav.len() - 68 ..];
+	assert_eq!(&chunk[.. 4], b"smpl");
+	assert_eq!(chunk[52 .. 60], [16, 0, 0, 0, 47, 0, 0, 0]);
+	assert_eq!(hound::WavReader::new(std::io::Cursor::new(wav)).unwrap().len(), 64);
+}
+
 #[test]
 fn encode_empty() {
 	assert!(encode_to_brr(&mut Vec::new(), None, CompressionLevel::Max).is_empty());
@@ -194,6 +422,120 @@ fn wav_sample_formats() {
 	let _ = read_wav_for_brr(std::fs::File::open("tests/yoshi-stereo.wav").unwrap()).unwrap();
 }
 
+#[test]
+fn audio_formats() {
+	let read = |path: &str| {
+		let format = AudioFormat::from_extension(std::path::Path::new(path));
+		read_audio_for_brr_with_rate(std::fs::File::open(path).unwrap(), format, &ConversionOptions::default()).unwrap()
+	};
+	let wav = read("tests/yoshi.wav");
+	assert_eq!(read("tests/yoshi.aiff"), wav);
+	assert_eq!(read("tests/yoshi.flac"), wav);
+	assert_eq!(read("tests/yoshi.raw"), wav);
+	assert_eq!(AudioFormat::from_extension(std::path::Path::new("sample.AIF")), AudioFormat::Aiff);
+	assert_eq!(AudioFormat::from_extension(std::path::Path::new("sample")), AudioFormat::Wav);
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+#[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+fn resample_sine() {
+	// A tenth of a second of a 1 kHz sine, so that every DFT bin below is 10 Hz wide.
+	let sine = (0 .. 3200)
+		.map(|index| ((f64::from(index) * 1000. / 32000. * std::f64::consts::TAU).sin() * 16000.) as DecodedSample)
+		.collect::<Vec<_>>();
+	let peak_frequency = |samples: &[DecodedSample], rate: u32| {
+		let magnitude = |bin: usize| {
+			let (real, imaginary) = samples.iter().enumerate().fold((0., 0.), |(real, imaginary), (index, sample)| {
+				let phase = std::f64::consts::TAU * (bin * index) as f64 / samples.len() as f64;
+				(phase.cos().mul_add(f64::from(*sample), real), phase.sin().mul_add(-f64::from(*sample), imaginary))
+			});
+			real.hypot(imaginary)
+		};
+		let peak_bin = (1 .. samples.len() / 2)
+			.map(|bin| (bin, magnitude(bin)))
+			.max_by(|(_, a), (_, b)| a.total_cmp(b))
+			.unwrap()
+			.0;
+		peak_bin as f64 * f64::from(rate) / samples.len() as f64
+	};
+
+	for rate in [16000, 48000] {
+		let resampled = dsp::resample(&sine, 32000, rate);
+		assert_eq!(resampled.len(), rate as usize / 10);
+		assert!((peak_frequency(&resampled, rate) - 1000.).abs() < f64::EPSILON);
+	}
+	assert_eq!(dsp::resample(&sine, 32000, 32000), sine);
+}
+
+#[test]
+fn conversion_options() {
+	let read = |options: ConversionOptions| {
+		read_audio_for_brr(std::fs::File::open("tests/yoshi-stereo.wav").unwrap(), AudioFormat::Wav, &options)
+	};
+	let left = read(ConversionOptions { downmix: Downmix::Channel(0), ..Default::default() }).unwrap();
+	assert_eq!(
+		read(ConversionOptions { downmix: Downmix::Weights(vec![1., 0.]), ..Default::default() }).unwrap(),
+		left
+	);
+	// Without options, the channels are averaged with truncation, like before the options existed.
+	let right = read(ConversionOptions { downmix: Downmix::Channel(1), ..Default::default() }).unwrap();
+	assert_eq!(
+		read(ConversionOptions::default()).unwrap(),
+		left.iter()
+			.zip(&right)
+			.map(|(left, right)| ((i32::from(*left) + i32::from(*right)) / 2) as i16)
+			.collect::<Vec<_>>()
+	);
+	assert!(read(ConversionOptions { downmix: Downmix::Channel(2), ..Default::default() }).is_err());
+	assert!(read(ConversionOptions { downmix: Downmix::Weights(vec![1.]), ..Default::default() }).is_err());
+
+	let normalized = read(ConversionOptions {
+		downmix: Downmix::Channel(1),
+		normalization: Some(Normalization::Peak(0.)),
+		..Default::default()
+	})
+	.unwrap();
+	assert!(normalized.iter().map(|sample| sample.unsigned_abs()).max().unwrap() >= i16::MAX as u16);
+
+	let dithered =
+		ConversionOptions { normalization: Some(Normalization::Rms(-12.)), dither: true, ..Default::default() };
+	assert_eq!(read(dithered.clone()).unwrap(), read(dithered).unwrap());
+}
+
+#[test]
+fn voice_preview() {
+	// Filter 0 and shift 11; the block decodes to 2048 and loops.
+	let mut encoded = [[0xB3].as_slice(), &[0x11; 8]].concat();
+	let looped = render_voice(&encoded, VoiceSettings::default(), 100).unwrap();
+	assert_eq!(looped, [[0; 5].as_slice(), &[2032; 95]].concat());
+
+	// Key off releases the envelope linearly.
+	let released = render_voice(&encoded, VoiceSettings { key_off: Some(50), ..Default::default() }, 1000).unwrap();
+	assert_eq!(released[50 .. 54], [2032, 2024, 2016, 2008]);
+	assert!(released.len() < 320);
+
+	// Attack rate 10 steps the envelope every 20 samples.
+	let attack = Envelope::Adsr { attack: 10, decay: 7, sustain_level: 7, sustain_rate: 0 };
+	let attacked = render_voice(&encoded, VoiceSettings { envelope: attack, ..Default::default() }, 100).unwrap();
+	assert_eq!(attacked[.. 16], [0; 16]);
+	assert_eq!(attacked[16 .. 36], [32; 20]);
+	assert_eq!(attacked[36], 64);
+
+	// Without the loop flag, the voice is silenced as soon as the decoder reaches the end block.
+	encoded[0] = 0xB1;
+	assert_eq!(render_voice(&encoded, VoiceSettings::default(), 100).unwrap(), [0; 5]);
+	assert!(render_voice(&encoded[.. 8], VoiceSettings::default(), 100).is_err());
+	assert!(render_voice(&encoded, VoiceSettings { loop_point: 16, ..Default::default() }, 100).is_err());
+
+	let mut samples = read_wav_for_brr(std::fs::File::open("tests/yoshi.wav").unwrap()).unwrap();
+	let encoded = encode_to_brr(&mut samples, None, CompressionLevel::Max);
+	let original = render_voice(&encoded, VoiceSettings::default(), usize::MAX).unwrap();
+	let octave_up = render_voice(&encoded, VoiceSettings { pitch: 0x2000, ..Default::default() }, usize::MAX).unwrap();
+	assert!(original.len().abs_diff(samples.len()) < 32);
+	assert!(octave_up.len().abs_diff(samples.len() / 2) < 32);
+}
+
 #[cfg(not(debug_assertions))]
 // Slow test that doesn't cover more than the BRR tests themselves.
 #[cfg_attr(miri, ignore)]
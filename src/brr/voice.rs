@@ -0,0 +1,406 @@
+//! Emulation of a single S-DSP voice, for previewing BRR samples the way the hardware plays them back.
+//!
+//! The emulation follows the per-sample behavior of the DSP: the 5-sample delay after key on, BRR decoding in groups
+//! of four samples into the 12-sample interpolation buffer, the pitch counter, Gaussian interpolation and the ADSR and
+//! GAIN envelopes including the hardware's envelope rate timing. Everything after the envelope, like channel volume,
+//! echo and the main mixer, is not emulated.
+
+#[allow(unused)]
+use flexstr::{shared_str, IntoSharedStr, SharedStr, ToSharedStr};
+
+use super::{Block, DecodedBlockSamples, DecodedSample, WarmUpSamples};
+
+/// Gaussian interpolation table of the DSP.
+pub const gauss_table: [u16; 512] = [
+	0x000, 0x000, 0x000, 0x000, 0x000, 0x000, 0x000, 0x000, 0x000, 0x000, 0x000, 0x000, 0x000, 0x000, 0x000, 0x000,
+	0x001, 0x001, 0x001, 0x001, 0x001, 0x001, 0x001, 0x001, 0x001, 0x001, 0x001, 0x002, 0x002, 0x002, 0x002, 0x002,
+	0x002, 0x002, 0x003, 0x003, 0x003, 0x003, 0x003, 0x004, 0x004, 0x004, 0x004, 0x004, 0x005, 0x005, 0x005, 0x005,
+	0x006, 0x006, 0x006, 0x006, 0x007, 0x007, 0x007, 0x008, 0x008, 0x008, 0x009, 0x009, 0x009, 0x00A, 0x00A, 0x00A,
+	0x00B, 0x00B, 0x00B, 0x00C, 0x00C, 0x00D, 0x00D, 0x00E, 0x00E, 0x00F, 0x00F, 0x00F, 0x010, 0x010, 0x011, 0x011,
+	0x012, 0x013, 0x013, 0x014, 0x014, 0x015, 0x015, 0x016, 0x017, 0x017, 0x018, 0x018, 0x019, 0x01A, 0x01B, 0x01B,
+	0x01C, 0x01D, 0x01D, 0x01E, 0x01F, 0x020, 0x020, 0x021, 0x022, 0x023, 0x024, 0x024, 0x025, 0x026, 0x027, 0x028,
+	0x029, 0x02A, 0x02B, 0x02C, 0x02D, 0x02E, 0x02F, 0x030, 0x031, 0x032, 0x033, 0x034, 0x035, 0x036, 0x037, 0x038,
+	0x03A, 0x03B, 0x03C, 0x03D, 0x03E, 0x040, 0x041, 0x042, 0x043, 0x045, 0x046, 0x047, 0x049, 0x04A, 0x04C, 0x04D,
+	0x04E, 0x050, 0x051, 0x053, 0x054, 0x056, 0x057, 0x059, 0x05A, 0x05C, 0x05E, 0x05F, 0x061, 0x063, 0x064, 0x066,
+	0x068, 0x06A, 0x06B, 0x06D, 0x06F, 0x071, 0x073, 0x075, 0x076, 0x078, 0x07A, 0x07C, 0x07E, 0x080, 0x082, 0x084,
+	0x086, 0x089, 0x08B, 0x08D, 0x08F, 0x091, 0x093, 0x096, 0x098, 0x09A, 0x09C, 0x09F, 0x0A1, 0x0A3, 0x0A6, 0x0A8,
+	0x0AB, 0x0AD, 0x0AF, 0x0B2, 0x0B4, 0x0B7, 0x0BA, 0x0BC, 0x0BF, 0x0C1, 0x0C4, 0x0C7, 0x0C9, 0x0CC, 0x0CF, 0x0D2,
+	0x0D4, 0x0D7, 0x0DA, 0x0DD, 0x0E0, 0x0E3, 0x0E6, 0x0E9, 0x0EC, 0x0EF, 0x0F2, 0x0F5, 0x0F8, 0x0FB, 0x0FE, 0x101,
+	0x104, 0x107, 0x10B, 0x10E, 0x111, 0x114, 0x118, 0x11B, 0x11E, 0x122, 0x125, 0x129, 0x12C, 0x130, 0x133, 0x137,
+	0x13A, 0x13E, 0x141, 0x145, 0x148, 0x14C, 0x150, 0x153, 0x157, 0x15B, 0x15F, 0x162, 0x166, 0x16A, 0x16E, 0x172,
+	0x176, 0x17A, 0x17D, 0x181, 0x185, 0x189, 0x18D, 0x191, 0x195, 0x19A, 0x19E, 0x1A2, 0x1A6, 0x1AA, 0x1AE, 0x1B2,
+	0x1B7, 0x1BB, 0x1BF, 0x1C3, 0x1C8, 0x1CC, 0x1D0, 0x1D5, 0x1D9, 0x1DD, 0x1E2, 0x1E6, 0x1EB, 0x1EF, 0x1F3, 0x1F8,
+	0x1FC, 0x201, 0x205, 0x20A, 0x20F, 0x213, 0x218, 0x21C, 0x221, 0x226, 0x22A, 0x22F, 0x233, 0x238, 0x23D, 0x241,
+	0x246, 0x24B, 0x250, 0x254, 0x259, 0x25E, 0x263, 0x267, 0x26C, 0x271, 0x276, 0x27B, 0x280, 0x284, 0x289, 0x28E,
+	0x293, 0x298, 0x29D, 0x2A2, 0x2A6, 0x2AB, 0x2B0, 0x2B5, 0x2BA, 0x2BF, 0x2C4, 0x2C9, 0x2CE, 0x2D3, 0x2D8, 0x2DC,
+	0x2E1, 0x2E6, 0x2EB, 0x2F0, 0x2F5, 0x2FA, 0x2FF, 0x304, 0x309, 0x30E, 0x313, 0x318, 0x31D, 0x322, 0x326, 0x32B,
+	0x330, 0x335, 0x33A, 0x33F, 0x344, 0x349, 0x34E, 0x353, 0x357, 0x35C, 0x361, 0x366, 0x36B, 0x370, 0x374, 0x379,
+	0x37E, 0x383, 0x388, 0x38C, 0x391, 0x396, 0x39B, 0x39F, 0x3A4, 0x3A9, 0x3AD, 0x3B2, 0x3B7, 0x3BB, 0x3C0, 0x3C5,
+	0x3C9, 0x3CE, 0x3D2, 0x3D7, 0x3DC, 0x3E0, 0x3E5, 0x3E9, 0x3ED, 0x3F2, 0x3F6, 0x3FB, 0x3FF, 0x403, 0x408, 0x40C,
+	0x410, 0x415, 0x419, 0x41D, 0x421, 0x425, 0x42A, 0x42E, 0x432, 0x436, 0x43A, 0x43E, 0x442, 0x446, 0x44A, 0x44E,
+	0x452, 0x455, 0x459, 0x45D, 0x461, 0x465, 0x468, 0x46C, 0x470, 0x473, 0x477, 0x47A, 0x47E, 0x481, 0x485, 0x488,
+	0x48C, 0x48F, 0x492, 0x496, 0x499, 0x49C, 0x49F, 0x4A2, 0x4A6, 0x4A9, 0x4AC, 0x4AF, 0x4B2, 0x4B5, 0x4B7, 0x4BA,
+	0x4BD, 0x4C0, 0x4C3, 0x4C5, 0x4C8, 0x4CB, 0x4CD, 0x4D0, 0x4D2, 0x4D5, 0x4D7, 0x4D9, 0x4DC, 0x4DE, 0x4E0, 0x4E3,
+	0x4E5, 0x4E7, 0x4E9, 0x4EB, 0x4ED, 0x4EF, 0x4F1, 0x4F3, 0x4F5, 0x4F6, 0x4F8, 0x4FA, 0x4FB, 0x4FD, 0x4FF, 0x500,
+	0x502, 0x503, 0x504, 0x506, 0x507, 0x508, 0x50A, 0x50B, 0x50C, 0x50D, 0x50E, 0x50F, 0x510, 0x511, 0x511, 0x512,
+	0x513, 0x514, 0x514, 0x515, 0x516, 0x516, 0x517, 0x517, 0x517, 0x518, 0x518, 0x518, 0x518, 0x518, 0x519, 0x519,
+];
+
+/// Number of samples between envelope steps for each envelope rate. Rate 0 never steps, since its period is longer
+/// than the range of the envelope counter.
+const envelope_rate_periods: [u16; 32] = [
+	envelope_counter_range + 1,
+	2048,
+	1536,
+	1280,
+	1024,
+	768,
+	640,
+	512,
+	384,
+	320,
+	256,
+	192,
+	160,
+	128,
+	96,
+	80,
+	64,
+	48,
+	40,
+	32,
+	24,
+	20,
+	16,
+	12,
+	10,
+	8,
+	6,
+	5,
+	4,
+	3,
+	2,
+	1,
+];
+/// Offsets of the envelope rates into the shared envelope counter, which determine at which counter values a rate
+/// steps.
+const envelope_rate_offsets: [u16; 32] = [
+	1, 0, 1040, 536, 0, 1040, 536, 0, 1040, 536, 0, 1040, 536, 0, 1040, 536, 0, 1040, 536, 0, 1040, 536, 0, 1040, 536,
+	0, 1040, 536, 0, 1040, 0, 0,
+];
+/// The envelope counter counts down from this value (exclusive) and wraps around. It is a multiple of all envelope
+/// rate periods.
+const envelope_counter_range: u16 = 2048 * 5 * 3;
+/// Number of samples between key on and the start of playback.
+const key_on_delay_length: u8 = 5;
+/// Size of the buffer that decoded samples are interpolated from.
+const buffer_size: usize = 12;
+/// Pitch (`VxPITCH`) value that plays a sample at its original rate of 32kHz.
+pub const original_pitch: u16 = 0x1000;
+
+/// The envelope that a voice applies to its sample.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Envelope {
+	/// An ADSR envelope, as set with the `ADSR1` and `ADSR2` registers. The values are the hardware's rate and level
+	/// numbers, not times.
+	Adsr {
+		/// Attack rate, 0-15. Rate 15 jumps to full volume immediately.
+		attack:        u8,
+		/// Decay rate, 0-7.
+		decay:         u8,
+		/// Sustain level, 0-7. The decay phase ends at (sustain level + 1) / 8 of full volume.
+		sustain_level: u8,
+		/// Sustain rate, 0-31. During the sustain phase, the envelope keeps decreasing exponentially at this rate.
+		/// Rate 0 keeps the envelope at the sustain level.
+		sustain_rate:  u8,
+	},
+	/// A GAIN envelope, given as the value of the `GAIN` register. Values below $80 set a fixed envelope level, the
+	/// others select one of the linear or exponential increase and decrease modes together with its rate.
+	Gain(u8),
+}
+
+impl Default for Envelope {
+	/// A fixed envelope at full volume.
+	fn default() -> Self {
+		Self::Gain(0x7F)
+	}
+}
+
+impl Envelope {
+	/// Returns the values of the `ADSR1` and `ADSR2` or `GAIN` registers that select this envelope. Unused registers
+	/// are zero.
+	#[must_use]
+	pub const fn registers(self) -> (u8, u8, u8) {
+		match self {
+			Self::Adsr { attack, decay, sustain_level, sustain_rate } => (
+				0x80 | ((decay & 0b111) << 4) | (attack & 0b1111),
+				((sustain_level & 0b111) << 5) | (sustain_rate & 0b1_1111),
+				0,
+			),
+			Self::Gain(gain) => (0, 0, gain),
+		}
+	}
+}
+
+/// Settings of a voice for [`render_voice`], which correspond to the voice's DSP registers.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct VoiceSettings {
+	/// The pitch, as in the `VxPITCH` register. Only the lower 14 bits are used. See [`original_pitch`].
+	pub pitch:      u16,
+	/// The envelope of the voice.
+	pub envelope:   Envelope,
+	/// The sample index that the sample loops back to, like the loop address of a sample directory entry. The loop
+	/// point is rounded down to the start of its block.
+	pub loop_point: usize,
+	/// Number of samples after key on at which the voice is keyed off, if at all. After key off, the envelope
+	/// decreases quickly until the voice is silent.
+	pub key_off:    Option<usize>,
+}
+
+impl Default for VoiceSettings {
+	fn default() -> Self {
+		Self { pitch: original_pitch, envelope: Envelope::default(), loop_point: 0, key_off: None }
+	}
+}
+
+/// Phase of the envelope.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum EnvelopePhase {
+	Attack,
+	Decay,
+	Sustain,
+	Release,
+}
+
+/// State of the emulated voice.
+struct Voice<'a> {
+	blocks:           &'a [[u8; 9]],
+	settings:         VoiceSettings,
+	/// Samples left until the voice starts playing after key on.
+	key_on_delay:     u8,
+	/// Index of the block that is currently decoded.
+	block_index:      usize,
+	/// Index of the next group of four samples to decode in the current block.
+	group:            usize,
+	/// The current block, decoded.
+	block_samples:    DecodedBlockSamples,
+	warm_up_samples:  WarmUpSamples,
+	/// Ring buffer of the last decoded samples.
+	buffer:           [DecodedSample; buffer_size],
+	/// Index in the buffer where the next decoded samples are written, which is also the oldest sample.
+	buffer_position:  usize,
+	/// Fixed-point position of the interpolation relative to the buffer position, with 12 fractional bits.
+	pitch_counter:    u16,
+	phase:            EnvelopePhase,
+	/// Envelope level, 11 bits.
+	envelope:         i32,
+	/// Envelope level that the envelope would step to, which is also used by the bent line GAIN mode.
+	hidden_envelope:  i32,
+	/// Shared envelope rate counter, which counts down once per sample.
+	envelope_counter: u16,
+}
+
+impl Voice<'_> {
+	/// Returns the header byte of the block that is currently decoded. Playback past the end of the data behaves like
+	/// a block with the end flag.
+	fn header(&self) -> u8 {
+		self.blocks.get(self.block_index).map_or(0b01, |block| block[0])
+	}
+
+	/// Decodes the next four samples into the buffer.
+	fn decode_group(&mut self) {
+		if self.group == 0 {
+			let block =
+				Block::from(self.blocks.get(self.block_index).copied().unwrap_or([0b01, 0, 0, 0, 0, 0, 0, 0, 0]));
+			(self.block_samples, self.warm_up_samples) = block.decode(self.warm_up_samples);
+		}
+		let group = &self.block_samples[self.group * 4 .. self.group * 4 + 4];
+		self.buffer[self.buffer_position .. self.buffer_position + 4].copy_from_slice(group);
+		self.buffer_position = (self.buffer_position + 4) % buffer_size;
+
+		self.group += 1;
+		if self.group == 4 {
+			self.group = 0;
+			// The end flag makes the decoder jump to the loop address, even if the voice stops at the end.
+			self.block_index =
+				if self.header() & 0b01 == 0 { self.block_index + 1 } else { self.settings.loop_point / 16 };
+		}
+	}
+
+	/// Returns the Gaussian interpolation of the buffered samples at the current pitch counter position.
+	fn interpolate(&self) -> i32 {
+		let fraction = usize::from(self.pitch_counter >> 4 & 0xFF);
+		let index = self.buffer_position + usize::from(self.pitch_counter >> 12);
+		let sample = |offset: usize| i32::from(self.buffer[(index + offset) % buffer_size]);
+		let weight = |table_index: usize| i32::from(gauss_table[table_index]);
+
+		let mut output = (weight(255 - fraction) * sample(0)) >> 11;
+		output += (weight(511 - fraction) * sample(1)) >> 11;
+		output += (weight(256 + fraction) * sample(2)) >> 11;
+		// The hardware wraps the intermediate result to 16 bits before adding the last sample.
+		output = i32::from(output as i16);
+		output += (weight(fraction) * sample(3)) >> 11;
+		output.clamp(i16::MIN.into(), i16::MAX.into()) & !1
+	}
+
+	/// Returns whether the envelope rate steps at the current envelope counter value.
+	const fn rate_steps(&self, rate: usize) -> bool {
+		(self.envelope_counter + envelope_rate_offsets[rate]) % envelope_rate_periods[rate] == 0
+	}
+
+	/// Runs the envelope for one sample.
+	fn run_envelope(&mut self) {
+		if self.phase == EnvelopePhase::Release {
+			self.envelope = (self.envelope - 8).max(0);
+			return;
+		}
+
+		let (adsr1, adsr2, gain) = self.settings.envelope.registers();
+		let mut envelope = self.envelope;
+		let (rate, level_register) = if adsr1 & 0x80 != 0 {
+			if self.phase == EnvelopePhase::Attack {
+				let rate = usize::from(adsr1 & 0x0F) * 2 + 1;
+				envelope += if rate < 31 { 0x20 } else { 0x400 };
+				(rate, adsr2)
+			} else {
+				envelope -= 1;
+				envelope -= envelope >> 8;
+				let rate = if self.phase == EnvelopePhase::Decay {
+					usize::from(adsr1 >> 3 & 0x0E) + 0x10
+				} else {
+					usize::from(adsr2 & 0x1F)
+				};
+				(rate, adsr2)
+			}
+		} else {
+			let mode = gain >> 5;
+			if mode < 4 {
+				envelope = i32::from(gain) * 0x10;
+				(31, gain)
+			} else {
+				match mode {
+					// Linear decrease
+					4 => envelope -= 0x20,
+					// Exponential decrease
+					5 => {
+						envelope -= 1;
+						envelope -= envelope >> 8;
+					},
+					// Linear increase
+					6 => envelope += 0x20,
+					// Bent line increase, which slows down at 3/4 of full volume
+					_ => envelope += if self.hidden_envelope < 0x600 { 0x20 } else { 0x08 },
+				}
+				(usize::from(gain & 0x1F), gain)
+			}
+		};
+
+		if self.phase == EnvelopePhase::Decay && envelope >> 8 == i32::from(level_register >> 5) {
+			self.phase = EnvelopePhase::Sustain;
+		}
+		self.hidden_envelope = envelope;
+		if !(0 ..= 0x7FF).contains(&envelope) {
+			envelope = envelope.clamp(0, 0x7FF);
+			if self.phase == EnvelopePhase::Attack {
+				self.phase = EnvelopePhase::Decay;
+			}
+		}
+		if self.rate_steps(rate) {
+			self.envelope = envelope;
+		}
+	}
+
+	/// Runs the voice for one sample and returns its output.
+	fn step(&mut self, sample_index: usize) -> DecodedSample {
+		self.envelope_counter = self.envelope_counter.checked_sub(1).unwrap_or(envelope_counter_range - 1);
+		let mut pitch = self.settings.pitch & 0x3FFF;
+		// The header is not read yet on the first sample after key on.
+		let header = if self.key_on_delay == key_on_delay_length { 0 } else { self.header() };
+		if self.key_on_delay > 0 {
+			self.envelope = 0;
+			self.hidden_envelope = 0;
+			self.key_on_delay -= 1;
+			// The first three groups of samples are decoded during the last samples of the delay.
+			self.pitch_counter = if self.key_on_delay % 4 == 0 { 0 } else { 0x4000 };
+			pitch = 0;
+		}
+
+		let output = (self.interpolate() * self.envelope) >> 11 & !1;
+
+		// A block with the end flag but without the loop flag silences the voice as soon as the decoder reaches it.
+		if header & 0b11 == 0b01 {
+			self.phase = EnvelopePhase::Release;
+			self.envelope = 0;
+		}
+		if self.settings.key_off == Some(sample_index) {
+			self.phase = EnvelopePhase::Release;
+		}
+		if self.key_on_delay == 0 {
+			self.run_envelope();
+		}
+
+		if self.pitch_counter >= 0x4000 {
+			self.decode_group();
+		}
+		self.pitch_counter = ((self.pitch_counter & 0x3FFF) + pitch).min(0x7FFF);
+		output as DecodedSample
+	}
+
+	/// Returns whether the voice has stopped, which is the case once the envelope has been released completely.
+	fn is_silent(&self) -> bool {
+		self.key_on_delay == 0 && self.phase == EnvelopePhase::Release && self.envelope == 0
+	}
+}
+
+/// Renders a BRR sample the way one voice of the DSP plays it back with the given settings, starting with key on.
+///
+/// The output is the voice's 32kHz output after the envelope, before the voice volume is applied. Rendering stops once
+/// the voice is silent after the sample ended or the voice was keyed off, or after `max_length` samples. Just like on
+/// hardware, a sample that ends without looping is silenced as soon as the decoder reaches its end block, so the end
+/// block itself is never heard.
+///
+/// If the data ends without any end flag, the voice stops there, even though the DSP would continue reading whatever
+/// data follows the sample in memory.
+///
+/// # Errors
+/// If the encoded data does not line up with a BRR block, or if the loop point is outside the sample.
+pub fn render_voice(
+	encoded: &[u8],
+	settings: VoiceSettings,
+	max_length: usize,
+) -> Result<Vec<DecodedSample>, SharedStr> {
+	let (blocks, remainder) = encoded.as_chunks::<9>();
+	if !remainder.is_empty() {
+		return Err(format!("Cut off BRR block (size {}) at the end of the stream", remainder.len()).into());
+	}
+	if settings.loop_point / 16 >= blocks.len() && !blocks.is_empty() {
+		return Err(format!(
+			"Loop point {} is after the end of the sample ({} samples)",
+			settings.loop_point,
+			blocks.len() * 16
+		)
+		.into());
+	}
+
+	let mut voice = Voice {
+		blocks,
+		settings,
+		key_on_delay: key_on_delay_length,
+		block_index: 0,
+		group: 0,
+		block_samples: [0; 16],
+		warm_up_samples: [0, 0],
+		buffer: [0; buffer_size],
+		buffer_position: 0,
+		pitch_counter: 0,
+		phase: EnvelopePhase::Attack,
+		envelope: 0,
+		hidden_envelope: 0,
+		envelope_counter: 0,
+	};
+	let mut samples = Vec::new();
+	while samples.len() < max_length && !voice.is_silent() {
+		samples.push(voice.step(samples.len()));
+	}
+	Ok(samples)
+}
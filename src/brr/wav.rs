@@ -1,56 +1,177 @@
-//! WAV file handling and conversion.
+//! WAV and other audio file handling and conversion.
 
 use std::fs::File;
-use std::io::{BufReader, Read};
+use std::io::{BufReader, Cursor, Read, Write};
+use std::ops::Range;
+use std::path::Path;
 
+use claxon::FlacReader;
 #[allow(unused)]
 use flexstr::{shared_str, IntoSharedStr, SharedStr, ToSharedStr};
-use hound::{SampleFormat, WavReader};
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
 
 use super::DecodedSample;
 
 const i24max: f64 = (0xff_ffff - 1) as f64;
+/// The value that corresponds to full scale for 16-bit samples.
+const full_scale: f64 = 32768.;
 
-/// Treats the given file as a WAV file, reads its samples and converts them to 16-bit mono. Note that there currently
-/// is no sample rate conversion.
-///
-/// # Errors
-/// Any errors from the WAV support library are passed on, as well as some custom errors.
-pub fn read_wav_for_brr(file: File) -> Result<Vec<DecodedSample>, SharedStr> {
-	let reader = WavReader::new(BufReader::new(file)).map_err(|err| SharedStr::from(err.to_string()))?;
-	convert_sample_format(reader)
+/// Sample rate of the DSP, in Hz.
+pub const DSP_SAMPLE_RATE: u32 = 32_000;
+
+/// Audio file formats that samples can be read from.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum AudioFormat {
+	/// Uncompressed WAV, with integer or float samples.
+	#[default]
+	Wav,
+	/// Uncompressed AIFF or AIFF-C, with integer samples.
+	Aiff,
+	/// FLAC.
+	Flac,
+	/// Headerless mono 16-bit signed little-endian PCM, which is assumed to be at the DSP sample rate.
+	Raw,
 }
 
-/// Convert the sample format to signed 16 bit mono.
-fn convert_sample_format<R: Read>(reader: WavReader<R>) -> Result<Vec<DecodedSample>, SharedStr> {
-	let channels = reader.spec().channels;
-	let s16bit_data = convert_bit_depth_to_16_bits(reader)?;
-	if channels > 1 {
-		average_channels(&s16bit_data, channels)
-	} else {
-		Ok(s16bit_data)
+impl AudioFormat {
+	/// Detects the format of an audio file from its extension. Files with unknown extensions are assumed to be WAV
+	/// files.
+	#[must_use]
+	pub fn from_extension(path: &Path) -> Self {
+		match path.extension().map(|extension| extension.to_string_lossy().to_ascii_lowercase()).as_deref() {
+			Some("aif" | "aiff" | "aifc") => Self::Aiff,
+			Some("flac") => Self::Flac,
+			Some("raw" | "pcm") => Self::Raw,
+			_ => Self::Wav,
+		}
 	}
 }
 
-/// Convert all samples to 16 bits.
-fn convert_bit_depth_to_16_bits<R: Read>(mut reader: WavReader<R>) -> Result<Vec<i16>, SharedStr> {
-	match reader.spec().bits_per_sample {
-		16 => reader.samples::<i16>().try_collect(),
-		8 => reader.samples::<i8>()
-			// The order of operations is very important! If we don't bracket the scaling constant at the end, we will overflow the first value into oblivion.
-			.map(|sample| Ok((i16::from(sample?) - i16::from(u8::MAX / 2)) * (i16::MAX / i16::from(u8::MAX))))
-			.try_collect(),
-		// It appears that we can only downsample effectively with an intermediary floating-point step.
-		// The problem is that the i16max / i24max is less than 1, so cannot be calculated in integer math.
-		24 => reader
-			.samples::<i32>()
-			.map(|sample| Ok((f64::from(sample?) / i24max * f64::from(i16::MAX)) as i16))
-			.try_collect(),
-		32 if reader.spec().sample_format == SampleFormat::Float =>
-			reader.samples::<f32>().map(|sample| Ok((sample? * f32::from(i16::MAX)) as i16)).try_collect(),
-		_ => Err(hound::Error::FormatError("Unsupported bit depth")),
+/// How multi-channel audio is mixed down to mono.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Downmix {
+	/// Average all channels with equal weight.
+	#[default]
+	Average,
+	/// Only use the channel with this (zero-based) index.
+	Channel(u16),
+	/// Mix the channels with these weights, one per channel. A weight of 1 keeps the channel's level.
+	Weights(Vec<f64>),
+}
+
+impl Downmix {
+	/// Returns the weight of every channel.
+	#[allow(clippy::cast_precision_loss)]
+	fn channel_weights(&self, channels: usize) -> Result<Vec<f64>, SharedStr> {
+		match self {
+			Self::Average => Ok(vec![1. / channels as f64; channels]),
+			Self::Channel(channel) if usize::from(*channel) < channels =>
+				Ok((0 .. channels).map(|index| if index == usize::from(*channel) { 1. } else { 0. }).collect()),
+			Self::Channel(channel) =>
+				Err(format!("Channel {channel} was selected, but the audio only has {channels} channel(s)").into()),
+			Self::Weights(weights) if weights.len() == channels => Ok(weights.clone()),
+			Self::Weights(weights) =>
+				Err(format!("{} mixdown weights were given, but the audio has {channels} channel(s)", weights.len())
+					.into()),
+		}
+	}
+}
+
+/// Gain normalization of the mixed-down audio.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Normalization {
+	/// Scale the audio so that its highest peak is at this level, in dBFS.
+	Peak(f64),
+	/// Scale the audio so that its RMS level is at this level, in dBFS. Peaks that exceed full scale are clipped.
+	Rms(f64),
+}
+
+impl Normalization {
+	/// Returns the factor that the samples need to be multiplied with. Silent audio is left as-is.
+	#[allow(clippy::cast_precision_loss)]
+	fn gain(self, samples: &[f64]) -> f64 {
+		let (level, target) = match self {
+			Self::Peak(target) => (samples.iter().fold(0., |peak: f64, sample| peak.max(sample.abs())), target),
+			Self::Rms(target) => (
+				(samples.iter().map(|sample| sample * sample).sum::<f64>() / samples.len().max(1) as f64).sqrt(),
+				target,
+			),
+		};
+		if level > 0. {
+			10f64.powf(target / 20.) / level
+		} else {
+			1.
+		}
+	}
+}
+
+/// Options for converting audio to the 16-bit mono samples that are encoded to BRR.
+///
+/// The conversion first mixes all channels down to mono, then normalizes the gain, and finally quantizes the samples
+/// to 16 bits. Samples that exceed the 16-bit range at that point are clipped.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConversionOptions {
+	/// How to mix multi-channel audio down to mono.
+	pub downmix:       Downmix,
+	/// Gain normalization to apply after mixing down, if any. The level is measured over the entire file.
+	pub normalization: Option<Normalization>,
+	/// Whether to add triangular dither noise when quantizing to 16 bits. This avoids quantization distortion when
+	/// reducing 24-bit or 32-bit audio, or after gain changes.
+	pub dither:        bool,
+}
+
+/// Audio read from a file, before it is converted for BRR encoding.
+struct Audio {
+	/// The samples of all channels, interleaved, scaled to the 16-bit range. Samples with a higher bit depth keep
+	/// their additional precision as the fractional part.
+	samples:     Vec<f64>,
+	/// Reduces a sample to 16 bits when no conversion options are given. This is the file format's usual conversion,
+	/// which the conversion options don't change unless they are used.
+	reduce:      fn(f64) -> i16,
+	/// Number of channels.
+	channels:    u16,
+	/// Sample rate in Hz.
+	sample_rate: u32,
+}
+
+impl Audio {
+	/// Converts the audio to 16-bit mono with the given options.
+	#[allow(clippy::cast_possible_truncation)]
+	fn convert(&self, options: &ConversionOptions) -> Result<Vec<DecodedSample>, SharedStr> {
+		if *options == ConversionOptions::default() {
+			let s16bit_data = self.samples.iter().map(|sample| (self.reduce)(*sample)).collect::<Vec<_>>();
+			return if self.channels > 1 { average_channels(&s16bit_data, self.channels) } else { Ok(s16bit_data) };
+		}
+
+		let channels = usize::from(self.channels.max(1));
+		let frames = self.samples.chunks_exact(channels);
+		if !frames.remainder().is_empty() {
+			return Err(format!(
+				"{channels}-channel audio, but last frame only contains {} samples",
+				frames.remainder().len()
+			)
+			.into());
+		}
+		let weights = options.downmix.channel_weights(channels)?;
+		let mut mono = frames
+			.map(|frame| frame.iter().zip(&weights).map(|(sample, weight)| sample * weight).sum::<f64>() / full_scale)
+			.collect::<Vec<_>>();
+		if let Some(normalization) = options.normalization {
+			let gain = normalization.gain(&mono);
+			for sample in &mut mono {
+				*sample *= gain;
+			}
+		}
+
+		let mut dither = options.dither.then(Dither::default);
+		Ok(mono
+			.into_iter()
+			.map(|sample| {
+				let scaled = sample.mul_add(full_scale, dither.as_mut().map_or(0., Dither::sample));
+				scaled.round().clamp(f64::from(i16::MIN), f64::from(i16::MAX)) as i16
+			})
+			.collect())
 	}
-	.map_err(|e| e.to_string().into())
 }
 
 /// Convert any number of channels to mono by averaging all channels together.
@@ -69,3 +190,304 @@ fn average_channels(multichannel_data: &[i16], channels: u16) -> Result<Vec<Deco
 	}
 	Ok(result)
 }
+
+/// Truncates a sample to 16 bits, which is how WAV and raw samples are reduced by default.
+#[allow(clippy::cast_possible_truncation)]
+const fn truncate_sample(sample: f64) -> i16 {
+	sample as i16
+}
+
+/// Rounds a sample down to 16 bits, which is how AIFF and FLAC samples are reduced by default.
+#[allow(clippy::cast_possible_truncation)]
+fn floor_sample(sample: f64) -> i16 {
+	sample.floor() as i16
+}
+
+/// Source of dither noise with a triangular probability distribution and a peak amplitude of one 16-bit step. The
+/// noise is pseudo-random with a fixed seed, so that converting the same audio always gives the same result.
+struct Dither(u64);
+
+impl Default for Dither {
+	fn default() -> Self {
+		Self(0x2545_f491_4f6c_dd1d)
+	}
+}
+
+impl Dither {
+	/// Returns a uniformly distributed random number in [0, 1), using a xorshift generator.
+	#[allow(clippy::cast_precision_loss)]
+	fn uniform(&mut self) -> f64 {
+		self.0 ^= self.0 << 13;
+		self.0 ^= self.0 >> 7;
+		self.0 ^= self.0 << 17;
+		(self.0 >> 11) as f64 / (1u64 << 53) as f64
+	}
+
+	/// Returns the next noise sample.
+	fn sample(&mut self) -> f64 {
+		self.uniform() - self.uniform()
+	}
+}
+
+/// Reads the samples of the given file in the given audio format and converts them to 16-bit mono with the given
+/// options. Note that there is no sample rate conversion.
+///
+/// # Errors
+/// Any errors from the audio support libraries are passed on, as well as some custom errors.
+pub fn read_audio_for_brr(
+	file: File,
+	format: AudioFormat,
+	options: &ConversionOptions,
+) -> Result<Vec<DecodedSample>, SharedStr> {
+	read_audio_for_brr_with_rate(file, format, options).map(|(samples, _)| samples)
+}
+
+/// Like [`read_audio_for_brr`], but also returns the sample rate of the file in Hz.
+///
+/// # Errors
+/// Any errors from the audio support libraries are passed on, as well as some custom errors.
+pub fn read_audio_for_brr_with_rate(
+	file: File,
+	format: AudioFormat,
+	options: &ConversionOptions,
+) -> Result<(Vec<DecodedSample>, u32), SharedStr> {
+	let audio = match format {
+		AudioFormat::Wav => read_wav(file),
+		AudioFormat::Aiff => read_aiff(file),
+		AudioFormat::Flac => read_flac(file),
+		AudioFormat::Raw => read_raw(file),
+	}?;
+	audio.convert(options).map(|samples| (samples, audio.sample_rate))
+}
+
+/// Treats the given file as a WAV file, reads its samples and converts them to 16-bit mono. Note that there currently
+/// is no sample rate conversion.
+///
+/// # Errors
+/// Any errors from the WAV support library are passed on, as well as some custom errors.
+pub fn read_wav_for_brr(file: File) -> Result<Vec<DecodedSample>, SharedStr> {
+	read_audio_for_brr(file, AudioFormat::Wav, &ConversionOptions::default())
+}
+
+/// Like [`read_wav_for_brr`], but also returns the sample rate of the file in Hz.
+///
+/// # Errors
+/// Any errors from the WAV support library are passed on, as well as some custom errors.
+pub fn read_wav_for_brr_with_rate(file: File) -> Result<(Vec<DecodedSample>, u32), SharedStr> {
+	read_audio_for_brr_with_rate(file, AudioFormat::Wav, &ConversionOptions::default())
+}
+
+/// Treats the given file as a WAV file and reads its samples.
+fn read_wav(file: File) -> Result<Audio, SharedStr> {
+	let mut reader = WavReader::new(BufReader::new(file)).map_err(|err| SharedStr::from(err.to_string()))?;
+	let spec = reader.spec();
+	let samples = match (spec.sample_format, spec.bits_per_sample) {
+		(SampleFormat::Float, 32) => reader
+			.samples::<f32>()
+			.map(|sample| sample.map(|sample| f64::from(sample * f32::from(i16::MAX))))
+			.try_collect(),
+		// The order of operations is very important! If we don't bracket the scaling constant at the end, we will
+		// overflow the first value into oblivion.
+		(SampleFormat::Int, 8) => reader
+			.samples::<i8>()
+			.map(|sample| {
+				sample.map(|sample| {
+					f64::from((i16::from(sample) - i16::from(u8::MAX / 2)) * (i16::MAX / i16::from(u8::MAX)))
+				})
+			})
+			.try_collect(),
+		(SampleFormat::Int, 16) => reader.samples::<i16>().map(|sample| sample.map(f64::from)).try_collect(),
+		// The problem is that the i16max / i24max is less than 1, so cannot be calculated in integer math.
+		(SampleFormat::Int, 24) => reader
+			.samples::<i32>()
+			.map(|sample| sample.map(|sample| f64::from(sample) / i24max * f64::from(i16::MAX)))
+			.try_collect(),
+		(SampleFormat::Int, bits_per_sample @ 9 ..= 32) => reader
+			.samples::<i32>()
+			.map(|sample| sample.map(|sample| f64::from(sample) / 2f64.powi(i32::from(bits_per_sample) - 16)))
+			.try_collect(),
+		_ => Err(hound::Error::FormatError("Unsupported bit depth")),
+	}
+	.map_err(|err| SharedStr::from(err.to_string()))?;
+	Ok(Audio { samples, reduce: truncate_sample, channels: spec.channels, sample_rate: spec.sample_rate })
+}
+
+/// Treats the given file as an AIFF or AIFF-C file and reads its samples.
+fn read_aiff(file: File) -> Result<Audio, SharedStr> {
+	let mut data = Vec::new();
+	BufReader::new(file).read_to_end(&mut data).map_err(|err| err.to_string())?;
+	let (is_aiff_c, mut chunks) = match data.as_slice() {
+		[b'F', b'O', b'R', b'M', _, _, _, _, b'A', b'I', b'F', b'F', chunks @ ..] => (false, chunks),
+		[b'F', b'O', b'R', b'M', _, _, _, _, b'A', b'I', b'F', b'C', chunks @ ..] => (true, chunks),
+		_ => return Err("Ill-formed AIFF file: no FORM chunk of type AIFF or AIFC found".into()),
+	};
+
+	let mut common = None;
+	let mut sound_data = None;
+	while let Some((header, rest)) = chunks.split_first_chunk::<8>() {
+		let size = u32::from_be_bytes(header[4 ..].try_into().unwrap()) as usize;
+		let body = rest.get(.. size).ok_or("Ill-formed AIFF file: chunk is cut off")?;
+		match &header[.. 4] {
+			b"COMM" => common = Some(body),
+			b"SSND" => sound_data = Some(body),
+			_ => {},
+		}
+		// Chunks are padded to an even size.
+		chunks = rest.get(size + size % 2 ..).unwrap_or_default();
+	}
+	let common = common.filter(|common| common.len() >= 18).ok_or("Ill-formed AIFF file: no valid COMM chunk found")?;
+	let sound_data = sound_data.ok_or("Ill-formed AIFF file: no SSND chunk found")?;
+
+	let channels = u16::from_be_bytes([common[0], common[1]]);
+	let frame_count = u32::from_be_bytes(common[2 .. 6].try_into().unwrap()) as usize;
+	let bits_per_sample = u16::from_be_bytes([common[6], common[7]]);
+	let sample_rate = extended_to_f64(common[8 .. 18].try_into().unwrap());
+	let is_little_endian = is_aiff_c
+		&& match common.get(18 .. 22) {
+			Some(b"NONE" | b"twos") => false,
+			Some(b"sowt") => true,
+			Some(compression) =>
+				return Err(
+					format!("Unsupported AIFF-C compression type `{}`", String::from_utf8_lossy(compression)).into()
+				),
+			None => return Err("Ill-formed AIFF-C file: no compression type found".into()),
+		};
+	let bytes_per_sample = usize::from(bits_per_sample.div_ceil(8));
+	if !(1 ..= 4).contains(&bytes_per_sample) {
+		return Err(format!("Unsupported bit depth {bits_per_sample}").into());
+	}
+
+	// The sound data starts with the offset of the samples and a block size, which is only relevant for streaming.
+	let offset = sound_data
+		.first_chunk::<4>()
+		.map(|offset| u32::from_be_bytes(*offset) as usize)
+		.ok_or("Ill-formed AIFF file: SSND chunk is too short")?;
+	let samples = sound_data
+		.get(offset.saturating_add(8) ..)
+		.and_then(|samples| samples.get(.. frame_count.saturating_mul(usize::from(channels) * bytes_per_sample)))
+		.ok_or("Ill-formed AIFF file: sound data is cut off")?;
+	let samples = samples
+		.chunks_exact(bytes_per_sample)
+		.map(|sample| {
+			// Samples are left-justified, so they can be read as 32-bit samples.
+			let mut bytes = [0; 4];
+			bytes[.. bytes_per_sample].copy_from_slice(sample);
+			if is_little_endian {
+				bytes[.. bytes_per_sample].reverse();
+			}
+			f64::from(i32::from_be_bytes(bytes)) / f64::from(1 << 16)
+		})
+		.collect();
+	Ok(Audio { samples, reduce: floor_sample, channels, sample_rate: sample_rate.round() as u32 })
+}
+
+/// Converts an 80-bit extended precision floating-point number, which AIFF uses for the sample rate, to a double.
+#[allow(clippy::cast_precision_loss)]
+fn extended_to_f64(bytes: [u8; 10]) -> f64 {
+	let sign_and_exponent = u16::from_be_bytes([bytes[0], bytes[1]]);
+	let mantissa = u64::from_be_bytes(bytes[2 ..].try_into().unwrap());
+	// The mantissa has an explicit integer bit, so it is a fixed-point number with 63 fractional bits.
+	let value = mantissa as f64 * 2f64.powi(i32::from(sign_and_exponent & 0x7fff) - 16383 - 63);
+	if sign_and_exponent & 0x8000 == 0 {
+		value
+	} else {
+		-value
+	}
+}
+
+/// Treats the given file as a FLAC file and reads its samples.
+fn read_flac(file: File) -> Result<Audio, SharedStr> {
+	let mut reader = FlacReader::new(BufReader::new(file)).map_err(|err| err.to_string())?;
+	let info = reader.streaminfo();
+	let samples = reader
+		.samples()
+		.map(|sample| sample.map(|sample| f64::from(sample) / 2f64.powi(info.bits_per_sample as i32 - 16)))
+		.try_collect()
+		.map_err(|err| err.to_string())?;
+	Ok(Audio { samples, reduce: floor_sample, channels: info.channels as u16, sample_rate: info.sample_rate })
+}
+
+/// Treats the given file as headerless mono 16-bit signed little-endian PCM data at the DSP sample rate and reads its
+/// samples.
+fn read_raw(file: File) -> Result<Audio, SharedStr> {
+	let mut data = Vec::new();
+	BufReader::new(file).read_to_end(&mut data).map_err(|err| err.to_string())?;
+	let (samples, remainder) = data.as_chunks::<2>();
+	if !remainder.is_empty() {
+		return Err("Raw PCM data has an odd number of bytes, but every 16-bit sample takes two bytes".into());
+	}
+	let samples = samples.iter().map(|sample| f64::from(i16::from_le_bytes(*sample))).collect();
+	Ok(Audio { samples, reduce: truncate_sample, channels: 1, sample_rate: DSP_SAMPLE_RATE })
+}
+
+/// Writes the given samples as a mono 16-bit WAV file with the sample rate of the DSP.
+///
+/// If a loop region is given, it is stored in a `smpl` chunk, which samplers and many audio editors use for loop
+/// points.
+///
+/// # Errors
+/// Any errors from the WAV support library or from writing the output are passed on.
+pub fn write_wav_for_brr(
+	output: &mut impl Write,
+	samples: &[DecodedSample],
+	loop_region: Option<Range<usize>>,
+) -> Result<(), SharedStr> {
+	let spec = WavSpec {
+		channels:        1,
+		sample_rate:     DSP_SAMPLE_RATE,
+		bits_per_sample: 16,
+		sample_format:   SampleFormat::Int,
+	};
+	let mut wav_data = Cursor::new(Vec::new());
+	let mut writer = WavWriter::new(&mut wav_data, spec).map_err(|err| SharedStr::from(err.to_string()))?;
+	for sample in samples {
+		writer.write_sample(*sample).map_err(|err| SharedStr::from(err.to_string()))?;
+	}
+	writer.finalize().map_err(|err| SharedStr::from(err.to_string()))?;
+	let mut wav_data = wav_data.into_inner();
+
+	if let Some(loop_region) = loop_region.filter(|region| !region.is_empty()) {
+		wav_data.extend_from_slice(&sample_chunk(&loop_region));
+		// The RIFF chunk size at offset 4 covers everything after the size field itself.
+		let riff_size = u32::try_from(wav_data.len() - 8).map_err(|_| shared_str!("WAV file is too large"))?;
+		wav_data[4 .. 8].copy_from_slice(&riff_size.to_le_bytes());
+	}
+	output.write_all(&wav_data).map_err(|err| err.to_string().into())
+}
+
+/// Creates a `smpl` chunk with a single forward loop over the given sample range.
+#[allow(clippy::cast_possible_truncation)]
+fn sample_chunk(loop_region: &Range<usize>) -> Vec<u8> {
+	/// MIDI note that plays the sample at its original pitch; this is middle C.
+	const unity_note: u32 = 60;
+	/// Duration of one sample in nanoseconds.
+	const sample_period: u32 = 1_000_000_000 / DSP_SAMPLE_RATE;
+
+	// Manufacturer, product, sample period, MIDI unity note, MIDI pitch fraction, SMPTE format, SMPTE offset, number
+	// of loops, sampler data size; then the single loop's cue point ID, type (forward), start, end (inclusive),
+	// fraction and play count (infinite).
+	let fields = [
+		0,
+		0,
+		sample_period,
+		unity_note,
+		0,
+		0,
+		0,
+		1,
+		0,
+		0,
+		0,
+		loop_region.start as u32,
+		(loop_region.end - 1) as u32,
+		0,
+		0,
+	];
+	let mut chunk = Vec::with_capacity(8 + fields.len() * 4);
+	chunk.extend_from_slice(b"smpl");
+	chunk.extend_from_slice(&(fields.len() as u32 * 4).to_le_bytes());
+	for field in fields {
+		chunk.extend_from_slice(&field.to_le_bytes());
+	}
+	chunk
+}
@@ -1,6 +1,7 @@
 //! Command-line interface related structures.
 
 use std::mem::Discriminant;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
 
@@ -13,6 +14,7 @@ use miette::{Diagnostic, Severity};
 use parking_lot::RwLock;
 
 use crate::error::{AssemblyError, ErrorCodes};
+use crate::sema::instruction::MemoryAddress;
 
 /// Interface between the assembler backend and any kind of frontend.
 ///
@@ -34,6 +36,23 @@ pub trait Frontend: std::fmt::Debug + Send + Sync {
 	/// Returns the maximum number of reference resolution passes.
 	fn maximum_reference_resolution_passes(&self) -> usize;
 
+	/// Returns the global symbols that are defined before assembly starts, such as via `-D` on the command line.
+	/// These take precedence over any definition of the same symbol in the source code.
+	fn predefined_symbols(&self) -> Vec<(SharedStr, MemoryAddress)>;
+	/// Returns the directories that are searched for included files (source code, binary and audio files) if they
+	/// can't be found relative to the including file.
+	fn include_directories(&self) -> Vec<PathBuf>;
+	/// Returns whether relative branches with out of range targets are relaxed into longer instruction sequences.
+	fn relax_branches(&self) -> bool;
+	/// Returns whether Asar compatibility mode is enabled, which accepts additional Asar syntax and directives.
+	fn asar_compatibility(&self) -> bool;
+	/// Returns whether unused labels, unused macros and unreachable code are reported as warnings.
+	fn warn_unused(&self) -> bool;
+
+	/// Signals to the frontend that the assembler read the given file, be it source code, binary data or audio. The
+	/// same file may be reported multiple times.
+	fn report_file_dependency(&self, file: &Path);
+
 	/// Not for public use; this function forces the frontend to receive a diagnostic no matter what its ignore status
 	/// is.
 	fn report_diagnostic_impl(&self, diagnostic: AssemblyError);
@@ -84,8 +103,49 @@ pub struct CliOptions {
 	#[arg(long, short = 'r', default_value = "1000")]
 	pub(crate) macro_recursion_limit: usize,
 
+	/// Predefine a global symbol as NAME=VALUE, or as NAME to give it the value 1.
+	#[arg(num_args = 1, action = clap::ArgAction::Append, long, short = 'D')]
+	pub(crate) define: Vec<SymbolDefinition>,
+
+	/// Add a directory to search for included files in.
+	///
+	/// Files included with `include`, `incbin` or `brr` are first searched relative to the including file, and then
+	/// in all include directories in the order they are given.
+	#[arg(num_args = 1, action = clap::ArgAction::Append, long, short = 'I')]
+	pub(crate) include_directory: Vec<PathBuf>,
+
+	/// Relax branches whose target is out of range into longer instruction sequences.
+	///
+	/// Conditional branches are inverted to skip over a `jmp` to the target, `bra` is replaced by `jmp`, and `cbne`,
+	/// `dbnz`, `bbs` and `bbc` branch to a `jmp` to the target, which is skipped by a `bra` otherwise.
+	#[arg(long)]
+	pub(crate) relax_branches: bool,
+
+	/// Enable Asar compatibility mode.
+	///
+	/// This accepts additional Asar syntax: `math pri`, `!define` text substitution and the `skip`, `warnpc`, `print`,
+	/// `assert`, `spcblock`, `endspcblock`, `table` and `cleartable` directives.
+	#[arg(long)]
+	pub(crate) asar: bool,
+
+	/// Warn about unused labels, unused macros and unreachable code.
+	///
+	/// These warnings are off by default, since unused labels and code after jumps are common in hand-written
+	/// assembly.
+	#[arg(long)]
+	pub(crate) warn_unused: bool,
+
+	/// Format of the diagnostics (errors and warnings) that spcasm prints.
+	#[arg(long, default_value = "human")]
+	pub(crate) message_format: MessageFormat,
+
 	#[clap(skip = RwLock::new(false))]
-	pub(crate) had_error: RwLock<bool>,
+	pub(crate) had_error:     RwLock<bool>,
+	#[clap(skip)]
+	pub(crate) dependencies:  RwLock<Vec<PathBuf>>,
+	/// Diagnostics collected for SARIF output, which is printed as one document at the end.
+	#[clap(skip)]
+	pub(crate) sarif_results: RwLock<Vec<serde_json::Value>>,
 }
 
 #[cfg(feature = "binaries")]
@@ -101,6 +161,15 @@ impl CliOptions {
 			}
 		}
 	}
+
+	/// Prints all diagnostics that are held back until the end of assembly. This is only the case for SARIF output,
+	/// where all diagnostics are part of one document.
+	pub fn finish_diagnostics(&self) {
+		if self.message_format == MessageFormat::Sarif {
+			let results = std::mem::take(&mut *self.sarif_results.write());
+			println!("{}", crate::diagnostic_output::sarif_log(&results));
+		}
+	}
 }
 
 #[cfg(feature = "binaries")]
@@ -126,11 +195,46 @@ impl Frontend for CliOptions {
 		self.macro_recursion_limit
 	}
 
+	fn predefined_symbols(&self) -> Vec<(SharedStr, MemoryAddress)> {
+		self.define.iter().map(|definition| (definition.name.clone(), definition.value)).collect()
+	}
+
+	fn include_directories(&self) -> Vec<PathBuf> {
+		self.include_directory.clone()
+	}
+
+	fn relax_branches(&self) -> bool {
+		self.relax_branches
+	}
+
+	fn asar_compatibility(&self) -> bool {
+		self.asar
+	}
+
+	fn warn_unused(&self) -> bool {
+		self.warn_unused
+	}
+
+	fn report_file_dependency(&self, file: &Path) {
+		let mut dependencies = self.dependencies.write();
+		if !dependencies.iter().any(|dependency| dependency == file) {
+			dependencies.push(file.to_owned());
+		}
+	}
+
 	fn report_diagnostic_impl(&self, diagnostic: AssemblyError) {
-		if self.is_error(&diagnostic) {
+		let is_error = self.is_error(&diagnostic);
+		if is_error {
 			*self.had_error.write() = true;
 		}
-		println!("{:?}", miette::Report::new(diagnostic));
+		match self.message_format {
+			MessageFormat::Human => println!("{:?}", miette::Report::new(diagnostic)),
+			MessageFormat::Json => println!("{}", crate::diagnostic_output::diagnostic_to_json(&diagnostic)),
+			MessageFormat::Sarif => self
+				.sarif_results
+				.write()
+				.push(crate::diagnostic_output::diagnostic_to_sarif_result(&diagnostic, is_error)),
+		}
 	}
 }
 
@@ -155,6 +259,30 @@ impl Frontend for DummyOptions {
 		1000
 	}
 
+	fn predefined_symbols(&self) -> Vec<(SharedStr, MemoryAddress)> {
+		Vec::new()
+	}
+
+	fn include_directories(&self) -> Vec<PathBuf> {
+		Vec::new()
+	}
+
+	fn relax_branches(&self) -> bool {
+		false
+	}
+
+	fn asar_compatibility(&self) -> bool {
+		false
+	}
+
+	fn warn_unused(&self) -> bool {
+		false
+	}
+
+	fn report_file_dependency(&self, _file: &Path) {
+		// noop
+	}
+
 	fn report_diagnostic_impl(&self, _diagnostic: AssemblyError) {
 		// noop
 	}
@@ -193,6 +321,46 @@ impl FromStr for ErrorCodeSpec {
 	}
 }
 
+/// A symbol definition given on the command line, in the form `NAME` or `NAME=VALUE`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg(feature = "binaries")]
+pub(crate) struct SymbolDefinition {
+	name:  SharedStr,
+	value: MemoryAddress,
+}
+
+#[cfg(feature = "binaries")]
+impl FromStr for SymbolDefinition {
+	type Err = std::string::String;
+
+	fn from_str(definition: &str) -> Result<Self, Self::Err> {
+		let (name, value) = definition.split_once('=').unwrap_or((definition, "1"));
+		let name = name.trim();
+		if name.is_empty()
+			|| name.starts_with(|chr: char| chr.is_ascii_digit())
+			|| !name.chars().all(|chr| chr.is_alphanumeric() || chr == '_')
+		{
+			return Err(format!("'{name}' is not a valid symbol name"));
+		}
+
+		let value = value.trim();
+		let (is_negative, magnitude) =
+			value.strip_prefix('-').map_or((false, value), |magnitude| (true, magnitude.trim_start()));
+		if magnitude.starts_with(['-', '+']) {
+			return Err(format!("invalid value '{value}' for symbol '{name}'"));
+		}
+		let (digits, radix) = [("$", 16), ("0x", 16), ("%", 2), ("0b", 2)]
+			.into_iter()
+			.find_map(|(prefix, radix)| magnitude.strip_prefix(prefix).map(|digits| (digits, radix)))
+			.unwrap_or((magnitude, 10));
+		let magnitude = MemoryAddress::from_str_radix(digits, radix)
+			.map_err(|error| format!("invalid value '{value}' for symbol '{name}': {error}"))?;
+		let value = if is_negative { -magnitude } else { magnitude };
+
+		Ok(Self { name: name.into(), value })
+	}
+}
+
 #[cfg(feature = "binaries")]
 mod clap_dependent {
 	use std::path::PathBuf;
@@ -219,6 +387,25 @@ mod clap_dependent {
 		/// Format to output to.
 		#[arg(default_value = "elf", long, short = 'f')]
 		pub output_format: OutputFormat,
+		/// Patch the assembled data into this existing memory image instead of creating a new one.
+		///
+		/// The image may be a raw memory dump, an ELF file or a .spc file, and the output file has the same format as
+		/// the image, regardless of the output format option. Only the hex dump output format is still respected, and
+		/// dumps the patched file. A warning is printed for every part of the image that the patch changes.
+		#[arg(long, value_name = "IMAGE")]
+		pub patch:         Option<PathBuf>,
+		/// Write a Makefile-style dependency file to this path, listing all files that were read during assembly.
+		///
+		/// The target of the dependency rule is the output file, or the input file if there is no output file. This is
+		/// equivalent to the `-MD` option of GCC and can be used by build systems like make or ninja.
+		#[arg(long)]
+		pub depfile:       Option<PathBuf>,
+		/// Keep running and reassemble whenever the input file or any file it depends on changes.
+		///
+		/// Files that didn't change are not parsed again. Diagnostics are printed for every reassembly, and the output
+		/// files are only written if there were no errors.
+		#[arg(long)]
+		pub watch:         bool,
 
 		/// Dump all references and their final values / locations.
 		#[arg(long, short = 'd')]
@@ -242,6 +429,27 @@ mod clap_dependent {
 		Plain,
 		/// Dump hexadecimal representation in a pretty format like in a hex editor.
 		HexDump,
+		/// Output the binary upload stream for the boot ROM upload routine.
+		Upload,
+		/// Output the upload stream as an Asar source file.
+		UploadAsar,
+		/// Output the upload stream as a ca65 source file.
+		UploadCa65,
+		/// Output the upload stream as a WLA-DX source file.
+		UploadWla,
+	}
+
+	/// Format of the diagnostics printed by spcasm; see [`CliOptions`].
+	#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+	#[repr(u8)]
+	pub enum MessageFormat {
+		/// Human-readable diagnostics with source code excerpts.
+		#[default]
+		Human,
+		/// One JSON object per diagnostic, each on its own line.
+		Json,
+		/// A single SARIF log containing all diagnostics, printed after assembly.
+		Sarif,
 	}
 }
 
@@ -0,0 +1,179 @@
+//! Machine-readable diagnostic output in the JSON and SARIF formats.
+
+use std::path::{Component, Path};
+
+#[allow(unused)]
+use flexstr::{shared_str, IntoSharedStr, SharedStr, ToSharedStr};
+use miette::{Diagnostic, LabeledSpan, Severity, SourceSpan};
+use serde_json::{json, Value};
+
+use crate::{buildinfo, AssemblyError};
+
+/// A position in the source code, with 1-based line and column numbers.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Position {
+	line:   usize,
+	column: usize,
+}
+
+impl Position {
+	/// Computes the position of the given offset within the source code of the diagnostic.
+	fn of_offset(diagnostic: &AssemblyError, offset: usize) -> Option<Self> {
+		let contents = diagnostic.source_code()?.read_span(&SourceSpan::from((offset, 0)), 0, 0).ok()?;
+		Some(Self { line: contents.line() + 1, column: contents.column() + 1 })
+	}
+
+	fn to_json(self) -> Value {
+		json!({ "line": self.line, "column": self.column })
+	}
+}
+
+/// Returns the pretty-printed name of the file the diagnostic occurred in, if it has a file.
+fn file_name(diagnostic: &AssemblyError) -> Option<String> {
+	let contents = diagnostic.source_code()?.read_span(&SourceSpan::from((0, 0)), 0, 0).ok()?;
+	contents.name().map(ToOwned::to_owned)
+}
+
+/// Converts a file name to a SARIF artifact location. Absolute paths become `file` URIs, while relative paths become
+/// relative references to the source root, which SARIF calls `%SRCROOT%`. In both cases, all characters that are not
+/// allowed in a URI path are percent-encoded.
+fn artifact_location(file: &str) -> Value {
+	let path = Path::new(file);
+	let encoded_path = path
+		.components()
+		.filter_map(|component| match component {
+			Component::Normal(part) => Some(percent_encode(&part.to_string_lossy())),
+			Component::ParentDir => Some("..".to_owned()),
+			// Windows drive letters are kept as they are, e.g. `file:///C:/`.
+			Component::Prefix(prefix) => Some(prefix.as_os_str().to_string_lossy().into_owned()),
+			Component::RootDir | Component::CurDir => None,
+		})
+		.collect::<Vec<_>>()
+		.join("/");
+	if path.is_absolute() {
+		json!({ "uri": format!("file:///{encoded_path}") })
+	} else {
+		json!({ "uri": encoded_path, "uriBaseId": "%SRCROOT%" })
+	}
+}
+
+/// Percent-encodes all bytes of the text except for the unreserved characters of RFC 3986.
+fn percent_encode(text: &str) -> String {
+	text.bytes()
+		.map(|byte| {
+			if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+				char::from(byte).to_string()
+			} else {
+				format!("%{byte:02X}")
+			}
+		})
+		.collect()
+}
+
+fn labels(diagnostic: &AssemblyError) -> Vec<LabeledSpan> {
+	diagnostic.labels().map(Iterator::collect).unwrap_or_default()
+}
+
+fn code(diagnostic: &AssemblyError) -> Option<String> {
+	diagnostic.code().map(|code| code.to_string())
+}
+
+fn help(diagnostic: &AssemblyError) -> Option<String> {
+	diagnostic.help().map(|help| help.to_string())
+}
+
+/// Converts a diagnostic to a self-contained JSON object.
+///
+/// Apart from the error code, severity, message and help text, the object contains the file name and all labels of
+/// the diagnostic. Each label has its byte offset and length as well as its start and end line and column.
+pub fn diagnostic_to_json(diagnostic: &AssemblyError) -> Value {
+	let labels = labels(diagnostic)
+		.into_iter()
+		.map(|label| {
+			json!({
+				"message": label.label(),
+				"offset": label.offset(),
+				"length": label.len(),
+				"start": Position::of_offset(diagnostic, label.offset()).map(Position::to_json),
+				"end": Position::of_offset(diagnostic, label.offset() + label.len()).map(Position::to_json),
+			})
+		})
+		.collect::<Vec<_>>();
+
+	json!({
+		"code": code(diagnostic),
+		"severity": match diagnostic.severity().unwrap_or(Severity::Error) {
+			Severity::Advice => "advice",
+			Severity::Warning => "warning",
+			Severity::Error => "error",
+		},
+		"message": diagnostic.to_string(),
+		"file": file_name(diagnostic),
+		"labels": labels,
+		"help": help(diagnostic),
+	})
+}
+
+/// Converts a diagnostic to a SARIF result object. The first label of the diagnostic becomes the result's location,
+/// all other labels become related locations. Diagnostics that stop the assembler, including warnings that were turned
+/// into errors, have the `error` level.
+pub fn diagnostic_to_sarif_result(diagnostic: &AssemblyError, is_error: bool) -> Value {
+	let artifact_location = file_name(diagnostic).map(|file| artifact_location(&file));
+	let mut locations = labels(diagnostic)
+		.into_iter()
+		.map(|label| {
+			let mut region = json!({ "byteOffset": label.offset(), "byteLength": label.len() });
+			if let Some(start) = Position::of_offset(diagnostic, label.offset())
+				&& let Some(end) = Position::of_offset(diagnostic, label.offset() + label.len())
+			{
+				region["startLine"] = start.line.into();
+				region["startColumn"] = start.column.into();
+				region["endLine"] = end.line.into();
+				region["endColumn"] = end.column.into();
+			}
+			let mut location = json!({
+				"physicalLocation": {
+					"artifactLocation": artifact_location,
+					"region": region,
+				},
+			});
+			if let Some(text) = label.label() {
+				location["message"] = json!({ "text": text });
+			}
+			location
+		})
+		.collect::<Vec<_>>();
+	let related_locations = if locations.is_empty() { Vec::new() } else { locations.split_off(1) };
+
+	let message = help(diagnostic).map_or_else(|| diagnostic.to_string(), |help| format!("{diagnostic}\nhelp: {help}"));
+	json!({
+		"ruleId": code(diagnostic),
+		"level": match diagnostic.severity().unwrap_or(Severity::Error) {
+			_ if is_error => "error",
+			Severity::Advice => "note",
+			Severity::Warning => "warning",
+			Severity::Error => "error",
+		},
+		"message": { "text": message },
+		"locations": locations,
+		"relatedLocations": related_locations,
+	})
+}
+
+/// Creates a complete SARIF log for one spcasm run from the given results.
+pub fn sarif_log(results: &[Value]) -> Value {
+	json!({
+		"$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+		"version": "2.1.0",
+		"runs": [{
+			"tool": {
+				"driver": {
+					"name": "spcasm",
+					"version": buildinfo::PKG_VERSION,
+					"informationUri": "https://spcasm.filmroellchen.eu/",
+				},
+			},
+			"results": results,
+		}],
+	})
+}
@@ -15,11 +15,12 @@ use num_traits::{FromPrimitive, ToPrimitive};
 use parking_lot::RwLock;
 use spcasm_derive::Parse;
 
+use crate::brr::wav::{ConversionOptions, Downmix, Normalization};
 use crate::parser::source_range;
 use crate::sema::instruction::MemoryAddress;
 use crate::sema::reference::{Label, MacroParent, Reference, ReferenceResolvable};
 use crate::sema::value::{Size, SizedAssemblyTimeValue};
-use crate::sema::{self, AssemblyTimeValue, ProgramElement};
+use crate::sema::{self, AssemblyTimeValue, CharmapChange, ProgramElement};
 use crate::{byte_vec_to_string, span_to_string, AssemblyCode, AssemblyError, Segments};
 
 /// An assembly directive, often confusingly referred to as a "macro".
@@ -53,6 +54,21 @@ impl Directive {
 				segments.new_segment(*address);
 				Ok(())
 			},
+			// An SPC block behaves like `pushpc` followed by `org`, except that it may also start the very first
+			// segment.
+			DirectiveValue::StartSpcBlock(address) =>
+				try {
+					if segments.current_segment_start.is_some() {
+						segments.push_segment()?;
+					}
+					segments.new_segment(*address);
+				},
+			DirectiveValue::EndSpcBlock { .. } =>
+				if segments.segment_stack.is_empty() {
+					Ok(())
+				} else {
+					segments.pop_segment()
+				},
 			DirectiveValue::Brr { directory: true, .. } => {
 				segments.sample_table.add_sample(AssemblyTimeValue::Reference(
 					current_label
@@ -88,7 +104,7 @@ impl Directive {
 					});
 				},
 			// For zero-sized strings, we would lose the preceding labels if we didn't remove the string here.
-			DirectiveValue::String { text, has_null_terminator: false } if text.is_empty() =>
+			DirectiveValue::String { text, has_null_terminator: false, .. } if text.is_empty() =>
 				try {
 					self.value = DirectiveValue::Placeholder;
 				},
@@ -162,6 +178,10 @@ pub enum DirectiveSymbol {
 	Word,
 	Dl,
 	Dd,
+	Dwbe,
+	Dlbe,
+	Ddbe,
+	Dwsplit,
 	Ascii,
 	Asciiz,
 	Brr,
@@ -176,6 +196,8 @@ pub enum DirectiveSymbol {
 	Macro,
 	EndMacro,
 	If,
+	Ifdef,
+	Ifndef,
 	Else,
 	ElseIf,
 	EndIf,
@@ -192,6 +214,43 @@ pub enum DirectiveSymbol {
 	PadDWord,
 	Startpos,
 	Namespace,
+	Dpage,
+	Base,
+	Charmap,
+	Skip,
+	Warnpc,
+	Print,
+	Assert,
+	Spcblock,
+	Endspcblock,
+	Table,
+	Cleartable,
+}
+
+impl DirectiveSymbol {
+	/// Returns whether this directive is only recognized in Asar compatibility mode. These directives have names that
+	/// are commonly used as labels, so they remain identifiers outside of Asar compatibility mode.
+	#[must_use]
+	pub const fn is_asar_only(self) -> bool {
+		matches!(
+			self,
+			Self::Skip
+				| Self::Warnpc
+				| Self::Print
+				| Self::Assert
+				| Self::Spcblock
+				| Self::Endspcblock
+				| Self::Table
+				| Self::Cleartable
+		)
+	}
+
+	/// Returns whether this directive is not recognized in Asar compatibility mode. Asar doesn't have these directives,
+	/// so code written for Asar may use their names as labels.
+	#[must_use]
+	pub const fn is_asar_incompatible(self) -> bool {
+		matches!(self, Self::Charmap)
+	}
 }
 
 impl Display for DirectiveSymbol {
@@ -202,6 +261,10 @@ impl Display for DirectiveSymbol {
 			Self::Dw | Self::Word => "dw",
 			Self::Dl => "dl",
 			Self::Dd => "dd",
+			Self::Dwbe => "dwbe",
+			Self::Dlbe => "dlbe",
+			Self::Ddbe => "ddbe",
+			Self::Dwsplit => "dwsplit",
 			Self::Ascii => "ascii",
 			Self::Asciiz => "asciiz",
 			Self::Brr => "brr",
@@ -215,6 +278,8 @@ impl Display for DirectiveSymbol {
 			Self::Macro => "macro",
 			Self::EndMacro => "endmacro",
 			Self::If => "if",
+			Self::Ifdef => "ifdef",
+			Self::Ifndef => "ifndef",
 			Self::Else => "else",
 			Self::ElseIf => "elseif",
 			Self::EndIf => "endif",
@@ -231,6 +296,17 @@ impl Display for DirectiveSymbol {
 			Self::PadDWord => "paddword",
 			Self::Startpos => "startpos",
 			Self::Namespace => "namespace",
+			Self::Dpage => "dpage",
+			Self::Base => "base",
+			Self::Charmap => "charmap",
+			Self::Skip => "skip",
+			Self::Warnpc => "warnpc",
+			Self::Print => "print",
+			Self::Assert => "assert",
+			Self::Spcblock => "spcblock",
+			Self::Endspcblock => "endspcblock",
+			Self::Table => "table",
+			Self::Cleartable => "cleartable",
 		})
 	}
 }
@@ -246,17 +322,21 @@ pub enum DirectiveValue {
 	Table {
 		/// The entries of the table. For simple directives like "dw $0A", this only has one entry.
 		values: Vec<SizedAssemblyTimeValue>,
+		/// How the bytes of the entries are arranged in memory.
+		layout: TableLayout,
 	},
 	/// `brr <file name>`
 	Brr {
 		/// Path to the WAV source file.
-		file:      SharedStr,
+		file:       SharedStr,
 		/// The range of samples to include.
-		range:     Option<SourceSpan>,
+		range:      Option<SourceSpan>,
 		/// Whether to automatically trim silence at the beginning and end of the sample (after cutting the range)
-		auto_trim: bool,
+		auto_trim:  bool,
 		/// Whether to add the sample to the sample directory (not currently implemented)
-		directory: bool,
+		directory:  bool,
+		/// How to convert the audio to 16-bit mono before encoding.
+		conversion: ConversionOptions,
 	},
 	/// `sampletable`
 	SampleTable {
@@ -264,7 +344,16 @@ pub enum DirectiveValue {
 		auto_align: bool,
 	},
 	/// `ascii(z) <string>`
-	String { text: Vec<u8>, has_null_terminator: bool },
+	String {
+		/// The text as written in the source code.
+		text:                SharedStr,
+		/// The encoded text. Until the character map is applied, this is the text encoded without a character map.
+		encoded:             Vec<u8>,
+		/// Whether a null byte is appended, as with `asciiz`.
+		has_null_terminator: bool,
+	},
+	/// `charmap`, `table`, `cleartable`
+	Charmap(CharmapChange),
 	/// `<reference> = <value>`
 	AssignReference { reference: Reference, value: AssemblyTimeValue },
 	/// `incbin <file name>`
@@ -298,12 +387,121 @@ pub enum DirectiveValue {
 		/// The block that is assembled if the condition is falsy.
 		false_block: Vec<ProgramElement>,
 	},
+	/// `ifdef`, `ifndef`
+	DefinitionConditional {
+		/// The symbol whose definition decides which of the two blocks is assembled.
+		symbol:      Reference,
+		/// Whether the true block is assembled if the symbol is *not* defined, i.e. this is an `ifndef`.
+		negated:     bool,
+		/// The block that is assembled if the condition holds.
+		true_block:  Vec<ProgramElement>,
+		/// The block that is assembled if the condition does not hold.
+		false_block: Vec<ProgramElement>,
+	},
 	/// `startpos`
 	Startpos,
 	/// `namespace`
 	StartNamespace { name: SharedStr },
 	/// `namespace off`
 	EndNamespace,
+	/// `dpage`
+	DirectPage {
+		/// The page that is selected as the direct page by the P flag from here on, either 0 or 1.
+		page: u8,
+	},
+	/// `base`
+	Base {
+		/// The logical address that the following code is assembled for, or `None` for `base off`, which makes the
+		/// logical address equal to the physical address again.
+		logical_address: Option<MemoryAddress>,
+	},
+	/// `skip`
+	Skip {
+		/// The number of bytes to advance the current address by, without writing any data.
+		amount: AssemblyTimeValue,
+	},
+	/// `warnpc`
+	WarnPc {
+		/// The address which the current address must not exceed.
+		limit: AssemblyTimeValue,
+	},
+	/// `print`
+	Print {
+		/// The parts of the printed message, which are concatenated.
+		parts: Vec<PrintPart>,
+	},
+	/// `assert`
+	Assert {
+		/// The condition that must be truthy.
+		condition: AssemblyTimeValue,
+		/// The message reported if the condition is falsy.
+		message:   Option<SharedStr>,
+	},
+	/// `spcblock`
+	StartSpcBlock(MemoryAddress),
+	/// `endspcblock`
+	EndSpcBlock {
+		/// The address where execution starts, given with `execute`.
+		execute: Option<AssemblyTimeValue>,
+	},
+}
+
+/// How the bytes of the entries of a table directive are arranged in memory.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum TableLayout {
+	/// Every entry is stored with its lowest byte first, as with `dw`.
+	#[default]
+	LittleEndian,
+	/// Every entry is stored with its highest byte first, as with `dwbe`.
+	BigEndian,
+	/// The entries are split into one array per byte, as with `dwsplit`: First the lowest byte of every entry, then
+	/// the second-lowest byte of every entry, and so on.
+	Split,
+}
+
+impl Display for TableLayout {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.pad(match self {
+			Self::LittleEndian => "little endian",
+			Self::BigEndian => "big endian",
+			Self::Split => "split",
+		})
+	}
+}
+
+/// One part of the message of a `print` directive.
+#[derive(Clone, Debug)]
+pub enum PrintPart {
+	/// Literal text.
+	Text(SharedStr),
+	/// A value printed in the given radix, either directly or via the `dec`, `hex` and `bin` functions.
+	Value {
+		/// The printed value.
+		value: AssemblyTimeValue,
+		/// The radix of the printed number: 2, 10 or 16.
+		radix: u8,
+	},
+}
+
+impl PrintPart {
+	/// Returns the value of this part, if it has one.
+	pub const fn value_mut(&mut self) -> Option<&mut AssemblyTimeValue> {
+		match self {
+			Self::Text(_) => None,
+			Self::Value { value, .. } => Some(value),
+		}
+	}
+}
+
+impl Display for PrintPart {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Text(text) => write!(f, "{text:?}"),
+			Self::Value { value, radix: 2 } => write!(f, "bin({value})"),
+			Self::Value { value, radix: 16 } => write!(f, "hex({value})"),
+			Self::Value { value, .. } => write!(f, "dec({value})"),
+		}
+	}
 }
 
 /// Expands to a pattern that matches all symbolic directives.
@@ -319,6 +517,8 @@ macro_rules! symbolic_directives {
 			| $crate::directive::DirectiveValue::EndNamespace
 			| $crate::directive::DirectiveValue::AssignReference { .. }
 			| $crate::directive::DirectiveValue::UserDefinedMacro { .. }
+			| $crate::directive::DirectiveValue::StartSpcBlock(_)
+			| $crate::directive::DirectiveValue::Charmap(_)
 	};
 }
 
@@ -346,12 +546,24 @@ impl DirectiveValue {
 			| Self::Placeholder
 			| Self::SetDirectiveParameters { .. }
 			| Self::Startpos
+			| Self::DirectPage { .. }
+			| Self::Base { .. }
+			| Self::WarnPc { .. }
+			| Self::Print { .. }
+			| Self::Assert { .. }
+			| Self::StartSpcBlock(..)
+			| Self::Charmap(..)
+			| Self::EndSpcBlock { .. }
 			| Self::StartNamespace { .. }
 			| Self::EndNamespace
 			| Self::Org(..) => 0,
-			Self::Table { values } =>
+			// Skipped bytes are not part of the segment, but the next segment starts after them.
+			Self::Skip { amount } => amount
+				.value_using_resolver(&|_| None)
+				.unwrap_or_else(|| (Self::LARGE_ASSEMBLED_SIZE).try_into().unwrap()) as usize,
+			Self::Table { values, .. } =>
 				values.len() * values.first().and_then(|value| value.size.to_u8()).unwrap_or(0) as usize,
-			Self::String { text, has_null_terminator } => text.len() + (usize::from(*has_null_terminator)),
+			Self::String { encoded, has_null_terminator, .. } => encoded.len() + (usize::from(*has_null_terminator)),
 			Self::Fill { operation: FillOperation::Amount, parameter, .. } => parameter
 				.value_using_resolver(&|_| None)
 				.unwrap_or_else(|| (Self::LARGE_ASSEMBLED_SIZE).try_into().unwrap())
@@ -368,6 +580,12 @@ impl DirectiveValue {
 						.sum::<usize>()
 						.max(false_block.iter().map(ProgramElement::assembled_size).sum())
 				},
+			// Before definition conditionals are evaluated, either block may be assembled.
+			Self::DefinitionConditional { true_block, false_block, .. } => true_block
+				.iter()
+				.map(ProgramElement::assembled_size)
+				.sum::<usize>()
+				.max(false_block.iter().map(ProgramElement::assembled_size).sum()),
 			// Use a large assembled size as a signal that we don't know at this point. This will force any later
 			// reference out of the direct page, which will always yield correct behavior.
 			Self::Include { .. } | Self::Brr { .. } | Self::SampleTable { .. } | Self::Fill { .. } =>
@@ -382,28 +600,35 @@ impl DirectiveValue {
 }
 
 impl Display for DirectiveValue {
+	#[allow(clippy::too_many_lines)]
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		f.pad(&match self {
 			Self::Placeholder => "[placeholder]".to_owned(),
 			Self::Org(value) => format!("org {value:04X}"),
-			Self::Table { values } => format!(
-				"table {}",
+			Self::Table { values, layout } => format!(
+				"table ({layout}) {}",
 				values.iter().map(ToString::to_string).intersperse(", ".to_string()).collect::<String>()
 			),
-			Self::Brr { file, range, auto_trim, directory } => format!(
-				"brr \"{}\" {}{}{}",
+			Self::Brr { file, range, auto_trim, directory, conversion } => format!(
+				"brr \"{}\" {}{}{}{}",
 				file,
 				range.map(span_to_string).unwrap_or_default(),
 				if *auto_trim { " autotrim" } else { " no autotrim" },
-				if *directory { " directory" } else { " nodirectory" }
+				if *directory { " directory" } else { " nodirectory" },
+				conversion_options_to_string(conversion)
 			),
 			Self::SampleTable { auto_align } =>
 				format!("sampletable{}", if *auto_align { " autoalign" } else { " no autoalign" }),
-			Self::String { text, has_null_terminator } => format!(
+			Self::String { encoded, has_null_terminator, .. } => format!(
 				"ascii \"{:?}{}\"",
-				text.iter().map(|byte| *byte as char).collect::<String>(),
+				encoded.iter().map(|byte| *byte as char).collect::<String>(),
 				if *has_null_terminator { "\\0" } else { "" }
 			),
+			Self::Charmap(CharmapChange::Insert { sequence, values }) => format!(
+				"charmap {sequence:?}, {}",
+				values.iter().map(ToString::to_string).intersperse(", ".to_string()).collect::<String>()
+			),
+			Self::Charmap(CharmapChange::Replace(_)) => "charmap [replace]".to_string(),
 			Self::AssignReference { reference, value } => format!("[reference] {reference} = {value:04X}"),
 			Self::Include { file, range } =>
 				format!("include \"{}\"{}", file, range.map(span_to_string).unwrap_or_default()),
@@ -411,6 +636,24 @@ impl Display for DirectiveValue {
 			Self::PushSection => "push".to_string(),
 			Self::PopSection => "pop".to_string(),
 			Self::Startpos => "startpos".to_string(),
+			Self::DirectPage { page } => format!("dpage {page}"),
+			Self::Base { logical_address: Some(address) } => format!("base {address:04X}"),
+			Self::Base { logical_address: None } => "base off".to_string(),
+			Self::Skip { amount } => format!("skip {amount}"),
+			Self::WarnPc { limit } => format!("warnpc {limit:04X}"),
+			Self::Print { parts } => format!(
+				"print {}",
+				parts.iter().map(ToString::to_string).intersperse(", ".to_string()).collect::<String>()
+			),
+			Self::Assert { condition, message } => format!(
+				"assert {condition}{}",
+				message.as_ref().map(|message| format!(", {message:?}")).unwrap_or_default()
+			),
+			Self::StartSpcBlock(address) => format!("spcblock {address:04X}"),
+			Self::EndSpcBlock { execute } => format!(
+				"endspcblock{}",
+				execute.as_ref().map(|execute| format!(" execute {execute:04X}")).unwrap_or_default()
+			),
 			Self::EndNamespace => "namespace off".to_string(),
 			Self::StartNamespace { name } => format!("namespace {name}"),
 			Self::UserDefinedMacro { name, arguments, body } => format!(
@@ -460,10 +703,50 @@ impl Display for DirectiveValue {
 					.collect::<String>()
 					.replace('\n', "\n    "),
 			),
+			Self::DefinitionConditional { symbol, negated, true_block, false_block } => format!(
+				"if{}def {}\n{}\nelse\n{}",
+				if *negated { "n" } else { "" },
+				symbol,
+				true_block
+					.iter()
+					.map(ProgramElement::to_string)
+					.intersperse("\n".into())
+					.collect::<String>()
+					.replace('\n', "\n    "),
+				false_block
+					.iter()
+					.map(ProgramElement::to_string)
+					.intersperse("\n".into())
+					.collect::<String>()
+					.replace('\n', "\n    "),
+			),
 		})
 	}
 }
 
+/// Formats the conversion options of a BRR directive the way they are written in source code.
+fn conversion_options_to_string(conversion: &ConversionOptions) -> String {
+	let downmix = match &conversion.downmix {
+		Downmix::Average => String::new(),
+		Downmix::Channel(channel) => format!(" channel({channel})"),
+		// Weights are given in percent.
+		Downmix::Weights(weights) => format!(
+			" mix({})",
+			weights
+				.iter()
+				.map(|weight| (weight * 100.).round().to_string())
+				.intersperse(", ".to_string())
+				.collect::<String>()
+		),
+	};
+	let normalization = match conversion.normalization {
+		None => String::new(),
+		Some(Normalization::Peak(level)) => format!(" peak({level})"),
+		Some(Normalization::Rms(level)) => format!(" rms({level})"),
+	};
+	format!("{downmix}{normalization}{}", if conversion.dither { " dither" } else { "" })
+}
+
 impl ReferenceResolvable for DirectiveValue {
 	fn replace_macro_parent(
 		&mut self,
@@ -477,13 +760,15 @@ impl ReferenceResolvable for DirectiveValue {
 						value.value.replace_macro_parent(replacement_parent.clone(), source_code)?;
 					}
 				},
-			Self::Conditional { true_block, false_block, .. } =>
+			Self::Conditional { true_block, false_block, .. }
+			| Self::DefinitionConditional { true_block, false_block, .. } =>
 				try {
 					for element in true_block.iter_mut().chain(false_block.iter_mut()) {
 						element.replace_macro_parent(replacement_parent.clone(), source_code)?;
 					}
 				},
 			Self::String { .. }
+			| Self::Charmap(_)
 			| Self::Include { .. }
 			| Self::End
 			| Self::PushSection
@@ -494,10 +779,24 @@ impl ReferenceResolvable for DirectiveValue {
 			| Self::Fill { .. }
 			| Self::PopSection
 			| Self::Startpos
+			| Self::DirectPage { .. }
+			| Self::Base { .. }
+			| Self::StartSpcBlock(_)
+			| Self::EndSpcBlock { execute: None }
 			| Self::StartNamespace { .. }
 			| Self::EndNamespace
 			| Self::Org(_) => Ok(()),
-			Self::AssignReference { value, .. } => value.replace_macro_parent(replacement_parent, source_code),
+			Self::Skip { amount: value }
+			| Self::WarnPc { limit: value }
+			| Self::Assert { condition: value, .. }
+			| Self::EndSpcBlock { execute: Some(value) }
+			| Self::AssignReference { value, .. } => value.replace_macro_parent(replacement_parent, source_code),
+			Self::Print { parts } =>
+				try {
+					for value in parts.iter_mut().filter_map(PrintPart::value_mut) {
+						value.replace_macro_parent(replacement_parent.clone(), source_code)?;
+					}
+				},
 			Self::UserDefinedMacro { name, body, .. } => Err(AssemblyError::RecursiveMacroDefinition {
 				name:     (*name).to_string().into(),
 				location: source_range(
@@ -521,7 +820,8 @@ impl ReferenceResolvable for DirectiveValue {
 				for value in values {
 					value.value.resolve_relative_labels(direction, relative_labels);
 				},
-			Self::Conditional { true_block, false_block, .. } =>
+			Self::Conditional { true_block, false_block, .. }
+			| Self::DefinitionConditional { true_block, false_block, .. } =>
 				for element in true_block.iter_mut().chain(false_block.iter_mut()) {
 					element.resolve_relative_labels(direction, relative_labels);
 				},
@@ -532,6 +832,7 @@ impl ReferenceResolvable for DirectiveValue {
 				}
 			},
 			Self::String { .. }
+			| Self::Charmap(_)
 			| Self::Include { .. }
 			| Self::End
 			| Self::PushSection
@@ -541,11 +842,23 @@ impl ReferenceResolvable for DirectiveValue {
 			| Self::SetDirectiveParameters { .. }
 			| Self::PopSection
 			| Self::Startpos
+			| Self::DirectPage { .. }
+			| Self::Base { .. }
+			| Self::StartSpcBlock(_)
+			| Self::EndSpcBlock { execute: None }
 			| Self::StartNamespace { .. }
 			| Self::EndNamespace
 			| Self::Org(_)
 			| Self::UserDefinedMacro { .. } => (),
-			Self::AssignReference { value, .. } => value.resolve_relative_labels(direction, relative_labels),
+			Self::Skip { amount: value }
+			| Self::WarnPc { limit: value }
+			| Self::Assert { condition: value, .. }
+			| Self::EndSpcBlock { execute: Some(value) }
+			| Self::AssignReference { value, .. } => value.resolve_relative_labels(direction, relative_labels),
+			Self::Print { parts } =>
+				for value in parts.iter_mut().filter_map(PrintPart::value_mut) {
+					value.resolve_relative_labels(direction, relative_labels);
+				},
 		}
 	}
 
@@ -555,7 +868,8 @@ impl ReferenceResolvable for DirectiveValue {
 				for value in values {
 					value.value.resolve_pseudo_labels(global_labels);
 				},
-			Self::Conditional { true_block, false_block, .. } =>
+			Self::Conditional { true_block, false_block, .. }
+			| Self::DefinitionConditional { true_block, false_block, .. } =>
 				for element in true_block.iter_mut().chain(false_block.iter_mut()) {
 					element.resolve_pseudo_labels(global_labels);
 				},
@@ -566,6 +880,7 @@ impl ReferenceResolvable for DirectiveValue {
 				}
 			},
 			Self::String { .. }
+			| Self::Charmap(_)
 			| Self::Include { .. }
 			| Self::End
 			| Self::PushSection
@@ -575,11 +890,23 @@ impl ReferenceResolvable for DirectiveValue {
 			| Self::SetDirectiveParameters { .. }
 			| Self::PopSection
 			| Self::Startpos
+			| Self::DirectPage { .. }
+			| Self::Base { .. }
+			| Self::StartSpcBlock(_)
+			| Self::EndSpcBlock { execute: None }
 			| Self::StartNamespace { .. }
 			| Self::EndNamespace
 			| Self::Org(_)
 			| Self::UserDefinedMacro { .. } => (),
-			Self::AssignReference { value, .. } => value.resolve_pseudo_labels(global_labels),
+			Self::Skip { amount: value }
+			| Self::WarnPc { limit: value }
+			| Self::Assert { condition: value, .. }
+			| Self::EndSpcBlock { execute: Some(value) }
+			| Self::AssignReference { value, .. } => value.resolve_pseudo_labels(global_labels),
+			Self::Print { parts } =>
+				for value in parts.iter_mut().filter_map(PrintPart::value_mut) {
+					value.resolve_pseudo_labels(global_labels);
+				},
 		}
 	}
 
@@ -595,7 +922,8 @@ impl ReferenceResolvable for DirectiveValue {
 						value.value.set_current_label(current_label, source_code)?;
 					}
 				},
-			Self::Conditional { true_block, false_block, .. } =>
+			Self::Conditional { true_block, false_block, .. }
+			| Self::DefinitionConditional { true_block, false_block, .. } =>
 				try {
 					for element in true_block.iter_mut().chain(false_block.iter_mut()) {
 						element.set_current_label(current_label, source_code)?;
@@ -608,6 +936,16 @@ impl ReferenceResolvable for DirectiveValue {
 						value.set_current_label(current_label, source_code)?;
 					}
 				},
+			Self::Skip { amount: value }
+			| Self::WarnPc { limit: value }
+			| Self::Assert { condition: value, .. }
+			| Self::EndSpcBlock { execute: Some(value) } => value.set_current_label(current_label, source_code),
+			Self::Print { parts } =>
+				try {
+					for value in parts.iter_mut().filter_map(PrintPart::value_mut) {
+						value.set_current_label(current_label, source_code)?;
+					}
+				},
 			Self::AssignReference { reference, value } => {
 				reference.set_current_label_with_kind(
 					current_label,
@@ -621,12 +959,17 @@ impl ReferenceResolvable for DirectiveValue {
 			| Self::SampleTable { .. }
 			| Self::Brr { .. }
 			| Self::String { .. }
+			| Self::Charmap(_)
 			| Self::SetDirectiveParameters { .. }
 			| Self::Placeholder
 			| Self::End
 			| Self::PushSection
 			| Self::PopSection
 			| Self::Startpos
+			| Self::DirectPage { .. }
+			| Self::Base { .. }
+			| Self::StartSpcBlock(_)
+			| Self::EndSpcBlock { execute: None }
 			| Self::StartNamespace { .. }
 			| Self::EndNamespace
 			| Self::Org(_) => Ok(()),
@@ -15,13 +15,17 @@ use crate::Segments;
 
 /// Save all the metadata since object is too dumb to do this itself.
 struct SegmentMetadata {
-	name:            std::string::String,
+	name:                  std::string::String,
 	// The following values are set later, once the data has been constructed into immutable memory.
-	name_id:         Cell<Option<StringId>>,
-	section_index:   Cell<Option<SectionIndex>>,
-	elf_file_offset: Cell<Option<u64>>,
-	data:            Vec<u8>,
-	start_address:   u64,
+	name_id:               Cell<Option<StringId>>,
+	section_index:         Cell<Option<SectionIndex>>,
+	elf_file_offset:       Cell<Option<u64>>,
+	data:                  Vec<u8>,
+	/// Physical address where the data is loaded (LMA).
+	start_address:         u64,
+	/// Logical address that the data was assembled for (VMA). This only differs from the physical address after
+	/// `base`.
+	logical_start_address: u64,
 }
 
 /// Writes assembled data to an ELF file, separating it by segments.
@@ -60,12 +64,13 @@ pub fn write_to_elf(
 	// Since segments is a BTreeMap, it will always be sorted by address, simplifying later write steps.
 	for (segment_start, segment_contents) in data.segments {
 		let metadata = Box::new(SegmentMetadata {
-			name:            format!(".text_{segment_start:04X}"),
-			name_id:         Cell::new(None),
-			section_index:   Cell::new(None),
-			elf_file_offset: Cell::new(None),
-			data:            segment_contents,
-			start_address:   segment_start as u64,
+			name:                  format!(".text_{segment_start:04X}"),
+			name_id:               Cell::new(None),
+			section_index:         Cell::new(None),
+			elf_file_offset:       Cell::new(None),
+			data:                  segment_contents,
+			start_address:         segment_start as u64,
+			logical_start_address: data.logical_starts.get(&segment_start).copied().unwrap_or(segment_start) as u64,
 		});
 		segments.push(metadata);
 	}
@@ -112,7 +117,7 @@ pub fn write_to_elf(
 			p_type:   PT_LOAD,
 			p_flags:  0,
 			p_offset: segment.elf_file_offset.get().unwrap(),
-			p_vaddr:  segment.start_address,
+			p_vaddr:  segment.logical_start_address,
 			p_paddr:  segment.start_address,
 			p_filesz: segment.data.len() as u64,
 			p_memsz:  segment.data.len() as u64,
@@ -134,7 +139,7 @@ pub fn write_to_elf(
 	elf.write_symtab_section_header(0);
 	for segment in &segments {
 		elf.write_section_header(&SectionHeader {
-			sh_addr:      segment.start_address,
+			sh_addr:      segment.logical_start_address,
 			name:         segment.name_id.get(),
 			sh_type:      SHT_PROGBITS,
 			// Read, write, execute.
@@ -72,7 +72,7 @@ pub enum AssemblyError {
 		severity(Error),
 		help(
 			"the `startpos` directive defines the execution entry point of the ROM after it was loaded. This is \
-			 required for ELF binary output."
+			 required for ELF binary output and upload stream output."
 		)
 	)]
 	MissingStartpos {
@@ -98,15 +98,15 @@ pub enum AssemblyError {
 		location: SourceSpan,
 	},
 
-	#[error("`math pri` is not supported by spcasm")]
+	#[error("`math pri` is only supported in Asar compatibility mode")]
 	#[diagnostic(
 		code(spcasm::directive::math_pri_unsupported),
 		severity(Error),
 		help(
 			"This directive switches between normal operator precedence and \"dumb\" left-to-right precedence in the \
-			 Asar multi-architecture assembler. This feature is intended for xkas compatibility, but spcasm does not \
-			 consider this an important feature. Therefore, spcasm cannot switch math priority mode. Please check \
-			 your math expressions manually if you are using `math pri off`."
+			 Asar multi-architecture assembler. This feature is intended for xkas compatibility, so spcasm only \
+			 supports it in Asar compatibility mode (`--asar`). Otherwise, please check your math expressions \
+			 manually if you are using `math pri off`."
 		)
 	)]
 	MathPriForbidden {
@@ -116,6 +116,23 @@ pub enum AssemblyError {
 		location: SourceSpan,
 	},
 
+	#[error("Strings in tables are only supported in Asar compatibility mode")]
+	#[diagnostic(
+		code(spcasm::directive::table_string_unsupported),
+		severity(Error),
+		help(
+			"Asar allows strings in `db` and other table directives, which are expanded into one entry per character. \
+			 spcasm only supports this in Asar compatibility mode (`--asar`). Otherwise, use the `ascii` directive for \
+			 strings."
+		)
+	)]
+	TableStringForbidden {
+		#[source_code]
+		src:      Arc<AssemblyCode>,
+		#[label("String in table")]
+		location: SourceSpan,
+	},
+
 	#[error("Assigning a value to {} '{name}' is not possible", .kind.name())]
 	#[diagnostic(
 		code(spcasm::reference::assign_invalid),
@@ -239,6 +256,20 @@ pub enum AssemblyError {
 		definition:      SourceSpan,
 	},
 
+	#[error("Macro '{name}' is never used")]
+	#[diagnostic(
+		code(spcasm::user_macro::unused),
+		severity(Warning),
+		help("Remove the macro if it is not needed anymore.")
+	)]
+	UnusedUserMacro {
+		name:     SharedStr,
+		#[source_code]
+		src:      Arc<AssemblyCode>,
+		#[label("Macro defined here")]
+		location: SourceSpan,
+	},
+
 	#[error("File \"{file_name}\" was not found")]
 	#[diagnostic(code(spcasm::io::file_not_found), severity(Error))]
 	FileNotFound {
@@ -299,6 +330,41 @@ pub enum AssemblyError {
 		src:           Arc<AssemblyCode>,
 	},
 
+	#[error("The base image \"{file_name}\" is not a valid {format} file: {reason}")]
+	#[diagnostic(code(spcasm::patch::invalid_base_image), severity(Error))]
+	InvalidBaseImage { file_name: SharedStr, format: SharedStr, reason: SharedStr },
+
+	#[error("Patch at {start:04X}-{end:04X} is outside of the memory contained in the base image \"{file_name}\"")]
+	#[diagnostic(
+		code(spcasm::patch::outside_base_image),
+		severity(Error),
+		help(
+			"{format} base images can only be patched within the memory they already contain, since their container \
+			 format would have to change otherwise."
+		)
+	)]
+	PatchOutsideBaseImage {
+		start:     MemoryAddress,
+		end:       MemoryAddress,
+		file_name: SharedStr,
+		format:    SharedStr,
+	},
+
+	#[error("Patch at {address:04X} changes {length} bytes of the base image")]
+	#[diagnostic(
+		code(spcasm::patch::overwritten_data),
+		severity(Warning),
+		help("Make sure that the base image doesn't need the original data anymore.")
+	)]
+	PatchOverwritesData {
+		address:  MemoryAddress,
+		length:   usize,
+		#[label("New data")]
+		location: SourceSpan,
+		#[source_code]
+		src:      Arc<AssemblyCode>,
+	},
+
 	#[error("There is no active segment here")]
 	#[diagnostic(
 		code(spcasm::segment::missing),
@@ -326,6 +392,20 @@ pub enum AssemblyError {
 		src:       Arc<AssemblyCode>,
 	},
 
+	#[error("Global label '{reference}' is never used")]
+	#[diagnostic(
+		code(spcasm::reference::unused),
+		severity(Warning),
+		help("Remove the label if it is not needed anymore.")
+	)]
+	UnusedReference {
+		reference: SharedStr,
+		#[label("Label defined here")]
+		location:  SourceSpan,
+		#[source_code]
+		src:       Arc<AssemblyCode>,
+	},
+
 	#[error("There is no segment on the stack")]
 	#[diagnostic(
 		code(spcasm::segment::empty_stack),
@@ -483,6 +563,43 @@ pub enum AssemblyError {
 		src:         Arc<AssemblyCode>,
 	},
 
+	#[error("`{mnemonic}` cannot be forced to {width} addressing with these operands")]
+	#[diagnostic(
+		code(spcasm::instruction::impossible_width),
+		help(
+			"This instruction has no {width} addressing form that differs from the other form. Remove the explicit \
+			 size suffix or operand prefix to let spcasm choose the addressing mode."
+		),
+		severity(Error)
+	)]
+	ImpossibleAddressingWidth {
+		mnemonic: Mnemonic,
+		width:    SharedStr,
+		#[label("Forced to {width} addressing")]
+		location: SourceSpan,
+		#[source_code]
+		src:      Arc<AssemblyCode>,
+	},
+
+	#[error("`{mnemonic}` is forced to both direct page and absolute addressing")]
+	#[diagnostic(
+		code(spcasm::instruction::conflicting_widths),
+		help(
+			"Use either `.b` and `<` to force direct page addressing, or `.w` and `!` to force absolute addressing, \
+			 but not both in the same instruction."
+		),
+		severity(Error)
+	)]
+	ConflictingAddressingWidths {
+		mnemonic:             Mnemonic,
+		#[label("Direct page addressing forced here")]
+		direct_page_location: SourceSpan,
+		#[label("Absolute addressing forced here")]
+		absolute_location:    SourceSpan,
+		#[source_code]
+		src:                  Arc<AssemblyCode>,
+	},
+
 	#[error("`{constant}` is not valid for {typename}")]
 	#[diagnostic(
 		code(spcasm::instruction::invalid_constant),
@@ -563,6 +680,58 @@ pub enum AssemblyError {
 		location: SourceSpan,
 	},
 
+	#[error("Page {page} cannot be the direct page")]
+	#[diagnostic(
+		code(spcasm::directive::invalid_direct_page),
+		help("The P flag selects either page 0 (`$00xx`) or page 1 (`$01xx`) as the direct page, so `dpage` only accepts 0 or 1."),
+		severity(Error)
+	)]
+	InvalidDirectPage {
+		page:     MemoryAddress,
+		#[source_code]
+		src:      Arc<AssemblyCode>,
+		#[label("`dpage` directive defined here")]
+		location: SourceSpan,
+	},
+
+	#[error("Address {pc:04X} exceeds the limit {limit:04X}")]
+	#[diagnostic(
+		code(spcasm::directive::warnpc),
+		help(
+			"The code or data before this `warnpc` directive is too large. Make it smaller, or move it to a lower \
+			 address."
+		),
+		severity(Error)
+	)]
+	PcExceedsLimit {
+		pc:       MemoryAddress,
+		limit:    MemoryAddress,
+		#[source_code]
+		src:      Arc<AssemblyCode>,
+		#[label("`warnpc` directive defined here")]
+		location: SourceSpan,
+	},
+
+	#[error("{}", .message.as_ref().map_or_else(|| "Assertion failed".to_string(), |message| format!("Assertion failed: {message}")))]
+	#[diagnostic(code(spcasm::directive::assertion_failed), severity(Error))]
+	AssertionFailed {
+		message:  Option<SharedStr>,
+		#[source_code]
+		src:      Arc<AssemblyCode>,
+		#[label("This condition is false")]
+		location: SourceSpan,
+	},
+
+	#[error("{message}")]
+	#[diagnostic(code(spcasm::directive::print), severity(Advice))]
+	UserPrint {
+		message:  SharedStr,
+		#[source_code]
+		src:      Arc<AssemblyCode>,
+		#[label("Printed here")]
+		location: SourceSpan,
+	},
+
 	#[error("Invalid option `{option}` for directive `{directive}`")]
 	#[diagnostic(
 		code(spcasm::directive::invalid_directive_option),
@@ -582,6 +751,55 @@ pub enum AssemblyError {
 		option_location:    SourceSpan,
 	},
 
+	#[error("Invalid arguments for option `{option}` of directive `{directive}`")]
+	#[diagnostic(
+		code(spcasm::directive::invalid_option_arguments),
+		help("This option is written as `{usage}`."),
+		severity(Error)
+	)]
+	InvalidDirectiveOptionArguments {
+		option:             SharedStr,
+		directive:          SharedStr,
+		usage:              SharedStr,
+		#[source_code]
+		src:                Arc<AssemblyCode>,
+		#[label("`{directive}` directive defined here")]
+		directive_location: SourceSpan,
+		#[label("Invalid arguments for this option")]
+		option_location:    SourceSpan,
+	},
+
+	#[error("Character map entry has no characters")]
+	#[diagnostic(
+		code(spcasm::directive::empty_charmap_sequence),
+		help("A character map entry maps a text of one or more characters to bytes, so the text can't be empty."),
+		severity(Error)
+	)]
+	EmptyCharmapSequence {
+		#[source_code]
+		src:      Arc<AssemblyCode>,
+		#[label("Empty text")]
+		location: SourceSpan,
+	},
+
+	#[error("Invalid entry in character map table \"{file_name}\": {reason}")]
+	#[diagnostic(
+		code(spcasm::directive::invalid_charmap_table),
+		help(
+			"Every line of a table file has the form `XX=text`, which maps the text to the bytes `XX` given in \
+			 hexadecimal. Empty lines are ignored."
+		),
+		severity(Error)
+	)]
+	InvalidCharmapTable {
+		file_name: SharedStr,
+		reason:    SharedStr,
+		#[source_code]
+		src:       Arc<AssemblyCode>,
+		#[label("Invalid entry")]
+		location:  SourceSpan,
+	},
+
 	#[error("No value specified for `{operation}`")]
 	#[diagnostic(
 		code(spcasm::directive::missing_fill_pad_parameter),
@@ -696,6 +914,46 @@ pub enum AssemblyError {
 		src:      Arc<AssemblyCode>,
 	},
 
+	#[error("This code is unreachable")]
+	#[diagnostic(
+		code(spcasm::instruction::unreachable),
+		severity(Warning),
+		help(
+			"The previous `{mnemonic}` instruction never continues with the next instruction, and there is no label \
+			 that allows jumping here."
+		)
+	)]
+	UnreachableCode {
+		mnemonic: Mnemonic,
+		#[label("Unreachable instruction")]
+		location: SourceSpan,
+		#[source_code]
+		src:      Arc<AssemblyCode>,
+	},
+
+	#[error("'{reference}' at address `{address:04X}` is accessed via direct page {page}")]
+	#[diagnostic(
+		code(spcasm::instruction::wrong_direct_page),
+		severity(Warning),
+		help(
+			"The P flag selects page {page} as the direct page here, so this instruction accesses \
+			 `{accessed_address:04X}` instead. Use `setp` or `clrp` to select the correct direct page first. If the P \
+			 flag is known to be different here, tell spcasm with a `dpage` directive."
+		)
+	)]
+	WrongDirectPage {
+		reference:        SharedStr,
+		address:          MemoryAddress,
+		accessed_address: MemoryAddress,
+		page:             u8,
+		#[label("Direct page operand")]
+		location:         SourceSpan,
+		#[label("Direct page {page} selected here")]
+		page_location:    SourceSpan,
+		#[source_code]
+		src:              Arc<AssemblyCode>,
+	},
+
 	#[error("Expected any of {}", expected.iter().map(std::string::ToString::to_string).collect::<Vec<_>>().join(", "))]
 	#[diagnostic(code(spcasm::syntax::missing_token), severity(Error))]
 	UnexpectedEndOfTokens {
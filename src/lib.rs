@@ -36,14 +36,20 @@ mod change;
 pub mod cli;
 mod common;
 mod default_hacks;
+#[cfg(feature = "binaries")]
+mod diagnostic_output;
 mod directive;
 #[cfg(feature = "binaries")]
 pub mod elf;
 mod error;
 pub mod parser;
+#[cfg(feature = "binaries")]
+pub mod patch;
 mod segments;
 pub mod sema;
 mod source;
+#[cfg(feature = "binaries")]
+pub mod upload;
 
 // can't use the shadow_rs macro for this purpose since it doesn't include documentation on all its elements and we
 // therefore have to allow missing docs.
@@ -56,6 +62,7 @@ pub mod buildinfo {
 #[cfg(test)] mod test;
 
 #[cfg(feature = "binaries")] mod spcasm;
+#[cfg(feature = "binaries")] mod watch;
 
 #[cfg(feature = "binaries")]
 #[allow(unused)]
@@ -1,5 +1,6 @@
 //! LALRPOP adaptor code.
 
+use std::collections::HashMap;
 use std::num::NonZeroU64;
 use std::vec::IntoIter;
 
@@ -7,6 +8,7 @@ use std::vec::IntoIter;
 use flexstr::{shared_str, IntoSharedStr, SharedStr, ToSharedStr};
 
 use super::{source_range, Token};
+use crate::directive::DirectiveSymbol;
 use crate::error::AssemblyError;
 use crate::sema::reference::RelativeReferenceDirection;
 use crate::sema::Register;
@@ -30,12 +32,65 @@ impl Iterator for LalrpopAdaptor {
 	}
 }
 
+/// Performs Asar-style define text substitution on the token stream. A line of the form `!name = tokens` stores the
+/// tokens as the define's replacement text and is removed from the stream, except for its newline. Any later use of
+/// `!name` is replaced by the stored tokens, relocated to the place of use. Definitions can refer to earlier defines,
+/// which are expanded at the point of definition. `ifdef !name` and `ifndef !name` on a known define turn into an
+/// unconditional `if`, since the define is known to exist at this point.
+///
+/// Names that are not (yet) defined lose their `!` sigil, so that they can still be used as labels like outside of Asar
+/// compatibility mode. In particular, `ifdef !name` then checks whether a label called `name` exists.
+pub fn expand_asar_defines(tokens: Vec<Token>, defines: &mut HashMap<SharedStr, Vec<Token>>) -> Vec<Token> {
+	let mut tokens = tokens.into_iter().peekable();
+	let mut result: Vec<Token> = Vec::new();
+	while let Some(next_token) = tokens.next() {
+		let at_line_start = result.last().is_none_or(Token::is_newline);
+		let next_is_define = matches!(tokens.peek(), Some(Token::Identifier(name, _)) if defines.contains_key(name));
+		match next_token {
+			Token::Identifier(ref name, ..)
+				if at_line_start && name.starts_with('!') && matches!(tokens.peek(), Some(Token::Equals(..))) =>
+			{
+				tokens.next();
+				let mut replacement = Vec::new();
+				while let Some(value_token) = tokens.next_if(|token| !token.is_newline()) {
+					match value_token {
+						Token::Identifier(ref value_name, span) if let Some(value) = defines.get(value_name) =>
+							replacement.extend(value.iter().map(|token| token.relocated(span))),
+						_ => replacement.push(strip_define_sigil(value_token)),
+					}
+				}
+				defines.insert(name.clone(), replacement);
+			},
+			Token::Directive(symbol @ (DirectiveSymbol::Ifdef | DirectiveSymbol::Ifndef), span) if next_is_define => {
+				let Some(Token::Identifier(name, name_span)) = tokens.next() else { unreachable!() };
+				let condition = i64::from(symbol == DirectiveSymbol::Ifdef);
+				result.push(Token::Directive(DirectiveSymbol::If, span));
+				result.push(Token::Number(condition, name, name_span));
+			},
+			Token::Identifier(ref name, span) if let Some(value) = defines.get(name) =>
+				result.extend(value.iter().map(|token| token.relocated(span))),
+			_ => result.push(strip_define_sigil(next_token)),
+		}
+	}
+	result
+}
+
+/// Turns an identifier of an undefined Asar define back into a plain identifier.
+fn strip_define_sigil(token: Token) -> Token {
+	match token {
+		Token::Identifier(ref name, span) if let Some(label) = name.strip_prefix('!') =>
+			Token::Identifier(label.into(), span),
+		_ => token,
+	}
+}
+
 /// Because LALRPOP's grammar needs to be LR(1) but SPC700 assembly by default is not, we do a preprocessing step which
 /// is (somewhat) LL(2). Things we do here:
 /// - insert final newline to allow simpler newline-related grammar rules
 /// - disambiguate parenthesis used for expressions and for addressing
 /// - combine '+X' and '+Y' into one token
 /// - transform simple '-' into a "range dash" for range specifications
+/// - turn the 'off' in 'base off' into a keyword, since it would otherwise be a label reference
 #[allow(clippy::too_many_lines)]
 pub fn preprocess_token_stream(tokens: Vec<Token>) -> Vec<Token> {
 	let mut tokens = tokens.into_iter();
@@ -46,6 +101,7 @@ pub fn preprocess_token_stream(tokens: Vec<Token>) -> Vec<Token> {
 	// We're currently on a line that has a mnemonic; important for determining what purpose commas have.
 	let mut in_mnemonic_line = false;
 	let mut last_offset = 0;
+	let is_off_keyword = |token: Option<&Token>| matches!(token, Some(Token::Identifier(name, _)) if &**name == "off");
 	while let Some(next_token) = tokens.next() {
 		last_offset = next_token.source_span().offset() + next_token.source_span().len() - 1;
 		match next_token {
@@ -165,6 +221,12 @@ pub fn preprocess_token_stream(tokens: Vec<Token>) -> Vec<Token> {
 				}
 				continue;
 			},
+			Token::Directive(DirectiveSymbol::Base, _) if is_off_keyword(tokens.as_slice().first()) => {
+				let Some(Token::Identifier(_, off_span)) = tokens.next() else { unreachable!() };
+				result.push(next_token);
+				result.push(Token::SpecialIdentifier("off", off_span));
+				continue;
+			},
 			// After all of these tokens we can be sure we can't have an indexing addressing mode, so parentheses can
 			// stay. For example: '#' signals immediate, '/' signals negated bit indexing, '+', '-' signal the
 			// unambiguous start of a number, '.'/identifier signal references.
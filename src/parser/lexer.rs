@@ -29,7 +29,7 @@ macro_rules! is_identifier {
 /// Lex the given assembly into a list of tokens.
 /// # Errors
 /// Errors are returned for any syntactical error at the token level, e.g. invalid number literals.
-#[allow(clippy::missing_panics_doc, clippy::too_many_lines)]
+#[allow(clippy::missing_panics_doc, clippy::too_many_lines, clippy::cognitive_complexity)]
 pub fn lex(source_code: Arc<AssemblyCode>, options: &dyn Frontend) -> Result<Vec<Token>, Box<AssemblyError>> {
 	let mut chars = source_code.text.chars().peekable();
 	let code_length = source_code.text.len();
@@ -62,12 +62,9 @@ pub fn lex(source_code: Arc<AssemblyCode>, options: &dyn Frontend) -> Result<Vec
 			'"' => {
 				let start_index = index;
 				index += 1;
-				let text = next_string(&mut chars, source_code.clone(), &mut index)?
-					.into_iter()
-					.map(|chr| chr as u8)
-					.collect();
+				let text = next_string(&mut chars, source_code.clone(), &mut index)?.into_iter().collect::<String>();
 				let text_span = (start_index, index - start_index).into();
-				tokens.push(Token::String(text, text_span));
+				tokens.push(Token::String(text.into(), text_span));
 			},
 			'\'' => {
 				let start_index = index;
@@ -121,7 +118,7 @@ pub fn lex(source_code: Arc<AssemblyCode>, options: &dyn Frontend) -> Result<Vec
 				));
 			},
 			start_of_identifier!() => {
-				tokens.push(parse_identifier_like(&mut chars, chr, &mut index, &source_code)?);
+				tokens.push(parse_identifier_like(&mut chars, chr, &mut index, &source_code, options)?);
 			},
 			'0' ..= '9' => {
 				let (number, size) = next_number(&mut chars, Some(chr), false, 10, index, &source_code, options)?;
@@ -134,7 +131,13 @@ pub fn lex(source_code: Arc<AssemblyCode>, options: &dyn Frontend) -> Result<Vec
 				if chars.peek().is_some_and(|chr| is_identifier!(chr)) {
 					tokens.push(parse_single_char_tokens('.', index.into()));
 					index += 1;
-					tokens.push(parse_identifier_like(&mut chars, letter_after_dot, &mut index, &source_code)?);
+					tokens.push(parse_identifier_like(
+						&mut chars,
+						letter_after_dot,
+						&mut index,
+						&source_code,
+						options,
+					)?);
 				} else {
 					index += 2;
 					tokens.push((if letter_after_dot == 'b' {
@@ -159,12 +162,25 @@ pub fn lex(source_code: Arc<AssemblyCode>, options: &dyn Frontend) -> Result<Vec
 				index += 2;
 				tokens.push(Token::DoubleCloseAngleBracket((index - 2, 2).into()));
 			},
+			// Before an instruction operand, `!` forces absolute addressing even if a label follows directly. The label
+			// itself is lexed on its own afterwards.
+			'!' if matches!(chars.peek(), Some(start_of_identifier!()))
+				&& is_at_instruction_operand(&tokens)
+				&& !options.asar_compatibility() =>
+			{
+				tokens.push(Token::Exclamation(index.into()));
+				index += 1;
+			},
 			'!' if matches!(chars.peek(), Some(start_of_identifier!())) => {
 				let chr = chars.next().unwrap();
 				let start_index = index;
 				let identifier = next_identifier(&mut chars, chr);
 				index += identifier.len() + 1;
 				let identifier_span = (start_index, identifier.len() + 1).into();
+				// Asar defines keep their sigil, so that define substitution can recognize them. Otherwise, `!name` is
+				// the same as `name`.
+				let identifier =
+					if options.asar_compatibility() { format!("!{identifier}").into() } else { identifier };
 				tokens.push(Token::Identifier(identifier, identifier_span));
 			},
 			'!' if chars.peek().is_some_and(|chr| chr == &'=') => {
@@ -187,6 +203,10 @@ pub fn lex(source_code: Arc<AssemblyCode>, options: &dyn Frontend) -> Result<Vec
 				index += 2;
 				tokens.push(Token::CloseAngleBracketEquals((index - 2, 2).into()));
 			},
+			'<' if is_at_instruction_operand(&tokens) && !is_macro_argument(chars.clone()) => {
+				tokens.push(Token::DirectPagePrefix(index.into()));
+				index += 1;
+			},
 			'-' => {
 				let start = index;
 				index += 1;
@@ -293,6 +313,7 @@ fn parse_identifier_like(
 	start: char,
 	index: &mut usize,
 	source_code: &Arc<AssemblyCode>,
+	options: &dyn Frontend,
 ) -> Result<Token, AssemblyError> {
 	let start_index = *index;
 	let identifier = next_identifier(chars, start);
@@ -305,9 +326,18 @@ fn parse_identifier_like(
 			Register::parse(&identifier.to_ascii_lowercase(), identifier_span, source_code.clone())
 				.map(|value| Token::Register(value, identifier_span))
 		})
-		.or_else(|_| {
-			DirectiveSymbol::parse(&identifier.to_ascii_lowercase(), identifier_span, source_code.clone())
-				.map(|value| Token::Directive(value, identifier_span))
+		.or_else(|error| {
+			DirectiveSymbol::parse(&identifier.to_ascii_lowercase(), identifier_span, source_code.clone()).and_then(
+				|value| {
+					if value.is_asar_only() && !options.asar_compatibility()
+						|| value.is_asar_incompatible() && options.asar_compatibility()
+					{
+						Err(error)
+					} else {
+						Ok(Token::Directive(value, identifier_span))
+					}
+				},
+			)
 		})
 		.or_else(|_| {
 			Mnemonic::parse(&identifier.to_ascii_lowercase(), identifier_span, source_code.clone())
@@ -442,7 +472,7 @@ fn next_escape_sequence(
 			},
 			_ => Err(AssemblyError::ExpectedTokens {
 				expected: vec!["'".into(), "\"".into(), "t".into(), "n".into(), "0".into(), "r".into(), "x".into()],
-				actual:   Token::String(vec![chr as u8], (*start_index, 0).into()),
+				actual:   Token::String(chr.to_string().into(), (*start_index, 0).into()),
 				location: (*start_index, 0).into(),
 				src:      source_code,
 			}
@@ -458,6 +488,29 @@ fn next_escape_sequence(
 	}
 }
 
+/// Whether the next token starts an instruction operand, i.e. it follows a mnemonic (with an optional size suffix) or
+/// the comma between two operands of an instruction.
+fn is_at_instruction_operand(tokens: &[Token]) -> bool {
+	match tokens.last() {
+		Some(Token::Mnemonic(..) | Token::ExplicitDirectPage(..) | Token::ExplicitNoDirectPage(..)) => true,
+		Some(Token::Comma(..)) => tokens
+			.iter()
+			.rev()
+			.take_while(|token| !matches!(token, Token::Newline(..)))
+			.any(|token| matches!(token, Token::Mnemonic(..))),
+		_ => false,
+	}
+}
+
+/// Whether the characters after a '<' form a macro argument reference like `<name>`.
+fn is_macro_argument(mut chars: Peekable<std::str::Chars>) -> bool {
+	let Some(start @ start_of_identifier!()) = chars.next() else {
+		return false;
+	};
+	next_identifier(&mut chars, start);
+	chars.next() == Some('>')
+}
+
 fn parse_single_char_tokens(chr: char, location: SourceOffset) -> Token {
 	match chr {
 		'+' => Token::Plus(location),
@@ -477,7 +530,8 @@ fn parse_single_char_tokens(chr: char, location: SourceOffset) -> Token {
 		'~' => Token::Tilde(location),
 		'^' => Token::Caret(location),
 		':' => Token::Colon(location),
-		'/' | '!' => Token::Slash(location),
+		'/' => Token::Slash(location),
+		'!' => Token::Exclamation(location),
 		'.' => Token::Period(location),
 		'=' => Token::Equals(location),
 		_ => unreachable!(),
@@ -9,10 +9,15 @@ use std::sync::Arc;
 use flexstr::{shared_str, IntoSharedStr, SharedStr, ToSharedStr};
 pub use lexer::*;
 use miette::{SourceOffset, SourceSpan};
+use parking_lot::RwLock;
 pub use token::Token;
 
-use crate::directive::DirectiveValue;
-use crate::sema::AssemblyTimeValue;
+use crate::assembler::resolve_file;
+use crate::brr::wav::{Downmix, Normalization};
+use crate::directive::{DirectiveSymbol, DirectiveValue};
+use crate::sema::instruction::{AddressingModeOptimization, Mnemonic};
+use crate::sema::value::BinaryOperator;
+use crate::sema::{AssemblyTimeValue, Charmap, CharmapChange, Environment};
 use crate::{AssemblyCode, AssemblyError};
 
 lalrpop_mod!(
@@ -115,31 +120,285 @@ where
 	}
 }
 
-/// Apply the given list of options to a BRR directive, and report errors if necessary. This function is called from
+/// Combines the addressing mode optimizations that were explicitly requested for an instruction.
+///
+/// They can be requested with a mnemonic suffix like `.b` or with operand prefixes like `<`. This function is called
+/// from parser generator action code.
+///
+/// # Errors
+/// Both direct page and absolute addressing were requested.
+#[allow(clippy::result_large_err)] // Used by LALRPOP
+pub fn combine_addressing_mode_optimizations(
+	mnemonic: Mnemonic,
+	optimizations: impl IntoIterator<Item = (AddressingModeOptimization, SourceSpan)>,
+	source_code: &Arc<AssemblyCode>,
+) -> Result<AddressingModeOptimization, AssemblyError> {
+	let mut direct_page_location = None;
+	let mut absolute_location = None;
+	for (optimization, location) in optimizations {
+		match optimization {
+			AddressingModeOptimization::ForceDirectPage => direct_page_location = direct_page_location.or(Some(location)),
+			AddressingModeOptimization::PreventDirectPage => absolute_location = absolute_location.or(Some(location)),
+			AddressingModeOptimization::Automatic => {},
+		}
+	}
+	match (direct_page_location, absolute_location) {
+		(Some(direct_page_location), Some(absolute_location)) => Err(AssemblyError::ConflictingAddressingWidths {
+			mnemonic,
+			direct_page_location,
+			absolute_location,
+			src: source_code.clone(),
+		}),
+		(Some(_), None) => Ok(AddressingModeOptimization::ForceDirectPage),
+		(None, Some(_)) => Ok(AddressingModeOptimization::PreventDirectPage),
+		(None, None) => Ok(AddressingModeOptimization::Automatic),
+	}
+}
+
+/// Rebuilds the given expression so that all of its binary operations are evaluated strictly from left to right.
+///
+/// This is what Asar does with `math pri off`. Parenthesized sub-expressions are kept intact; they are recognized by
+/// their span, which includes the parentheses (see [`parenthesize_for_left_to_right`]). This function is called from
 /// parser generator action code.
 ///
+/// # Panics
+/// All panics are programming bugs.
+#[must_use]
+pub fn evaluate_left_to_right(value: AssemblyTimeValue) -> AssemblyTimeValue {
+	fn flatten(value: AssemblyTimeValue, operands: &mut Vec<AssemblyTimeValue>, operators: &mut Vec<BinaryOperator>) {
+		match value {
+			AssemblyTimeValue::BinaryOperation { lhs, rhs, operator, span }
+				if span == source_range(lhs.source_span().into(), rhs.source_span().into()) =>
+			{
+				flatten(*lhs, operands, operators);
+				operators.push(operator);
+				flatten(*rhs, operands, operators);
+			},
+			_ => operands.push(value),
+		}
+	}
+
+	let mut operands = Vec::new();
+	let mut operators = Vec::new();
+	flatten(value, &mut operands, &mut operators);
+	let mut operands = operands.into_iter();
+	// There is always at least one operand, and one more operand than there are operators.
+	let first = operands.next().unwrap();
+	operators.into_iter().zip(operands).fold(first, |lhs, (operator, rhs)| {
+		let span = source_range(lhs.source_span().into(), rhs.source_span().into());
+		AssemblyTimeValue::BinaryOperation { lhs: Box::new(lhs), rhs: Box::new(rhs), operator, span }
+	})
+}
+
+/// Marks a parenthesized expression so that [`evaluate_left_to_right`] does not split it up.
+///
+/// This extends the span of a binary operation to include the parentheses. Outside of left-to-right evaluation, the
+/// value is returned unchanged. This function is called from parser generator action code.
+#[must_use]
+pub fn parenthesize_for_left_to_right(
+	value: AssemblyTimeValue,
+	parentheses: SourceSpan,
+	left_to_right: bool,
+) -> AssemblyTimeValue {
+	match value {
+		AssemblyTimeValue::BinaryOperation { lhs, rhs, operator, .. } if left_to_right =>
+			AssemblyTimeValue::BinaryOperation { lhs, rhs, operator, span: parentheses },
+		value => value,
+	}
+}
+
+/// Apply the options of a `math` directive, which is only supported in Asar compatibility mode.
+///
+/// The only valid options are `pri on` and `pri off`, which switch the math priority mode for all following
+/// expressions. This function is called from parser generator action code.
+///
 /// # Errors
-/// An invalid option was provided.
+/// Asar compatibility mode is disabled, or an invalid option was provided.
+#[allow(clippy::result_large_err)] // Used by LALRPOP
+pub fn apply_math_options(
+	directive_location: SourceSpan,
+	source_code: &Arc<AssemblyCode>,
+	environment: &Arc<RwLock<Environment>>,
+	options: &[(SharedStr, SourceSpan)],
+) -> Result<(), AssemblyError> {
+	if !environment.read_recursive().options.asar_compatibility() {
+		return Err(AssemblyError::MathPriForbidden { src: source_code.clone(), location: directive_location });
+	}
+	match options {
+		[(pri, _), (setting, _)] if &**pri == "pri" && ["on", "off"].contains(&&**setting) => {
+			environment.write().left_to_right_math = &**setting == "off";
+			Ok(())
+		},
+		_ => {
+			let (option, option_location) = options
+				.iter()
+				.find(|(option, _)| !["pri", "on", "off"].contains(&&**option))
+				.or_else(|| options.last())
+				.cloned()
+				.unwrap_or_else(|| (shared_str!("math"), directive_location));
+			Err(AssemblyError::InvalidDirectiveOption {
+				directive_location,
+				option_location,
+				option,
+				directive: "math".into(),
+				valid_options: vec![shared_str!("pri on"), shared_str!("pri off")],
+				src: source_code.clone(),
+			})
+		},
+	}
+}
+
+/// Create the character map change of a `charmap` directive with an option.
+///
+/// `charmap load "<file>"` replaces the character map with the contents of a table file, and `charmap clear` removes
+/// all character mappings. This function is called from parser generator action code.
+///
+/// # Errors
+/// An invalid option was provided, or the table file can't be read or is invalid.
+#[allow(clippy::result_large_err)] // Used by LALRPOP
+pub fn charmap_option(
+	directive_location: SourceSpan,
+	source_code: &Arc<AssemblyCode>,
+	environment: &Arc<RwLock<Environment>>,
+	(option, option_location): (SharedStr, SourceSpan),
+	file: Option<(SharedStr, SourceSpan)>,
+) -> Result<CharmapChange, AssemblyError> {
+	match (&*option, file) {
+		("load", Some(file)) =>
+			load_charmap_table(directive_location, source_code, environment, file).map(CharmapChange::Replace),
+		("clear", None) => Ok(CharmapChange::Replace(Charmap::default())),
+		(_, file) => Err(AssemblyError::InvalidDirectiveOption {
+			directive_location,
+			option_location,
+			option,
+			directive: "charmap".into(),
+			valid_options: vec![if file.is_some() { shared_str!("load") } else { shared_str!("clear") }],
+			src: source_code.clone(),
+		}),
+	}
+}
+
+/// Load the character map from a table file, as done by `charmap load` and Asar's `table`.
+///
+/// See [`Charmap::from_table`] for the table file format. This function is called from parser generator action code.
+///
+/// # Errors
+/// The table file can't be read or is invalid.
+#[allow(clippy::result_large_err)] // Used by LALRPOP
+pub fn load_charmap_table(
+	directive_location: SourceSpan,
+	source_code: &Arc<AssemblyCode>,
+	environment: &Arc<RwLock<Environment>>,
+	(file, file_location): (SharedStr, SourceSpan),
+) -> Result<Charmap, AssemblyError> {
+	let include_directories = environment.read_recursive().options.include_directories();
+	let path = resolve_file(source_code, &include_directories, &file);
+	let text = std::fs::read_to_string(&path)
+		.map_err(|os_error| AssemblyError::FileNotFound {
+			os_error:  Arc::new(os_error),
+			file_name: file,
+			src:       source_code.clone(),
+			location:  source_range(directive_location.into(), file_location.into()),
+		})?
+		.replace('\r', "");
+	environment.read_recursive().options.report_file_dependency(&path);
+
+	Charmap::from_table(&text).map_err(|(location, reason)| AssemblyError::InvalidCharmapTable {
+		file_name: AssemblyCode::file_name_for(&path),
+		reason,
+		location,
+		src: Arc::new(AssemblyCode::new_from_path(&text, &path)),
+	})
+}
+
+/// Create the character map change of a `charmap "<text>", <bytes>` directive.
+///
+/// The bytes are only evaluated once the character map is applied, since they may contain character literals. This
+/// function is called from parser generator action code.
+///
+/// # Errors
+/// The text is empty.
 #[allow(clippy::result_large_err)] // Used by LALRPOP
+pub fn charmap_entry(
+	source_code: &Arc<AssemblyCode>,
+	(sequence, sequence_location): (SharedStr, SourceSpan),
+	values: Vec<AssemblyTimeValue>,
+) -> Result<CharmapChange, AssemblyError> {
+	if sequence.is_empty() {
+		return Err(AssemblyError::EmptyCharmapSequence { src: source_code.clone(), location: sequence_location });
+	}
+	Ok(CharmapChange::Insert { sequence, values })
+}
+
+/// Apply the given list of options to a BRR directive, and report errors if necessary. This function is called from
+/// parser generator action code.
+///
+/// # Errors
+/// An invalid option was provided, or an option was given the wrong arguments.
+#[allow(clippy::result_large_err, clippy::cast_precision_loss)] // Used by LALRPOP
 pub fn apply_brr_options(
 	directive_location: SourceSpan,
 	source_code: &Arc<AssemblyCode>,
 	mut value: DirectiveValue,
-	options: Vec<(SharedStr, SourceSpan)>,
+	options: Vec<(SharedStr, SourceSpan, Vec<AssemblyTimeValue>)>,
 ) -> Result<DirectiveValue, AssemblyError> {
 	match &mut value {
-		DirectiveValue::Brr { auto_trim, directory, .. } => {
-			for (option, option_location) in options {
-				match &*option {
-					"nodirectory" => *directory = false,
-					"autotrim" => *auto_trim = true,
+		DirectiveValue::Brr { auto_trim, directory, conversion, .. } => {
+			for (option, option_location, arguments) in options {
+				let arguments: Vec<_> = arguments
+					.iter()
+					.map(|argument| {
+						argument.try_value(option_location, source_code).map_err(|_| {
+							AssemblyError::ReferencesInDirectiveArgument {
+								directive:         DirectiveSymbol::Brr,
+								src:               source_code.clone(),
+								location:          directive_location,
+								argument_location: argument.source_span(),
+							}
+						})
+					})
+					.try_collect()?;
+				let invalid_arguments = |usage: &str| AssemblyError::InvalidDirectiveOptionArguments {
+					option: option.clone(),
+					directive: "brr".into(),
+					usage: usage.into(),
+					src: source_code.clone(),
+					directive_location,
+					option_location,
+				};
+				match (&*option, arguments.as_slice()) {
+					("nodirectory", []) => *directory = false,
+					("autotrim", []) => *auto_trim = true,
+					("dither", []) => conversion.dither = true,
+					("channel", [channel]) =>
+						conversion.downmix = Downmix::Channel(
+							u16::try_from(*channel).map_err(|_| invalid_arguments("channel(<index>)"))?,
+						),
+					// Weights are given in percent, since there are no fractional numbers.
+					("mix", weights) if !weights.is_empty() =>
+						conversion.downmix =
+							Downmix::Weights(weights.iter().map(|weight| *weight as f64 / 100.).collect()),
+					("peak", [level]) => conversion.normalization = Some(Normalization::Peak(*level as f64)),
+					("rms", [level]) => conversion.normalization = Some(Normalization::Rms(*level as f64)),
+					("nodirectory" | "autotrim" | "dither", _) => return Err(invalid_arguments(&option)),
+					("channel", _) => return Err(invalid_arguments("channel(<index>)")),
+					("mix", _) => return Err(invalid_arguments("mix(<percent>, <percent>, ...)")),
+					("peak" | "rms", _) => return Err(invalid_arguments(&format!("{option}(<dBFS>)"))),
 					_ =>
 						return Err(AssemblyError::InvalidDirectiveOption {
 							directive_location,
 							option_location,
 							option: option.clone(),
 							directive: "brr".into(),
-							valid_options: vec![shared_str!("nodirectory"), shared_str!("autotrim")],
+							valid_options: vec![
+								shared_str!("nodirectory"),
+								shared_str!("autotrim"),
+								shared_str!("channel"),
+								shared_str!("mix"),
+								shared_str!("peak"),
+								shared_str!("rms"),
+								shared_str!("dither"),
+							],
 							src: source_code.clone(),
 						}),
 				}
@@ -30,7 +30,7 @@ pub enum Token {
 	/// Literal number which was already parsed.
 	Number(i64, SharedStr, SourceSpan),
 	/// Text string delimited by "".
-	String(Vec<u8>, SourceSpan),
+	String(SharedStr, SourceSpan),
 	/// '#'
 	Hash(SourceOffset),
 	/// ','
@@ -73,6 +73,8 @@ pub enum Token {
 	Period(SourceOffset),
 	/// '<'
 	OpenAngleBracket(SourceOffset),
+	/// '<' before an instruction operand, forcing direct page addressing.
+	DirectPagePrefix(SourceOffset),
 	/// '<<'
 	DoubleOpenAngleBracket(SourceSpan),
 	/// '>'
@@ -91,6 +93,8 @@ pub enum Token {
 	OpenAngleBracketEquals(SourceSpan),
 	/// '<='
 	CloseAngleBracketEquals(SourceSpan),
+	/// '!'
+	Exclamation(SourceOffset),
 	/// '!='
 	ExclamationEquals(SourceSpan),
 	/// '=='
@@ -141,6 +145,8 @@ impl PartialEq for Token {
 			| (Self::DoubleStar(..), Self::DoubleStar(..))
 			| (Self::RangeMinus(..), Self::RangeMinus(..))
 			| (Self::Slash(..), Self::Slash(..))
+			| (Self::DirectPagePrefix(..), Self::DirectPagePrefix(..))
+			| (Self::Exclamation(..), Self::Exclamation(..))
 			| (Self::Star(..), Self::Star(..))
 			| (Self::Newline(..), Self::Newline(..))
 			| (Self::ExplicitDirectPage(..), Self::ExplicitDirectPage(..))
@@ -178,6 +184,8 @@ impl Token {
 			| Self::CloseAngleBracket(location)
 			| Self::Percent(location)
 			| Self::Slash(location)
+			| Self::DirectPagePrefix(location)
+			| Self::Exclamation(location)
 			| Self::Pipe(location)
 			| Self::Tilde(location)
 			| Self::Caret(location)
@@ -206,6 +214,59 @@ impl Token {
 		}
 	}
 
+	/// Returns a copy of this token that is located at the given span instead. Tokens that only store an offset are
+	/// moved to the start of the span.
+	#[must_use]
+	pub fn relocated(&self, span: SourceSpan) -> Self {
+		let offset: SourceOffset = span.offset().into();
+		match self.clone() {
+			Self::Hash(_) => Self::Hash(offset),
+			Self::CloseParenthesis(_) => Self::CloseParenthesis(offset),
+			Self::CloseIndexingParenthesis(_) => Self::CloseIndexingParenthesis(offset),
+			Self::Colon(_) => Self::Colon(offset),
+			Self::Comma(_) => Self::Comma(offset),
+			Self::Equals(_) => Self::Equals(offset),
+			Self::Star(_) => Self::Star(offset),
+			Self::Minus(_) => Self::Minus(offset),
+			Self::RangeMinus(_) => Self::RangeMinus(offset),
+			Self::Newline(_) => Self::Newline(offset),
+			Self::OpenParenthesis(_) => Self::OpenParenthesis(offset),
+			Self::OpenIndexingParenthesis(_) => Self::OpenIndexingParenthesis(offset),
+			Self::Period(_) => Self::Period(offset),
+			Self::OpenAngleBracket(_) => Self::OpenAngleBracket(offset),
+			Self::CloseAngleBracket(_) => Self::CloseAngleBracket(offset),
+			Self::Percent(_) => Self::Percent(offset),
+			Self::Slash(_) => Self::Slash(offset),
+			Self::DirectPagePrefix(_) => Self::DirectPagePrefix(offset),
+			Self::Exclamation(_) => Self::Exclamation(offset),
+			Self::Pipe(_) => Self::Pipe(offset),
+			Self::Tilde(_) => Self::Tilde(offset),
+			Self::Caret(_) => Self::Caret(offset),
+			Self::Ampersand(_) => Self::Ampersand(offset),
+			Self::Plus(_) => Self::Plus(offset),
+			Self::Identifier(name, _) => Self::Identifier(name, span),
+			Self::ExplicitDirectPage(_) => Self::ExplicitDirectPage(span),
+			Self::ExplicitNoDirectPage(_) => Self::ExplicitNoDirectPage(span),
+			Self::Number(value, text, _) => Self::Number(value, text, span),
+			Self::Register(register, _) => Self::Register(register, span),
+			Self::String(text, _) => Self::String(text, span),
+			Self::PlusRegister(register, _) => Self::PlusRegister(register, span),
+			Self::DoubleStar(_) => Self::DoubleStar(span),
+			Self::DoubleOpenAngleBracket(_) => Self::DoubleOpenAngleBracket(span),
+			Self::DoubleCloseAngleBracket(_) => Self::DoubleCloseAngleBracket(span),
+			Self::OpenAngleBracketEquals(_) => Self::OpenAngleBracketEquals(span),
+			Self::CloseAngleBracketEquals(_) => Self::CloseAngleBracketEquals(span),
+			Self::DoubleEquals(_) => Self::DoubleEquals(span),
+			Self::ExclamationEquals(_) => Self::ExclamationEquals(span),
+			Self::RelativeLabelMinus(count, _) => Self::RelativeLabelMinus(count, span),
+			Self::RelativeLabelPlus(count, _) => Self::RelativeLabelPlus(count, span),
+			Self::Mnemonic(mnemonic, _) => Self::Mnemonic(mnemonic, span),
+			Self::SpecialIdentifier(name, _) => Self::SpecialIdentifier(name, span),
+			Self::Directive(symbol, _) => Self::Directive(symbol, span),
+			Self::TestComment(contents, _) => Self::TestComment(contents, span),
+		}
+	}
+
 	/// Parse a special identifier, mainly validating that the identifier given is one of the special identifiers.
 	///
 	/// # Errors
@@ -262,12 +323,13 @@ impl Display for Token {
 			Self::Star(..) => "'*'".to_string(),
 			Self::Tilde(..) => "'~'".to_string(),
 			Self::DoubleStar(..) => "'**'".to_string(),
-			Self::OpenAngleBracket(..) => "'<'".to_string(),
+			Self::OpenAngleBracket(..) | Self::DirectPagePrefix(..) => "'<'".to_string(),
 			Self::CloseAngleBracket(..) => "'>'".to_string(),
 			Self::DoubleOpenAngleBracket(..) => "'<<'".to_string(),
 			Self::DoubleCloseAngleBracket(..) => "'>>'".to_string(),
 			Self::OpenAngleBracketEquals(..) => "'>='".to_string(),
 			Self::CloseAngleBracketEquals(..) => "'<='".to_string(),
+			Self::Exclamation(..) => "'!'".to_string(),
 			Self::ExclamationEquals(..) => "'!='".to_string(),
 			Self::DoubleEquals(..) => "'=='".to_string(),
 			Self::Percent(..) => "'%'".to_string(),
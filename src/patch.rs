@@ -0,0 +1,156 @@
+//! Patching assembled data into existing memory images.
+#![allow(clippy::module_name_repetitions)]
+
+use std::fmt::Display;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[allow(unused)]
+use flexstr::{shared_str, IntoSharedStr, SharedStr, ToSharedStr};
+use object::elf::{ELFMAG, PT_LOAD};
+use object::read::elf::{ElfFile32, ProgramHeader};
+
+use crate::cli::Frontend;
+use crate::sema::instruction::MemoryAddress;
+use crate::{pretty_hex, AssemblyCode, AssemblyError, Segments};
+
+/// Magic string at the start of every .spc file.
+const SPC_MAGIC: &[u8] = b"SNES-SPC700 Sound File Data";
+/// Offset of the RAM contents within a .spc file.
+const SPC_RAM_OFFSET: usize = 0x100;
+/// Size of the SPC700 address space, which is also the size of the RAM contents of a .spc file.
+const ADDRESS_SPACE_SIZE: MemoryAddress = 0x10000;
+
+/// Container format of a base image.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ImageFormat {
+	/// A plain memory image, where every file offset is the memory address.
+	Raw,
+	/// An ELF file, whose loadable program segments map memory addresses to file offsets.
+	Elf,
+	/// A .spc file, which contains the entire RAM.
+	Spc,
+}
+
+impl Display for ImageFormat {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.pad(match self {
+			Self::Raw => "raw",
+			Self::Elf => "ELF",
+			Self::Spc => ".spc",
+		})
+	}
+}
+
+/// An existing memory image, such as a RAM dump or a sound engine, which assembled data is patched into.
+///
+/// The image keeps its original container format. Patching only replaces the bytes of the memory addresses that the
+/// assembled segments cover, and everything else in the file stays untouched.
+#[derive(Debug, Clone)]
+pub struct BaseImage {
+	/// Path of the image file.
+	path:    PathBuf,
+	/// Detected container format of the image.
+	format:  ImageFormat,
+	/// Contents of the image file.
+	data:    Vec<u8>,
+	/// The memory regions contained in the image, together with the file offset where each region starts.
+	regions: Vec<(Range<MemoryAddress>, usize)>,
+}
+
+impl BaseImage {
+	/// Reads a base image from a file. The format of the image is detected from its contents.
+	///
+	/// # Errors
+	/// If the file can't be read, or if it is not a valid file of the detected format.
+	#[allow(clippy::cast_possible_truncation)]
+	pub fn from_file(path: &Path) -> Result<Self, Box<AssemblyError>> {
+		let data = std::fs::read(path).map_err(AssemblyError::from)?;
+		let invalid = |format: ImageFormat, reason: SharedStr| AssemblyError::InvalidBaseImage {
+			file_name: AssemblyCode::file_name_for(path),
+			format: format.to_string().into(),
+			reason,
+		};
+
+		let (format, regions) = if data.starts_with(SPC_MAGIC) {
+			// The parser's error contains the entire remaining file, which is not useful to print.
+			spcfile::parser::parse_from_bytes(&data)
+				.map_err(|_| invalid(ImageFormat::Spc, "the file is truncated or malformed".into()))?;
+			(ImageFormat::Spc, vec![(0 .. ADDRESS_SPACE_SIZE, SPC_RAM_OFFSET)])
+		} else if data.starts_with(&ELFMAG) {
+			let elf = ElfFile32::<object::Endianness>::parse(&*data)
+				.map_err(|error| invalid(ImageFormat::Elf, error.to_string().into()))?;
+			let endian = elf.endian();
+			// Patches are placed by their physical address, just like the loader would place the data.
+			let regions = elf
+				.elf_program_headers()
+				.iter()
+				.filter(|header| header.p_type(endian) == PT_LOAD)
+				.map(|header| {
+					let start = MemoryAddress::from(header.p_paddr(endian));
+					(start .. start + MemoryAddress::from(header.p_filesz(endian)), header.p_offset(endian) as usize)
+				})
+				.collect();
+			(ImageFormat::Elf, regions)
+		} else {
+			// Raw images grow as necessary, just like the plain output format.
+			(ImageFormat::Raw, vec![(0 .. ADDRESS_SPACE_SIZE, 0)])
+		};
+
+		Ok(Self { path: path.to_owned(), format, data, regions })
+	}
+
+	/// Returns the container format of this image.
+	#[must_use]
+	pub const fn format(&self) -> ImageFormat {
+		self.format
+	}
+
+	/// Returns the (patched) contents of the image file.
+	#[must_use]
+	pub fn data(&self) -> &[u8] {
+		&self.data
+	}
+
+	/// Overlays the assembled segments onto this image. A warning is reported for every segment that changes the data
+	/// of the image, showing the original and the new bytes.
+	///
+	/// # Errors
+	/// If a segment is not entirely within one of the memory regions contained in the image.
+	#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss, clippy::cast_possible_wrap)]
+	pub fn apply(&mut self, segments: &Segments<u8>, options: &dyn Frontend) -> Result<(), Box<AssemblyError>> {
+		for (start, contents) in segments.segments.iter().filter(|(_, contents)| !contents.is_empty()) {
+			let end = start + contents.len() as MemoryAddress;
+			let (region, region_offset) =
+				self.regions.iter().find(|(region, _)| region.start <= *start && end <= region.end).ok_or_else(
+					|| AssemblyError::PatchOutsideBaseImage {
+						start:     *start,
+						end:       end - 1,
+						file_name: AssemblyCode::file_name_for(&self.path),
+						format:    self.format.to_string().into(),
+					},
+				)?;
+			let offset = region_offset + (start - region.start) as usize;
+			if self.data.len() < offset + contents.len() {
+				self.data.resize(offset + contents.len(), 0);
+			}
+
+			let original = &mut self.data[offset .. offset + contents.len()];
+			if original != contents.as_slice() {
+				let original_text = pretty_hex(original, None);
+				let new_text = pretty_hex(contents, None);
+				let text = format!("original:\n{original_text}new:\n{new_text}");
+				options.report_diagnostic(AssemblyError::PatchOverwritesData {
+					address:  *start,
+					length:   contents.len(),
+					// Don't include the final newline.
+					location: (text.len() - new_text.len(), new_text.len() - 1).into(),
+					src:      Arc::new(AssemblyCode::new_from_path(&text, &self.path)),
+				});
+			}
+			original.copy_from_slice(contents);
+		}
+		Ok(())
+	}
+}
@@ -25,6 +25,10 @@ pub struct Segments<Contained> {
 	pub current_segment_start: Option<MemoryAddress>,
 	/// The stack of saved segments, manipulated with pushpc/pullpc.
 	pub segment_stack:         Vec<MemoryAddress>,
+	/// The logical start addresses of segments that are assembled for a different address than the one they are
+	/// stored at, as set up by the `base` directive. The keys are the (physical) segment starts; segments without an
+	/// entry have the same logical and physical address.
+	pub logical_starts:        BTreeMap<MemoryAddress, MemoryAddress>,
 	/// Current contents of the BRR sample table.
 	pub sample_table:          SampleTable,
 	/// Current state of the directive parameters.
@@ -60,10 +64,32 @@ impl<Contained> Segments<Contained> {
 	#[inline]
 	pub fn new_segment(&mut self, segment_start: MemoryAddress) -> &mut Self {
 		self.segments.insert(segment_start, Vec::new());
+		self.logical_starts.remove(&segment_start);
 		self.current_segment_start = Some(segment_start);
 		self
 	}
 
+	/// Starts a new segment at the current memory location, which is assembled for the given logical address. If no
+	/// logical address is given, the new segment's logical address is its physical address.
+	///
+	/// # Errors
+	/// If there is no current segment.
+	pub fn new_logical_segment(&mut self, logical_start: Option<MemoryAddress>) -> Result<(), ()> {
+		let segment_start = self.current_location()?;
+		self.new_segment(segment_start);
+		if let Some(logical_start) = logical_start {
+			self.logical_starts.insert(segment_start, logical_start);
+		}
+		Ok(())
+	}
+
+	/// Returns the logical start address of the segment with the given (physical) start address. This is the address
+	/// that labels in the segment are relative to.
+	#[must_use]
+	pub fn logical_start(&self, segment_start: MemoryAddress) -> MemoryAddress {
+		self.logical_starts.get(&segment_start).copied().unwrap_or(segment_start)
+	}
+
 	/// Returns the current memory location where data is written to.
 	/// # Errors
 	/// If this assembly data doesn't have a started segment yet, or the start address overflowed (unlikely).
@@ -74,6 +100,16 @@ impl<Contained> Segments<Contained> {
 			+ self.current_segment_start.unwrap())
 	}
 
+	/// Returns the current logical memory location, which is the address that labels at this location refer to. This
+	/// only differs from the current memory location if the code is assembled for another address with `base`.
+	/// # Errors
+	/// If this assembly data doesn't have a started segment yet.
+	#[inline]
+	pub fn current_logical_location(&self) -> Result<MemoryAddress, ()> {
+		let segment_start = self.current_segment_start.ok_or(())?;
+		Ok(self.current_location()? - segment_start + self.logical_start(segment_start))
+	}
+
 	/// Returns a mutable reference to the data of the current segment.
 	/// # Errors
 	/// If this assembly data doesn't have a started segment yet.
@@ -101,6 +137,7 @@ impl<Contained> Segments<Contained> {
 		Ok(Segments::<Output> {
 			current_segment_start: self.current_segment_start,
 			segment_stack:         self.segment_stack,
+			logical_starts:        self.logical_starts,
 			sample_table:          self.sample_table,
 			directive_parameters:  self.directive_parameters,
 			segments:              self
@@ -120,6 +157,7 @@ impl<Contained> Default for Segments<Contained> {
 			segments:              BTreeMap::default(),
 			current_segment_start: None,
 			segment_stack:         Vec::default(),
+			logical_starts:        BTreeMap::default(),
 			sample_table:          SampleTable::default(),
 			directive_parameters:  DirectiveParameterTable::default(),
 		}
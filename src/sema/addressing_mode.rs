@@ -190,19 +190,25 @@ impl AddressingMode {
 	/// simplifies and improves later optimization steps.
 	#[must_use]
 	pub fn optimize_numbers(self) -> Self {
+		self.map_numbers(AssemblyTimeValue::try_resolve)
+	}
+
+	/// Applies the given function to all numbers in this addressing mode.
+	#[must_use]
+	pub fn map_numbers(self, function: impl Fn(AssemblyTimeValue) -> AssemblyTimeValue) -> Self {
 		match self {
-			Self::Immediate(number) => Self::Immediate(number.try_resolve()),
-			Self::DirectPage(number) => Self::DirectPage(number.try_resolve()),
-			Self::DirectPageXIndexed(number) => Self::DirectPageXIndexed(number.try_resolve()),
-			Self::DirectPageYIndexed(number) => Self::DirectPageYIndexed(number.try_resolve()),
-			Self::Address(number) => Self::Address(number.try_resolve()),
-			Self::XIndexed(number) => Self::XIndexed(number.try_resolve()),
-			Self::YIndexed(number) => Self::YIndexed(number.try_resolve()),
-			Self::DirectPageXIndexedIndirect(number) => Self::DirectPageXIndexedIndirect(number.try_resolve()),
-			Self::DirectPageIndirectYIndexed(number) => Self::DirectPageIndirectYIndexed(number.try_resolve()),
-			Self::DirectPageBit(number, bit) => Self::DirectPageBit(number.try_resolve(), bit),
-			Self::AddressBit(number, bit) => Self::AddressBit(number.try_resolve(), bit),
-			Self::NegatedAddressBit(number, bit) => Self::NegatedAddressBit(number.try_resolve(), bit),
+			Self::Immediate(number) => Self::Immediate(function(number)),
+			Self::DirectPage(number) => Self::DirectPage(function(number)),
+			Self::DirectPageXIndexed(number) => Self::DirectPageXIndexed(function(number)),
+			Self::DirectPageYIndexed(number) => Self::DirectPageYIndexed(function(number)),
+			Self::Address(number) => Self::Address(function(number)),
+			Self::XIndexed(number) => Self::XIndexed(function(number)),
+			Self::YIndexed(number) => Self::YIndexed(function(number)),
+			Self::DirectPageXIndexedIndirect(number) => Self::DirectPageXIndexedIndirect(function(number)),
+			Self::DirectPageIndirectYIndexed(number) => Self::DirectPageIndirectYIndexed(function(number)),
+			Self::DirectPageBit(number, bit) => Self::DirectPageBit(function(number), bit),
+			Self::AddressBit(number, bit) => Self::AddressBit(function(number), bit),
+			Self::NegatedAddressBit(number, bit) => Self::NegatedAddressBit(function(number), bit),
 			Self::Register(_) | Self::IndirectY | Self::IndirectX | Self::IndirectXAutoIncrement | Self::CarryFlag =>
 				self,
 		}
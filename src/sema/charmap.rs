@@ -0,0 +1,281 @@
+//! [`Charmap`].
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[allow(unused)]
+use flexstr::{shared_str, IntoSharedStr, SharedStr, ToSharedStr};
+use miette::SourceSpan;
+
+use super::instruction::{Instruction, MemoryAddress};
+use super::value::SizedAssemblyTimeValue;
+use super::{AssemblyTimeValue, ProgramElement};
+use crate::cli::Frontend;
+use crate::directive::{DirectiveSymbol, DirectiveValue, PrintPart};
+use crate::error::AssemblyError;
+use crate::{AssemblyCode, Directive};
+
+/// A change to the character map, made by the `charmap`, `table` and `cleartable` directives.
+#[derive(Clone, Debug)]
+pub enum CharmapChange {
+	/// Maps the character sequence to the bytes given by the values, as done by `charmap "<text>", <bytes>`.
+	Insert {
+		/// The mapped character sequence.
+		sequence: SharedStr,
+		/// The bytes that the sequence is encoded as. These must be constant.
+		values:   Vec<AssemblyTimeValue>,
+	},
+	/// Replaces the entire character map, as done by `charmap load`, `charmap clear`, `table` and `cleartable`.
+	Replace(Charmap),
+}
+
+/// Character map that encodes string and character literals, as defined by the `charmap`, `table` and `cleartable`
+/// directives.
+///
+/// The character map maps sequences of one or more characters to byte sequences. Text is encoded by repeatedly
+/// replacing the longest mapped sequence at the current position. Characters that are not part of a mapped sequence
+/// are encoded as their code point truncated to a byte, which is also how text is encoded without a character map.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Charmap {
+	/// The mapped character sequences and their encoded bytes.
+	mappings:         HashMap<SharedStr, Vec<u8>>,
+	/// Number of characters in the longest mapped sequence.
+	longest_sequence: usize,
+}
+
+impl Charmap {
+	/// Parses a table file in the format used by Asar and many other ROM hacking tools. Every line has the form
+	/// `XX=text`, which maps the text to the bytes given in hexadecimal. Any number of bytes can be given, so `0102=ab`
+	/// encodes "ab" as the bytes 01 and 02. Empty lines are ignored.
+	///
+	/// # Errors
+	/// If a line is not a valid table entry, its location and the reason are returned.
+	///
+	/// # Panics
+	/// All panics are programming bugs.
+	pub fn from_table(text: &str) -> Result<Self, (SourceSpan, SharedStr)> {
+		let mut charmap = Self::default();
+		let mut line_start = 0;
+		for line in text.split('\n') {
+			let location = (line_start, line.len()).into();
+			line_start += line.len() + 1;
+			if line.trim().is_empty() {
+				continue;
+			}
+
+			let (bytes, sequence) = line.split_once('=').ok_or_else(|| (location, shared_str!("missing `=`")))?;
+			let bytes = bytes.trim();
+			if bytes.is_empty() || bytes.len() % 2 != 0 || !bytes.chars().all(|chr| chr.is_ascii_hexdigit()) {
+				return Err((location, format!("`{bytes}` is not a sequence of hexadecimal bytes").into()));
+			}
+			if sequence.is_empty() {
+				return Err((location, shared_str!("the text is empty")));
+			}
+			let bytes =
+				(0 .. bytes.len()).step_by(2).map(|index| u8::from_str_radix(&bytes[index ..= index + 1], 16).unwrap());
+			charmap.insert(sequence.into(), bytes.collect());
+		}
+		Ok(charmap)
+	}
+
+	/// Maps the given character sequence to the given bytes, replacing any previous mapping of the sequence.
+	pub fn insert(&mut self, sequence: SharedStr, bytes: Vec<u8>) {
+		self.longest_sequence = self.longest_sequence.max(sequence.chars().count());
+		self.mappings.insert(sequence, bytes);
+	}
+
+	/// Encodes the given text into bytes.
+	#[must_use]
+	pub fn encode(&self, text: &str) -> Vec<u8> {
+		let chars = text.chars().collect::<Vec<_>>();
+		let mut encoded = Vec::with_capacity(chars.len());
+		let mut index = 0;
+		while index < chars.len() {
+			let longest_match = (1 ..= self.longest_sequence.min(chars.len() - index)).rev().find_map(|length| {
+				let sequence = chars[index .. index + length].iter().collect::<String>();
+				self.mappings.get(sequence.as_str()).map(|bytes| (length, bytes))
+			});
+			if let Some((length, bytes)) = longest_match {
+				encoded.extend_from_slice(bytes);
+				index += length;
+			} else {
+				encoded.push(chars[index] as u8);
+				index += 1;
+			}
+		}
+		encoded
+	}
+
+	/// Encodes the value of a character literal. If the character is mapped, its bytes are combined into a little
+	/// endian number; otherwise, the character's code point is returned unchanged.
+	#[must_use]
+	pub fn encode_character(&self, value: MemoryAddress) -> MemoryAddress {
+		u32::try_from(value)
+			.ok()
+			.and_then(char::from_u32)
+			.and_then(|chr| self.mappings.get(chr.to_string().as_str()))
+			.map_or(value, |bytes| {
+				bytes.iter().rev().fold(0, |number, byte| (number << 8) | MemoryAddress::from(*byte))
+			})
+	}
+
+	/// Encodes all text in the given program elements in source code order, while applying the character map changes
+	/// of the elements to this character map. Both blocks of a conditional start with the character map that is active
+	/// before the conditional, and only the changes of the block that is assembled remain active after it. The bodies
+	/// of user-defined macros are skipped, since their text is encoded wherever the macros are expanded.
+	///
+	/// # Errors
+	/// If the bytes of a character map entry are not constant, or if the character map changes differently in the two
+	/// blocks of a conditional whose condition is not known yet.
+	pub(crate) fn encode_program(
+		&mut self,
+		elements: &mut [ProgramElement],
+		source_code: &Arc<AssemblyCode>,
+		options: &dyn Frontend,
+	) -> Result<(), Box<AssemblyError>> {
+		for element in elements {
+			match element {
+				ProgramElement::Instruction(Instruction { opcode, source_operands, .. }) => {
+					let encode = |mut value: AssemblyTimeValue| {
+						value.encode_text(self);
+						value
+					};
+					for operand in [
+						&mut opcode.first_operand,
+						&mut opcode.second_operand,
+						&mut source_operands.0,
+						&mut source_operands.1,
+					] {
+						*operand = operand.take().map(|operand| operand.map_numbers(encode));
+					}
+				},
+				ProgramElement::Directive(directive) => self.encode_directive(directive, source_code, options)?,
+				ProgramElement::Label(_)
+				| ProgramElement::IncludeSource { .. }
+				| ProgramElement::UserDefinedMacroCall { .. } => (),
+			}
+		}
+		Ok(())
+	}
+
+	/// Encodes all text in the given directive, or applies the character map change of the directive.
+	#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss, clippy::too_many_lines)]
+	fn encode_directive(
+		&mut self,
+		directive: &mut Directive,
+		source_code: &Arc<AssemblyCode>,
+		options: &dyn Frontend,
+	) -> Result<(), Box<AssemblyError>> {
+		match &mut directive.value {
+			DirectiveValue::Charmap(CharmapChange::Insert { sequence, values }) => {
+				let mut bytes = Vec::with_capacity(values.len());
+				for value in values {
+					value.encode_text(self);
+					let number = value.try_value(directive.span, source_code).map_err(|_| {
+						AssemblyError::ReferencesInDirectiveArgument {
+							directive:         DirectiveSymbol::Charmap,
+							src:               source_code.clone(),
+							location:          directive.span,
+							argument_location: value.source_span(),
+						}
+					})?;
+					if u8::try_from(number).is_err() {
+						options.report_diagnostic(AssemblyError::ValueTooLarge {
+							value:    number,
+							size:     8,
+							location: value.source_span(),
+							src:      source_code.clone(),
+						});
+					}
+					bytes.push(number as u8);
+				}
+				self.insert(sequence.clone(), bytes);
+			},
+			DirectiveValue::Charmap(CharmapChange::Replace(charmap)) => self.clone_from(charmap),
+			DirectiveValue::String { text, encoded, .. } => *encoded = self.encode(text),
+			DirectiveValue::Table { values, .. } =>
+				*values = values
+					.drain(..)
+					.flat_map(|SizedAssemblyTimeValue { mut value, size }| {
+						if let AssemblyTimeValue::Text { ref text, is_character: false, span } = value {
+							self.encode(text)
+								.into_iter()
+								.map(|byte| SizedAssemblyTimeValue {
+									value: AssemblyTimeValue::Literal(MemoryAddress::from(byte), span),
+									size,
+								})
+								.collect()
+						} else {
+							value.encode_text(self);
+							vec![SizedAssemblyTimeValue { value, size }]
+						}
+					})
+					.collect(),
+			DirectiveValue::Conditional { condition, true_block, false_block } => {
+				condition.encode_text(self);
+				let mut true_charmap = self.clone();
+				true_charmap.encode_program(true_block, source_code, options)?;
+				let mut false_charmap = self.clone();
+				false_charmap.encode_program(false_block, source_code, options)?;
+				*self = if condition.is_truthy() || true_charmap == false_charmap {
+					true_charmap
+				} else if condition.is_falsy() {
+					false_charmap
+				} else {
+					return Err(AssemblyError::ReferencesInDirectiveArgument {
+						directive:         DirectiveSymbol::If,
+						src:               source_code.clone(),
+						location:          directive.span,
+						argument_location: condition.source_span(),
+					}
+					.into());
+				};
+			},
+			// Definition conditionals were replaced by their taken block before the character map is applied.
+			DirectiveValue::DefinitionConditional { .. } => unreachable!(),
+			DirectiveValue::AssignReference { reference, value } => {
+				let unencoded_value = value.clone();
+				value.encode_text(self);
+				// The label received the unencoded value while parsing.
+				if *value != unencoded_value {
+					reference.set_location(value.clone());
+				}
+			},
+			DirectiveValue::Fill { parameter, value, .. } => {
+				parameter.encode_text(self);
+				if let Some(value) = value {
+					value.value.encode_text(self);
+				}
+			},
+			DirectiveValue::SetDirectiveParameters(parameters) =>
+				for value in parameters.values_mut() {
+					value.encode_text(self);
+				},
+			DirectiveValue::Skip { amount: value }
+			| DirectiveValue::WarnPc { limit: value }
+			| DirectiveValue::Assert { condition: value, .. }
+			| DirectiveValue::EndSpcBlock { execute: Some(value) } => value.encode_text(self),
+			DirectiveValue::Print { parts } =>
+				for value in parts.iter_mut().filter_map(PrintPart::value_mut) {
+					value.encode_text(self);
+				},
+			DirectiveValue::Placeholder
+			| DirectiveValue::Org(_)
+			| DirectiveValue::Brr { .. }
+			| DirectiveValue::SampleTable { .. }
+			| DirectiveValue::Include { .. }
+			| DirectiveValue::End
+			| DirectiveValue::PushSection
+			| DirectiveValue::PopSection
+			| DirectiveValue::UserDefinedMacro { .. }
+			| DirectiveValue::Startpos
+			| DirectiveValue::StartNamespace { .. }
+			| DirectiveValue::EndNamespace
+			| DirectiveValue::DirectPage { .. }
+			| DirectiveValue::Base { .. }
+			| DirectiveValue::StartSpcBlock(_)
+			| DirectiveValue::EndSpcBlock { execute: None } => (),
+		}
+		Ok(())
+	}
+}
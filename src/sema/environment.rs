@@ -1,6 +1,6 @@
 //! [`Environment`].
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use std::result::Result;
 use std::sync::Arc;
@@ -11,7 +11,7 @@ use miette::SourceSpan;
 use parking_lot::RwLock;
 
 use super::reference::Label;
-use super::{AssemblyFile, LabelUsageKind};
+use super::{AssemblyFile, AssemblyTimeValue, LabelUsageKind};
 use crate::cli::{default_backend_options, Frontend};
 use crate::error::AssemblyError;
 use crate::parser::{lalrpop_adaptor, Token};
@@ -21,11 +21,28 @@ use crate::AssemblyCode;
 #[derive(Debug)]
 pub struct Environment {
 	/// The list of global labels.
-	pub globals: HashMap<SharedStr, Arc<RwLock<Label>>>,
+	pub globals:                             HashMap<SharedStr, Arc<RwLock<Label>>>,
 	/// The files included in this "tree" created by include statements.
-	pub files:   HashMap<PathBuf, Arc<RwLock<AssemblyFile>>>,
+	pub files:                               HashMap<PathBuf, Arc<RwLock<AssemblyFile>>>,
+	/// The files that each parsed file includes directly. Since included files are only parsed once, this is the only
+	/// place that records all files including a file.
+	pub(crate) included_files:               HashMap<PathBuf, HashSet<PathBuf>>,
 	/// Error and warning options passed on the command line.
-	pub options: Arc<dyn Frontend>,
+	pub options:                             Arc<dyn Frontend>,
+	/// Names of the global labels that were predefined by the frontend.
+	pub(crate) predefined:                   HashSet<SharedStr>,
+	/// Names of the user-defined macros that were called somewhere.
+	pub(crate) used_macros:                  HashSet<SharedStr>,
+	/// Whether math expressions are evaluated strictly left-to-right, as with Asar's `math pri off`.
+	pub(crate) left_to_right_math:           bool,
+	/// Asar defines and their replacement tokens. Only used in Asar compatibility mode.
+	defines:                                 HashMap<SharedStr, Vec<Token>>,
+	/// Number of `ifdef` and `ifndef` blocks that the parser is currently within.
+	pub(crate) definition_conditional_depth: usize,
+	/// Names of the global labels that are defined within `ifdef` or `ifndef` blocks. Which of their definitions
+	/// actually apply is only known once the conditionals are evaluated, so they may be defined more than once while
+	/// parsing.
+	pub(crate) conditional_definitions:      HashSet<SharedStr>,
 }
 
 impl Environment {
@@ -33,17 +50,76 @@ impl Environment {
 	#[must_use]
 	pub fn new() -> Arc<RwLock<Self>> {
 		Arc::new(RwLock::new(Self {
-			globals: HashMap::new(),
-			files:   HashMap::new(),
-			options: default_backend_options(),
+			globals:                      HashMap::new(),
+			files:                        HashMap::new(),
+			included_files:               HashMap::new(),
+			options:                      default_backend_options(),
+			predefined:                   HashSet::new(),
+			used_macros:                  HashSet::new(),
+			left_to_right_math:           false,
+			defines:                      HashMap::new(),
+			definition_conditional_depth: 0,
+			conditional_definitions:      HashSet::new(),
 		}))
 	}
 
-	/// Sets the user-provided error options.
+	/// Sets the user-provided error options. This also defines all symbols that the frontend predefines.
 	pub fn set_error_options(&mut self, options: Arc<dyn Frontend>) {
+		for (name, value) in options.predefined_symbols() {
+			// Predefined symbols have no source code, so there is no meaningful span to give them.
+			let label = Label::new_with_definition(name.clone(), (0, 0).into());
+			label.write().location = Some(AssemblyTimeValue::Literal(value, (0, 0).into()));
+			self.globals.insert(name.clone(), label);
+			self.predefined.insert(name);
+		}
+		// Asar evaluates math left-to-right unless `math pri on` is given.
+		self.left_to_right_math = options.asar_compatibility();
 		self.options = options;
 	}
 
+	/// Removes the given changed files as well as all files that include them or that they include, directly or
+	/// through other files, from the list of parsed files, so that they are parsed again the next time they are used.
+	/// Files that are not affected by the change are kept around. The global labels are rebuilt from the predefined
+	/// symbols and the remaining parsed files, since the labels of the removed files will be defined anew while
+	/// parsing them again.
+	pub fn invalidate_files(&mut self, changed_files: &[PathBuf]) {
+		// Files that include a changed file, no matter through which chain of includes.
+		let mut invalidated_files = changed_files.iter().cloned().collect::<HashSet<_>>();
+		let mut unvisited_files = changed_files.to_vec();
+		while let Some(file) = unvisited_files.pop() {
+			for (including_file, included_files) in &self.included_files {
+				if included_files.contains(&file) && invalidated_files.insert(including_file.clone()) {
+					unvisited_files.push(including_file.clone());
+				}
+			}
+		}
+		// Files that a changed file includes, no matter through which chain of includes. They were parsed within the
+		// include chain of the changed file.
+		let mut unvisited_files = changed_files.to_vec();
+		while let Some(file) = unvisited_files.pop() {
+			for included_file in self.included_files.get(&file).into_iter().flatten() {
+				if invalidated_files.insert(included_file.clone()) {
+					unvisited_files.push(included_file.clone());
+				}
+			}
+		}
+		self.files.retain(|name, _| !invalidated_files.contains(name));
+		self.included_files.retain(|name, _| !invalidated_files.contains(name));
+
+		self.globals.clear();
+		self.set_error_options(self.options.clone());
+		for file in self.files.values() {
+			let labels = file.read().defined_global_labels();
+			for label in labels {
+				let name = label.read().name.clone();
+				// Predefined symbols always take precedence over definitions in the source code.
+				if !self.predefined.contains(&name) {
+					self.globals.insert(name, label);
+				}
+			}
+		}
+	}
+
 	/// Searches for an existing parsed file in this environment given that file's source code.
 	/// Note that the source code does not have to be the identical object in memory, it just has to compare equal.
 	/// See [`crate::AssemblyCode::eq`] for the equality semantics of the source code objects.
@@ -82,6 +158,10 @@ impl Environment {
 		tokens: Vec<Token>,
 		source_code: &Arc<AssemblyCode>,
 	) -> Result<Arc<RwLock<AssemblyFile>>, Box<AssemblyError>> {
+		// Report the file before checking whether it was already parsed, so that it is also reported for every
+		// assembler run that reuses a parsed file.
+		this.read_recursive().options.report_file_dependency(&source_code.name);
+
 		#[allow(clippy::significant_drop_in_scrutinee)]
 		if let Some(already_parsed_file) = this.read_recursive().find_file_by_source(source_code)? {
 			// If we're in a cycle, the already parsed file still has unresolved references.
@@ -96,11 +176,26 @@ impl Environment {
 				}
 				.into())
 			} else {
+				// The files included by the already parsed file are not parsed again, so report them here.
+				let environment = this.read_recursive();
+				for file in environment.files.values().filter_map(|file| file.try_read_recursive()) {
+					if file.source_code.include_path.contains(&source_code.name) {
+						environment.options.report_file_dependency(&file.source_code.name);
+					}
+				}
+				drop(environment);
 				Ok(already_parsed_file)
 			};
 		}
 
+		let tokens = if this.read_recursive().options.asar_compatibility() {
+			lalrpop_adaptor::expand_asar_defines(tokens, &mut this.write().defines)
+		} else {
+			tokens
+		};
 		let lexed = lalrpop_adaptor::preprocess_token_stream(tokens);
+		// A previous parse may have stopped with an error inside a definition conditional.
+		this.write().definition_conditional_depth = 0;
 		let lalrpop_lexed = lalrpop_adaptor::LalrpopAdaptor::from(lexed.clone());
 		let program = crate::parser::ProgramParser::new()
 			.parse(this, source_code, lalrpop_lexed)
@@ -123,15 +218,85 @@ impl Environment {
 		// ...once we start including source files here.
 		let mut file = rc_file.write();
 		file.resolve_source_includes()?;
+		// Definedness depends on everything before a definition conditional, including the files that include this one,
+		// so definition conditionals are only evaluated once all files are included into the root file.
+		if source_code.include_path.is_empty() {
+			file.evaluate_definition_conditionals()?;
+		}
 
 		file.expand_user_macros()?;
+		// Macro bodies may contain definition conditionals as well.
+		if source_code.include_path.is_empty() {
+			file.evaluate_definition_conditionals()?;
+		}
 		file.fill_in_reference_links()?;
+		// Included files use the character map of the including file, so the character map is only applied once all
+		// files are included into the root file.
+		if source_code.include_path.is_empty() {
+			file.apply_charmap()?;
+		}
 		file.coerce_to_direct_page_addressing();
 		drop(file);
 
+		// Only the root file sees all uses of labels and macros, including those in files that include other files.
+		if source_code.include_path.is_empty() && this.read_recursive().options.warn_unused() {
+			this.read_recursive().report_unused_symbols(source_code);
+		}
+
 		Ok(rc_file)
 	}
 
+	/// Reports warnings for all global labels and user-defined macros that are defined in the given root file or the
+	/// files it includes, but never used anywhere. Since included files are inlined into the including file, every
+	/// definition is attributed to the most deeply included file that contains it, which is the file where it is
+	/// actually defined.
+	#[allow(clippy::significant_drop_tightening)]
+	fn report_unused_symbols(&self, root: &AssemblyCode) {
+		let mut files = self
+			.files
+			.values()
+			.map(|file| file.read_recursive())
+			.filter(|file| {
+				file.source_code.name == root.name || file.source_code.include_path.last() == Some(&root.name)
+			})
+			.collect::<Vec<_>>();
+		files.sort_by_cached_key(|file| {
+			(std::cmp::Reverse(file.source_code.include_path.len()), file.source_code.name.clone())
+		});
+
+		let mut reported_labels = HashSet::new();
+		let mut reported_macros = HashSet::new();
+		for file in files {
+			for label in file.defined_global_labels() {
+				if !reported_labels.insert(Arc::as_ptr(&label)) {
+					continue;
+				}
+				let label = label.read();
+				if let Some(definition_span) = label.definition_span
+					&& !label.is_used()
+					&& !label.synthetic
+					&& !self.predefined.contains(&label.name)
+				{
+					self.options.report_diagnostic(AssemblyError::UnusedReference {
+						reference: label.name.clone(),
+						location:  definition_span,
+						src:       file.source_code.clone(),
+					});
+				}
+			}
+
+			for (name, location) in file.defined_user_macros() {
+				if reported_macros.insert(name.clone()) && !self.used_macros.contains(&name) {
+					self.options.report_diagnostic(AssemblyError::UnusedUserMacro {
+						name,
+						location,
+						src: file.source_code.clone(),
+					});
+				}
+			}
+		}
+	}
+
 	/// Lookup a global label in this environment, and create it if necessary.
 	///
 	/// # Errors
@@ -149,7 +314,21 @@ impl Environment {
 			// If the caller flags this use of the reference as its definition, we check that this is the first
 			// definition.
 			if usage_kind == LabelUsageKind::AsDefinition {
-				if mutable_matching_reference.has_definition() {
+				// Predefined symbols take precedence over the source code, so the source definition is given a detached
+				// label that nothing else refers to. This allows for default definitions like
+				// `ifndef X / X = 1 / endif`.
+				if self.predefined.contains(name) {
+					return Ok(Label::new_with_definition(name.into(), span));
+				}
+				let is_conditional = self.definition_conditional_depth > 0;
+				if is_conditional {
+					self.conditional_definitions.insert(name.into());
+				}
+				// Redefinitions within definition conditionals are checked once the conditionals are evaluated.
+				if mutable_matching_reference.has_definition()
+					&& !is_conditional
+					&& !self.conditional_definitions.contains(name)
+				{
 					return Err(AssemblyError::RedefinedReference {
 						redefine_location:  span,
 						reference_location: mutable_matching_reference.source_span(),
@@ -164,6 +343,9 @@ impl Environment {
 			drop(mutable_matching_reference);
 			Ok(matching_reference.clone())
 		} else {
+			if usage_kind == LabelUsageKind::AsDefinition && self.definition_conditional_depth > 0 {
+				self.conditional_definitions.insert(name.into());
+			}
 			let new_reference = if usage_kind == LabelUsageKind::AsDefinition {
 				Label::new_with_definition(name.into(), span)
 			} else {
@@ -13,9 +13,10 @@ use super::instruction::{AddressingModeOptimization, Instruction, MemoryAddress,
 use super::reference::{
 	Label, MacroParameters, MacroParent, Reference, ReferenceResolvable, RelativeReferenceDirection,
 };
-use super::{AddressingMode, AssemblyTimeValue, Environment, ProgramElement};
+use super::value::BinaryOperator;
+use super::{AddressingMode, AssemblyTimeValue, Charmap, Environment, ProgramElement};
 use crate::assembler::resolve_file;
-use crate::directive::DirectiveValue;
+use crate::directive::{DirectiveValue, TableLayout};
 use crate::error::AssemblyError;
 use crate::parser::{lex, Token};
 use crate::{AssemblyCode, Change, Directive, Segments};
@@ -105,6 +106,29 @@ impl AssemblyFile {
 			.collect()
 	}
 
+	/// Returns the names and name locations of all user-defined macros in this file.
+	#[must_use]
+	pub(crate) fn defined_user_macros(&self) -> Vec<(SharedStr, SourceSpan)> {
+		self.content
+			.iter()
+			.filter_map(|element| match element {
+				ProgramElement::Directive(Directive {
+					value: DirectiveValue::UserDefinedMacro { name, arguments, .. },
+					..
+				}) => Some((name.clone(), arguments.read().global_label().read().source_span())),
+				_ => None,
+			})
+			.collect()
+	}
+
+	/// Returns all global labels that are defined in this file, including the ones within conditional blocks.
+	#[must_use]
+	pub(crate) fn defined_global_labels(&self) -> Vec<Arc<RwLock<Label>>> {
+		let mut labels = Vec::new();
+		collect_global_labels(&self.content, &mut labels);
+		labels
+	}
+
 	/// Fills in the global label references for all local labels. Existing ones are overwritten, so the labels are
 	/// always consistent.
 	///
@@ -233,27 +257,101 @@ impl AssemblyFile {
 		Ok(())
 	}
 
+	/// Encodes all string and character literals with the character map, which the character map directives change in
+	/// source code order. This needs to be done after all files are included and all macros are expanded.
+	///
+	/// # Errors
+	/// If a character map directive can't be applied.
+	///
+	/// # Panics
+	/// All panics are programming bugs.
+	pub fn apply_charmap(&mut self) -> Result<(), Box<AssemblyError>> {
+		let options =
+			self.parent.upgrade().expect("environment destroyed before assembly file").read_recursive().options.clone();
+		Charmap::default().encode_program(&mut self.content, &self.source_code, &*options)
+	}
+
 	/// Tries to coerce addressing modes to direct page addressing wherever possible. This needs to be done again as the
 	/// unresolved local labels did not provide memory locations before merging.
 	pub fn coerce_to_direct_page_addressing(&mut self) {
 		for element in &mut self.content {
-			if let ProgramElement::Instruction(Instruction { opcode, .. }) = element {
-				if !opcode.can_use_direct_page_addressing() {
-					opcode.addressing_mode_optimization = AddressingModeOptimization::PreventDirectPage;
-				}
+			if let ProgramElement::Instruction(Instruction { opcode, source_operands, .. }) = element {
+				let can_use_direct_page_addressing = opcode.can_use_direct_page_addressing();
 				let Opcode { first_operand, second_operand, addressing_mode_optimization, .. } = opcode;
 
+				// Only the automatic optimization is restricted to possible addressing modes; an explicitly forced
+				// addressing mode is kept even if it is impossible, so that the assembler can report it.
 				let coercion_function = match addressing_mode_optimization {
 					AddressingModeOptimization::ForceDirectPage => AddressingMode::force_to_direct_page_addressing,
 					AddressingModeOptimization::PreventDirectPage => AddressingMode::force_to_wide_addressing,
+					AddressingModeOptimization::Automatic if !can_use_direct_page_addressing =>
+						AddressingMode::force_to_wide_addressing,
 					AddressingModeOptimization::Automatic => AddressingMode::coerce_to_direct_page_addressing,
 				};
+				// Included files were already coerced on their own, at which point they were still unresolved.
+				if source_operands.0.is_none() && source_operands.1.is_none() {
+					*source_operands = (first_operand.clone(), second_operand.clone());
+				}
 				*first_operand = first_operand.clone().map(AddressingMode::optimize_numbers).map(coercion_function);
 				*second_operand = second_operand.clone().map(AddressingMode::optimize_numbers).map(coercion_function);
 			}
 		}
 	}
 
+	/// Defines the `<label>_hi` label for every global label of a split table, which refers to the table's array of
+	/// high bytes. The array starts right after the low bytes, so its offset from the table label is the number of
+	/// entries.
+	///
+	/// # Errors
+	/// If the high byte label is already defined elsewhere.
+	///
+	/// # Panics
+	/// All panics are programming bugs.
+	#[allow(clippy::cast_possible_wrap)]
+	fn define_split_table_high_labels(
+		&self,
+		table_labels: &[Reference],
+		entry_count: usize,
+		span: SourceSpan,
+	) -> Result<(), Box<AssemblyError>> {
+		let environment = self.parent.upgrade().expect("environment destroyed before assembly file");
+		for table_label in table_labels {
+			let Reference::Label(table_label) = table_label else {
+				continue;
+			};
+			if !table_label.read().is_global() {
+				continue;
+			}
+			let name: SharedStr = format!("{}_hi", table_label.read().name).into();
+			let high_label = environment
+				.write()
+				.globals
+				.entry(name.clone())
+				.or_insert_with(|| Label::new_synthetic(name.clone(), span))
+				.clone();
+			let mut high_label = high_label.write();
+			if let Some(definition_span) = high_label.definition_span
+				&& definition_span != span
+			{
+				return Err(AssemblyError::RedefinedReference {
+					reference:          name,
+					reference_location: definition_span,
+					redefine_location:  span,
+					src:                self.source_code.clone(),
+				}
+				.into());
+			}
+			high_label.definition_span = Some(span);
+			high_label.location = Some(AssemblyTimeValue::BinaryOperation {
+				lhs: Box::new(AssemblyTimeValue::Reference(Reference::Label(table_label.clone()), span)),
+				rhs: Box::new(AssemblyTimeValue::Literal(entry_count as MemoryAddress, span)),
+				operator: BinaryOperator::Add,
+				span,
+			});
+		}
+		Ok(())
+	}
+
 	fn to_asm_error<'a>(
 		span: &'a SourceSpan,
 		source_code: &'a Arc<AssemblyCode>,
@@ -315,6 +413,9 @@ impl AssemblyFile {
 							.map_err(Self::to_asm_error(&new_brr_label.read().source_span(), &self.source_code))?;
 						current_labels.push(Reference::Label(new_brr_label));
 					}
+					if let DirectiveValue::Table { values, layout: TableLayout::Split } = &directive.value {
+						self.define_split_table_high_labels(&current_labels, values.len(), directive.span)?;
+					}
 
 					directive.perform_segment_operations_if_necessary(
 						&mut segments,
@@ -385,10 +486,18 @@ impl AssemblyFile {
 		let mut referenced_objects = Vec::<ReferencedObject>::new();
 
 		for (segment_start, segment_contents) in &segments.segments {
-			for (index_in_segment, (element, offset)) in segment_contents
+			for (index_in_segment, (element, element_address)) in segment_contents
 				.iter()
-				.scan(0.into(), |offset: &mut MemoryAddress, element| {
-					let return_value = Some((element, *offset));
+				.scan((0, *segment_start), |(offset, logical_start), element| {
+					// `base` changes the logical address of all following elements.
+					if let ProgramElement::Directive(Directive {
+						value: DirectiveValue::Base { logical_address },
+						..
+					}) = element
+					{
+						*logical_start = logical_address.map_or(*segment_start, |address| address - *offset);
+					}
+					let return_value = Some((element, *logical_start + *offset));
 					*offset += element.assembled_size() as MemoryAddress;
 					return_value
 				})
@@ -396,7 +505,7 @@ impl AssemblyFile {
 			{
 				match element {
 					ProgramElement::Label(label) => referenced_objects.push(ReferencedObject {
-						address:       offset + *segment_start,
+						address:       element_address,
 						segment_start: *segment_start,
 						object:        InstructionOrReference::Reference(label.clone()),
 					}),
@@ -407,7 +516,7 @@ impl AssemblyFile {
 							&& opcode.can_use_direct_page_addressing()
 						{
 							referenced_objects.push(ReferencedObject {
-								address:       offset + *segment_start,
+								address:       element_address,
 								segment_start: *segment_start,
 								object:        InstructionOrReference::Instruction {
 									index_in_segment,
@@ -534,31 +643,12 @@ impl AssemblyFile {
 	///
 	/// # Errors
 	/// All errors from other files are propagated, as well as include cycles.
-	///
-	/// # Panics
-	/// All panics are programming bugs.
 	pub fn resolve_source_includes(&mut self) -> Result<(), Box<AssemblyError>> {
 		let mut index = 0;
 		while index < self.content.len() {
-			let element = self.content[index].clone();
-			if let ProgramElement::IncludeSource { ref file, span } = element {
-				let environment = self.parent.upgrade().expect("parent deleted while we're still parsing");
-				let file: SharedStr = resolve_file(&self.source_code, file).to_string_lossy().as_ref().into();
-				let mut included_code =
-					AssemblyCode::from_file(&file).map_err(|os_error| AssemblyError::FileNotFound {
-						os_error:  Arc::new(os_error),
-						file_name: file,
-						src:       self.source_code.clone(),
-						location:  span,
-					})?;
-				let child_include_path = &mut unsafe { Arc::get_mut_unchecked(&mut included_code) }.include_path;
-				child_include_path.push(self.source_code.name.clone());
-				child_include_path.append(&mut self.source_code.include_path.clone());
-
-				let tokens = lex(included_code.clone(), &*environment.read().options)?;
-				let included_file = Environment::parse(&environment, tokens, &included_code)?;
-
-				self.content.splice(index ..= index, included_file.read().content.clone());
+			if let ProgramElement::IncludeSource { ref file, span } = self.content[index] {
+				let included_content = self.parse_included_file(&file.clone(), span)?;
+				self.content.splice(index ..= index, included_content);
 				continue;
 			}
 			index += 1;
@@ -566,6 +656,169 @@ impl AssemblyFile {
 		Ok(())
 	}
 
+	/// Parses the file included at the given location and returns its content.
+	///
+	/// # Errors
+	/// All errors from the included file are propagated, as well as include cycles.
+	///
+	/// # Panics
+	/// All panics are programming bugs.
+	fn parse_included_file(&self, file: &str, span: SourceSpan) -> Result<Vec<ProgramElement>, Box<AssemblyError>> {
+		let environment = self.parent.upgrade().expect("parent deleted while we're still parsing");
+		let include_directories = environment.read().options.include_directories();
+		let file: SharedStr =
+			resolve_file(&self.source_code, &include_directories, file).to_string_lossy().as_ref().into();
+		let mut included_code = AssemblyCode::from_file(&file).map_err(|os_error| AssemblyError::FileNotFound {
+			os_error:  Arc::new(os_error),
+			file_name: file,
+			src:       self.source_code.clone(),
+			location:  span,
+		})?;
+		let child_include_path = &mut unsafe { Arc::get_mut_unchecked(&mut included_code) }.include_path;
+		child_include_path.push(self.source_code.name.clone());
+		child_include_path.append(&mut self.source_code.include_path.clone());
+
+		environment
+			.write()
+			.included_files
+			.entry(self.source_code.name.clone())
+			.or_default()
+			.insert(included_code.name.clone());
+
+		let tokens = lex(included_code.clone(), &*environment.read().options)?;
+		let included_file = Environment::parse(&environment, tokens, &included_code)?;
+		let included_content = included_file.read().content.clone();
+		Ok(included_content)
+	}
+
+	/// Evaluates all `ifdef` and `ifndef` conditionals in source order and replaces them with their taken block. A
+	/// symbol is defined for a conditional if it is predefined or defined before the conditional, which includes the
+	/// definitions in included files and in the taken blocks of earlier conditionals. Source includes within the
+	/// conditionals are resolved along the way. Labels that are only defined in blocks which are not taken lose their
+	/// definition.
+	///
+	/// # Errors
+	/// If a label is defined more than once in the taken blocks, as well as all errors from included files.
+	///
+	/// # Panics
+	/// All panics are programming bugs.
+	pub(super) fn evaluate_definition_conditionals(&mut self) -> Result<(), Box<AssemblyError>> {
+		let environment = self.parent.upgrade().expect("environment destroyed before assembly file");
+		let mut defined: HashMap<SharedStr, SourceSpan> =
+			environment.read().predefined.iter().map(|name| (name.clone(), (0, 0).into())).collect();
+		let mut dropped_labels = Vec::new();
+
+		let mut content = std::mem::take(&mut self.content);
+		let result = self.evaluate_definition_conditionals_in(&mut content, &mut defined, &mut dropped_labels);
+		self.content = content;
+		result?;
+
+		for label in dropped_labels {
+			let mut label = label.write();
+			if !defined.contains_key(&label.name) {
+				label.definition_span = None;
+				label.location = None;
+			}
+		}
+		Ok(())
+	}
+
+	/// Evaluates the definition conditionals within the given elements, see
+	/// [`Self::evaluate_definition_conditionals`].
+	fn evaluate_definition_conditionals_in(
+		&self,
+		elements: &mut Vec<ProgramElement>,
+		defined: &mut HashMap<SharedStr, SourceSpan>,
+		dropped_labels: &mut Vec<Arc<RwLock<Label>>>,
+	) -> Result<(), Box<AssemblyError>> {
+		let mut index = 0;
+		while index < elements.len() {
+			let element_span = elements[index].span();
+			match &mut elements[index] {
+				ProgramElement::IncludeSource { file, span } => {
+					let (file, span) = (file.clone(), *span);
+					let included_content = self.parse_included_file(&file, span)?;
+					elements.splice(index ..= index, included_content);
+					continue;
+				},
+				ProgramElement::Directive(Directive {
+					value: DirectiveValue::DefinitionConditional { symbol, negated, true_block, false_block },
+					..
+				}) => {
+					let is_defined = match symbol {
+						Reference::Label(label) => defined.contains_key(&label.read().name),
+						_ => false,
+					};
+					let (taken_block, dropped_block) =
+						if is_defined == *negated { (false_block, true_block) } else { (true_block, false_block) };
+					collect_global_labels(dropped_block, dropped_labels);
+					let taken_block = std::mem::take(taken_block);
+					elements.splice(index ..= index, taken_block);
+					continue;
+				},
+				ProgramElement::Directive(Directive {
+					value: DirectiveValue::Conditional { true_block, false_block, .. },
+					..
+				}) => {
+					// Either block may be assembled, so definitions from both count afterwards.
+					let mut true_defined = defined.clone();
+					self.evaluate_definition_conditionals_in(true_block, &mut true_defined, dropped_labels)?;
+					let mut false_defined = defined.clone();
+					self.evaluate_definition_conditionals_in(false_block, &mut false_defined, dropped_labels)?;
+					defined.extend(true_defined);
+					defined.extend(false_defined);
+				},
+				ProgramElement::Label(Reference::Label(label)) => self.define(label, element_span, defined)?,
+				ProgramElement::Directive(Directive {
+					value: DirectiveValue::AssignReference { reference, value },
+					..
+				}) => {
+					// Only the assignments that are taken apply, and the last one in source order wins.
+					reference.set_location(value.clone());
+					if let Reference::Label(label) = reference {
+						self.define(label, element_span, defined)?;
+					}
+				},
+				_ => (),
+			}
+			index += 1;
+		}
+		Ok(())
+	}
+
+	/// Records the definition of a global label while evaluating definition conditionals.
+	///
+	/// # Errors
+	/// If the label was defined before and one of its definitions is within a definition conditional. Other
+	/// redefinitions are reported by the parser.
+	///
+	/// # Panics
+	/// All panics are programming bugs.
+	fn define(
+		&self,
+		label: &Arc<RwLock<Label>>,
+		span: SourceSpan,
+		defined: &mut HashMap<SharedStr, SourceSpan>,
+	) -> Result<(), Box<AssemblyError>> {
+		if !label.read().is_global() {
+			return Ok(());
+		}
+		let name = label.read().name.clone();
+		let environment = self.parent.upgrade().expect("environment destroyed before assembly file");
+		if let Some(previous_span) = defined.insert(name.clone(), span)
+			&& environment.read().conditional_definitions.contains(&name)
+		{
+			return Err(AssemblyError::RedefinedReference {
+				redefine_location:  span,
+				reference_location: previous_span,
+				reference:          name,
+				src:                self.source_code.clone(),
+			}
+			.into());
+		}
+		Ok(())
+	}
+
 	/// Expands calls to user-defined macros.
 	///
 	/// # Errors
@@ -647,6 +900,12 @@ impl AssemblyFile {
 						),
 					);
 					drop(arguments);
+					self.parent
+						.upgrade()
+						.expect("environment destroyed before assembly file")
+						.write()
+						.used_macros
+						.insert(macro_name.clone());
 					// FIXME: Doesn't handle macro-internal references correctly; also no support for the \@ special
 					// label.
 					let mut inserted_body = body.clone();
@@ -682,3 +941,27 @@ impl AssemblyFile {
 		Ok(Change::Unmodified)
 	}
 }
+
+/// Appends all global labels that are defined in the elements to the list, including the ones within conditional
+/// blocks.
+fn collect_global_labels(elements: &[ProgramElement], labels: &mut Vec<Arc<RwLock<Label>>>) {
+	for element in elements {
+		match element {
+			ProgramElement::Label(Reference::Label(label))
+			| ProgramElement::Directive(Directive {
+				value: DirectiveValue::AssignReference { reference: Reference::Label(label), .. },
+				..
+			}) if label.read().is_global() => labels.push(label.clone()),
+			ProgramElement::Directive(Directive {
+				value:
+					DirectiveValue::Conditional { true_block, false_block, .. }
+					| DirectiveValue::DefinitionConditional { true_block, false_block, .. },
+				..
+			}) => {
+				collect_global_labels(true_block, labels);
+				collect_global_labels(false_block, labels);
+			},
+			_ => (),
+		}
+	}
+}
@@ -26,12 +26,15 @@ pub type MemoryAddress = i64;
 #[derive(Clone, Debug)]
 pub struct Instruction {
 	/// Opcode of this instruction (slightly misnamed)
-	pub opcode:         Opcode,
-	pub(crate) span:    SourceSpan,
+	pub opcode:                 Opcode,
+	pub(crate) span:            SourceSpan,
+	/// The first and second operand as they were written in the source code, before semantic analysis resolved their
+	/// values. Analyses that need to know which labels an operand uses look at these.
+	pub(crate) source_operands: (Option<AddressingMode>, Option<AddressingMode>),
 	/// Only used for testing purposes: this is the data that the instruction should assemble to according to the test
 	/// file.
 	#[cfg(test)]
-	pub expected_value: Option<Vec<u8>>,
+	pub expected_value:         Option<Vec<u8>>,
 }
 
 impl Default for Instruction {
@@ -44,6 +47,7 @@ impl Default for Instruction {
 				addressing_mode_optimization: AddressingModeOptimization::default(),
 			},
 			span:                        (0, 0).into(),
+			source_operands:             (None, None),
 			#[cfg(test)]
 			expected_value:              None,
 		}
@@ -98,6 +102,41 @@ impl std::fmt::Display for Instruction {
 	}
 }
 
+/// Instructions that don't exist on the SPC700, but are expanded to a sequence of real instructions by the assembler.
+///
+/// Like real instructions, pseudo-instructions don't modify any registers other than the ones they operate on.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum PseudoInstruction {
+	/// `movw dp, #imm`: Moves the low and high byte of the immediate to `dp` and `dp+1` with two `mov dp, #imm`.
+	MovwDirectPageImmediate,
+	/// `movw ya, #imm`: Moves the low byte of the immediate to A and the high byte to Y.
+	MovwYaImmediate,
+	/// `incw addr`: Increments the word at an absolute address with `inc addr`, and increments the high byte as well
+	/// if the low byte overflowed.
+	IncwAddress,
+	/// `decw addr`: Decrements the word at an absolute address. Whether the low byte is zero and will underflow is
+	/// checked by incrementing and decrementing it, in which case the high byte is decremented first.
+	DecwAddress,
+}
+
+impl PseudoInstruction {
+	/// Returns the assembled size of the instruction sequence that this pseudo-instruction expands to.
+	#[must_use]
+	pub const fn assembled_size(self) -> u8 {
+		match self {
+			// mov dp, #imm ; mov dp+1, #imm
+			Self::MovwDirectPageImmediate => 6,
+			// mov a, #imm ; mov y, #imm
+			Self::MovwYaImmediate => 4,
+			// inc addr ; bne +3 ; inc addr+1
+			Self::IncwAddress => 8,
+			// inc addr ; dec addr ; bne +3 ; dec addr+1 ; dec addr
+			Self::DecwAddress => 14,
+		}
+	}
+}
+
 /// How to optimize the addressing mode
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 #[repr(u8)]
@@ -151,6 +190,9 @@ impl Opcode {
 			| Mnemonic::Dbnz =>
 				1 + self.first_operand.clone().map_or(0, AddressingMode::assembled_size)
 					+ self.second_operand.clone().map_or(0, AddressingMode::assembled_size),
+			// These instructions have pseudo-instruction forms which are longer.
+			Mnemonic::Movw | Mnemonic::Incw | Mnemonic::Decw =>
+				self.pseudo_instruction().map_or(2, PseudoInstruction::assembled_size),
 			// Just to be sure: for these instructions, we know they have a constant size, so we explicitly use that.
 			Mnemonic::Xcn
 			| Mnemonic::Mul
@@ -184,9 +226,6 @@ impl Opcode {
 			| Mnemonic::Bvc
 			| Mnemonic::Bmi
 			| Mnemonic::Bpl
-			| Mnemonic::Movw
-			| Mnemonic::Incw
-			| Mnemonic::Decw
 			| Mnemonic::Addw
 			| Mnemonic::Subw
 			| Mnemonic::Cmpw
@@ -242,6 +281,22 @@ impl Opcode {
 		}
 	}
 
+	/// Returns the pseudo-instruction this opcode represents, if any.
+	#[must_use]
+	pub const fn pseudo_instruction(&self) -> Option<PseudoInstruction> {
+		match (self.mnemonic, &self.first_operand, &self.second_operand) {
+			// The expansion only stores to direct page addresses, so absolute addresses are rejected like they are for
+			// the real MOVW instructions.
+			(Mnemonic::Movw, Some(AddressingMode::DirectPage(_)), Some(AddressingMode::Immediate(_))) =>
+				Some(PseudoInstruction::MovwDirectPageImmediate),
+			(Mnemonic::Movw, Some(AddressingMode::Register(Register::YA)), Some(AddressingMode::Immediate(_))) =>
+				Some(PseudoInstruction::MovwYaImmediate),
+			(Mnemonic::Incw, Some(AddressingMode::Address(_)), None) => Some(PseudoInstruction::IncwAddress),
+			(Mnemonic::Decw, Some(AddressingMode::Address(_)), None) => Some(PseudoInstruction::DecwAddress),
+			_ => None,
+		}
+	}
+
 	/// Returns whether this opcode contains a two-byte "long" address.
 	pub fn has_long_address(&self) -> bool {
 		self.first_operand.clone().is_some_and(AddressingMode::has_long_address)
@@ -514,6 +569,37 @@ impl Mnemonic {
 		}
 	}
 
+	/// Specifies if this mnemonic's instruction never continues execution with the following instruction.
+	#[must_use]
+	pub const fn ends_control_flow(&self) -> bool {
+		matches!(self, Self::Bra | Self::Jmp | Self::Ret | Self::Ret1 | Self::Reti | Self::Stop)
+	}
+
+	/// Specifies if this mnemonic's instruction transfers control to a target address given by its last operand. This
+	/// includes all branches, as well as `jmp` and `call`.
+	#[must_use]
+	pub const fn has_jump_target(&self) -> bool {
+		matches!(
+			self,
+			Self::Bra
+				| Self::Beq | Self::Bne
+				| Self::Bcs | Self::Bcc
+				| Self::Bvs | Self::Bvc
+				| Self::Bmi | Self::Bpl
+				| Self::Bbs | Self::Bbs0
+				| Self::Bbs1 | Self::Bbs2
+				| Self::Bbs3 | Self::Bbs4
+				| Self::Bbs5 | Self::Bbs6
+				| Self::Bbs7 | Self::Bbc
+				| Self::Bbc0 | Self::Bbc1
+				| Self::Bbc2 | Self::Bbc3
+				| Self::Bbc4 | Self::Bbc5
+				| Self::Bbc6 | Self::Bbc7
+				| Self::Cbne | Self::Dbnz
+				| Self::Jmp | Self::Call
+		)
+	}
+
 	/// Specifies if this mnemonic's instruction uses any kind of bit index.
 	#[must_use]
 	pub fn uses_any_bit_index(&self) -> bool {
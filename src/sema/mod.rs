@@ -1,6 +1,7 @@
 //! Semantic analysis and AST datastructures.
 
 mod addressing_mode;
+mod charmap;
 mod environment;
 mod file;
 pub mod instruction;
@@ -11,6 +12,7 @@ mod register;
 pub mod value;
 
 pub use addressing_mode::{AddressingMode, AddressingModeCategory};
+pub use charmap::{Charmap, CharmapChange};
 pub use environment::Environment;
 pub use file::AssemblyFile;
 pub use program::ProgramElement;
@@ -416,6 +416,12 @@ impl Label {
 		self.nesting_count() == 0
 	}
 
+	/// Returns whether the label or any of its (transitive) children is used somewhere. Local labels can only be
+	/// reached through their parent, so using a local label also counts as a use of the parent.
+	pub fn is_used(&self) -> bool {
+		!self.usage_spans.is_empty() || self.children.values().any(|child| child.read().is_used())
+	}
+
 	/// Returns whether the label is defined somewhere.
 	/// Otherwise, the label was just used as part of an expression, such as a jump target or an address to load from.
 	pub const fn has_definition(&self) -> bool {
@@ -2,6 +2,8 @@
 
 use crate::cli::default_backend_options;
 use crate::parser::Token;
+use crate::sema::instruction::MemoryAddress;
+use crate::sema::Charmap;
 use crate::{run_assembler_into_symbolic_segments, AssemblyCode};
 
 #[test]
@@ -35,3 +37,16 @@ fn file_token_reference_lookup() {
 	assert!(!file.read().get_definition_spans_of("thing").is_empty());
 	assert!(!file.read().get_definition_spans_of("some_macro").is_empty());
 }
+
+#[test]
+fn charmap_table() {
+	let charmap = Charmap::from_table("00=A\n\n1A=a\n3F00=ch\n").unwrap();
+	assert_eq!(charmap.encode("Aachx"), [0x00, 0x1A, 0x3F, 0x00, b'x']);
+	assert_eq!(charmap.encode_character(MemoryAddress::from(b'a')), 0x1A);
+	assert_eq!(charmap.encode_character(MemoryAddress::from(b'x')), MemoryAddress::from(b'x'));
+
+	assert_eq!(Charmap::from_table("00=A\n01\n").unwrap_err().0, (5, 2).into());
+	assert_eq!(Charmap::from_table("0=A").unwrap_err().0, (0, 3).into());
+	assert_eq!(Charmap::from_table("0G=A").unwrap_err().0, (0, 4).into());
+	assert_eq!(Charmap::from_table("00=").unwrap_err().0, (0, 3).into());
+}
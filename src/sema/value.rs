@@ -15,6 +15,7 @@ use parking_lot::RwLock;
 
 use super::instruction::MemoryAddress;
 use super::reference::{self, Label, Reference, ReferenceResolvable, RelativeReferenceDirection};
+use super::Charmap;
 use crate::error::AssemblyError;
 use crate::AssemblyCode;
 
@@ -46,6 +47,18 @@ pub enum AssemblyTimeValue {
 		/// The source code location of the operation.
 		span:     SourceSpan,
 	},
+	/// A character literal, or a string in a table directive. The text is encoded with the character map that is
+	/// active at its position in source code order, which is only known once all files are included and all macros
+	/// are expanded; see [`Charmap`].
+	Text {
+		/// The text as written in the source code.
+		text:         SharedStr,
+		/// Whether this is a character literal, which is encoded into a single number. Strings are instead expanded
+		/// into one table entry per encoded byte.
+		is_character: bool,
+		/// The source code location of the text.
+		span:         SourceSpan,
+	},
 }
 
 impl AssemblyTimeValue {
@@ -56,7 +69,8 @@ impl AssemblyTimeValue {
 			Self::Literal(_, span)
 			| Self::Reference(_, span)
 			| Self::UnaryOperation { span, .. }
-			| Self::BinaryOperation { span, .. } => *span,
+			| Self::BinaryOperation { span, .. }
+			| Self::Text { span, .. } => *span,
 		}
 	}
 
@@ -64,7 +78,7 @@ impl AssemblyTimeValue {
 	#[must_use]
 	pub fn first_reference(&self) -> Option<Reference> {
 		match self {
-			Self::Literal(..) => None,
+			Self::Literal(..) | Self::Text { .. } => None,
 			Self::Reference(reference, ..) => Some(reference.clone()),
 			Self::UnaryOperation { inner_value, .. } => inner_value.first_reference(),
 			Self::BinaryOperation { lhs, rhs, .. } => lhs.first_reference().or_else(|| rhs.first_reference()),
@@ -75,7 +89,7 @@ impl AssemblyTimeValue {
 	#[must_use]
 	pub fn references(&self) -> Vec<&Reference> {
 		match self {
-			Self::Literal(..) => Vec::default(),
+			Self::Literal(..) | Self::Text { .. } => Vec::default(),
 			Self::Reference(reference, ..) => vec![reference],
 			Self::UnaryOperation { inner_value, .. } => inner_value.references(),
 			Self::BinaryOperation { lhs, rhs, .. } => {
@@ -166,6 +180,7 @@ impl AssemblyTimeValue {
 				(lhs, rhs) => Self::BinaryOperation { lhs: Box::new(lhs), rhs: Box::new(rhs), operator, span },
 			},
 			Self::Literal(..)
+			| Self::Text { .. }
 			| Self::Reference(
 				Reference::Label(..)
 				| Reference::MacroArgument { value: None, .. }
@@ -207,6 +222,8 @@ impl AssemblyTimeValue {
 		// fundamentally incompatible)
 		match self {
 			Self::Literal(value, ..) => Some(*value),
+			// Unencoded text has no value yet, and a resolver can't provide one either.
+			Self::Text { .. } => None,
 			Self::Reference(ref reference, ..) => match reference {
 				Reference::Label(label) if let Some(ref value) = label.read().location =>
 					value.value_using_resolver(resolver),
@@ -231,6 +248,25 @@ impl AssemblyTimeValue {
 	pub fn is_resolved(&self) -> bool {
 		matches!(self.clone().try_resolve(), Self::Literal(..))
 	}
+
+	/// Encodes all character literals in this value with the given character map. This also includes the values of
+	/// macro arguments, since they are part of the expanded macro's source code.
+	pub(crate) fn encode_text(&mut self, charmap: &Charmap) {
+		match self {
+			Self::Text { text, span, .. } =>
+				*self = Self::Literal(
+					charmap.encode_character(text.chars().next().map_or(0, |chr| MemoryAddress::from(u32::from(chr)))),
+					*span,
+				),
+			Self::Reference(Reference::MacroArgument { value: Some(value), .. }, ..) => value.encode_text(charmap),
+			Self::UnaryOperation { inner_value, .. } => inner_value.encode_text(charmap),
+			Self::BinaryOperation { lhs, rhs, .. } => {
+				lhs.encode_text(charmap);
+				rhs.encode_text(charmap);
+			},
+			Self::Literal(..) | Self::Reference(..) => (),
+		}
+	}
 }
 
 impl ReferenceResolvable for AssemblyTimeValue {
@@ -240,7 +276,7 @@ impl ReferenceResolvable for AssemblyTimeValue {
 		source_code: &Arc<AssemblyCode>,
 	) -> Result<(), Box<AssemblyError>> {
 		match self {
-			Self::Literal(..) => Ok(()),
+			Self::Literal(..) | Self::Text { .. } => Ok(()),
 			Self::Reference(reference @ Reference::MacroGlobal { .. }, ..) => {
 				let new_global = replacement_parent.read().global_label();
 				*reference = Reference::Label(new_global);
@@ -274,7 +310,7 @@ impl ReferenceResolvable for AssemblyTimeValue {
 					*reference = Reference::Label(new_reference);
 				}
 			},
-			Self::Literal(..) => (),
+			Self::Literal(..) | Self::Text { .. } => (),
 			Self::Reference(reference, ..) => reference.resolve_relative_labels(direction, relative_labels),
 			Self::UnaryOperation { inner_value: number, .. } =>
 				number.resolve_relative_labels(direction, relative_labels),
@@ -287,7 +323,7 @@ impl ReferenceResolvable for AssemblyTimeValue {
 
 	fn resolve_pseudo_labels(&mut self, global_labels: &[Arc<RwLock<Label>>]) {
 		match self {
-			Self::Literal(..) => (),
+			Self::Literal(..) | Self::Text { .. } => (),
 			Self::Reference(reference, ..) => reference.resolve_pseudo_labels(global_labels),
 			Self::UnaryOperation { inner_value: number, .. } => number.resolve_pseudo_labels(global_labels),
 			Self::BinaryOperation { lhs, rhs, .. } => {
@@ -310,7 +346,7 @@ impl ReferenceResolvable for AssemblyTimeValue {
 			Self::UnaryOperation { inner_value: val, .. } => val.set_current_label(label, source_code),
 			Self::BinaryOperation { lhs, rhs, .. } =>
 				lhs.set_current_label(label, source_code).and_then(|()| rhs.set_current_label(label, source_code)),
-			Self::Literal(..) => Ok(()),
+			Self::Literal(..) | Self::Text { .. } => Ok(()),
 		}
 	}
 }
@@ -355,6 +391,8 @@ impl UpperHex for AssemblyTimeValue {
 			Self::UnaryOperation { inner_value: number, operator, .. } =>
 				write_correctly(&operator.to_string(), f, number.as_ref()),
 			Self::BinaryOperation { lhs, rhs, operator, .. } => write_binary(&operator.to_string(), f, lhs, rhs),
+			Self::Text { text, is_character: true, .. } => write!(f, "'{text}'"),
+			Self::Text { text, is_character: false, .. } => write!(f, "\"{text}\""),
 		}
 	}
 }
@@ -2,12 +2,19 @@
 
 use std::fs::File;
 use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 #[allow(unused)]
 use flexstr::{shared_str, IntoSharedStr, SharedStr, ToSharedStr};
+use parking_lot::RwLock;
 
+use crate::assembler::{assemble_inside_segments, combine_resolved_segments, EntryPoint};
+use crate::cli::{CliOptions, Frontend, SpcasmCli};
+use crate::patch::BaseImage;
 use crate::{
-	cli, dump_ast, dump_reference_tree, elf, run_assembler, run_assembler_into_segments, AssemblyCode, AssemblyError,
+	cli, dump_ast, dump_reference_tree, elf, run_assembler_into_symbolic_segments, upload, AssemblyCode, AssemblyError,
+	Environment, Segments,
 };
 
 pub fn main() -> miette::Result<()> {
@@ -23,72 +30,140 @@ pub fn main() -> miette::Result<()> {
 
 	let mut args = cli::SpcasmCli::parse();
 	args.warning_flags.expand_all();
-	let file_name = args.input;
+	let options = Arc::new(std::mem::take(&mut args.warning_flags));
 
-	let options = std::sync::Arc::new(args.warning_flags);
-	let code = AssemblyCode::from_file_or_assembly_error(&file_name.to_string_lossy()).map_err(AssemblyError::from)?;
-	// Errors are already reported, so we only need to handle the success case.
-	if let Ok((environment, assembled)) = run_assembler(&code, options.clone()) {
-		if args.dump_references {
-			let mut references = environment.read_recursive().globals.values().cloned().collect::<Vec<_>>();
-			references.sort_by_cached_key(|reference| {
-				reference
-					.read()
-					.location
-					.as_ref()
-					.and_then(|location| {
-						location
-							.try_value(
-								reference.read_recursive().source_span(),
-								&std::sync::Arc::new(AssemblyCode::new("", &String::new())),
-							)
-							.ok()
-					})
-					.map_or_else(|| "(unknown)".to_string(), |location| format!("{location:04X}"))
-			});
-			dump_reference_tree(&references);
-		}
+	if args.watch {
+		crate::watch::watch(&args, &options);
+	}
 
-		if args.dump_ast {
-			dump_ast(&environment.read_recursive().files.get(&code.name).unwrap().read_recursive().content);
+	// Every error goes through the frontend, so that it is printed in the selected message format.
+	let result: Result<(), Box<AssemblyError>> = try {
+		let code = AssemblyCode::from_file_or_assembly_error(&args.input.to_string_lossy())?;
+		let (environment, mut segmented_program) = run_assembler_into_symbolic_segments(&code, options.clone())?;
+		let (assembled, entry_point) = assemble_inside_segments(&mut segmented_program, &code, options.clone())?;
+		dump_debug_output(&args, &environment, &code);
+		// Warnings may have been turned into errors, in which case no output is written.
+		if !*options.had_error.read() {
+			write_output_files(&args, &options, &code, assembled, entry_point)?;
 		}
+	};
+	if let Err(why) = result {
+		options.report_diagnostic_impl(*why);
+	}
+	options.finish_diagnostics();
+	if *options.had_error.read() {
+		std::process::exit(1);
+	}
+	Ok(())
+}
 
-		if *options.had_error.read() {
-			std::process::exit(1);
-		}
+/// Prints the debug information requested on the command line.
+pub fn dump_debug_output(args: &SpcasmCli, environment: &Arc<RwLock<Environment>>, code: &Arc<AssemblyCode>) {
+	if args.dump_references {
+		let mut references = environment.read_recursive().globals.values().cloned().collect::<Vec<_>>();
+		references.sort_by_cached_key(|reference| {
+			reference
+				.read()
+				.location
+				.as_ref()
+				.and_then(|location| {
+					location
+						.try_value(
+							reference.read_recursive().source_span(),
+							&Arc::new(AssemblyCode::new("", &String::new())),
+						)
+						.ok()
+				})
+				.map_or_else(|| "(unknown)".to_string(), |location| format!("{location:04X}"))
+		});
+		dump_reference_tree(&references);
+	}
+
+	if args.dump_ast {
+		dump_ast(&environment.read_recursive().files.get(&code.name).unwrap().read_recursive().content);
+	}
+}
 
-		if let Some(outfile) = args.output {
-			let mut outfile: Box<dyn Write> = if outfile.to_string_lossy() == "-" {
-				Box::new(std::io::stdout())
+/// Writes the dependency file and the output file requested on the command line.
+///
+/// # Errors
+/// I/O errors while writing the files, overlapping segments, a missing entry point for ELF output, as well as invalid
+/// base images and patches outside of them.
+pub fn write_output_files(
+	args: &SpcasmCli,
+	options: &Arc<CliOptions>,
+	code: &Arc<AssemblyCode>,
+	assembled: Segments<u8>,
+	entry_point: EntryPoint,
+) -> Result<(), Box<AssemblyError>> {
+	let combined = combine_resolved_segments(&assembled, code)?;
+	// Read the base image first, so that it is part of the dependency file.
+	let mut base_image = args.patch.as_deref().map(BaseImage::from_file).transpose()?;
+	if let Some(image_path) = &args.patch {
+		options.report_file_dependency(image_path);
+	}
+
+	if let Some(depfile) = &args.depfile {
+		let target = args.output.as_ref().unwrap_or(&args.input);
+		write_depfile(depfile, target, &options.dependencies.read()).map_err(AssemblyError::from)?;
+	}
+
+	if let Some(outfile) = &args.output {
+		let mut outfile: Box<dyn Write> = if outfile.to_string_lossy() == "-" {
+			Box::new(std::io::stdout())
+		} else {
+			Box::new(std::io::BufWriter::new(
+				File::options().create(true).truncate(true).write(true).open(outfile).map_err(AssemblyError::from)?,
+			))
+		};
+		if let Some(base_image) = &mut base_image {
+			base_image.apply(&assembled, &**options)?;
+			// Warnings about overwritten data may have been turned into errors.
+			if *options.had_error.read() {
+				return Ok(());
+			}
+			return if args.output_format == cli::OutputFormat::HexDump {
+				outfile.write_fmt(format_args!("{}", crate::pretty_hex(base_image.data(), None)))
 			} else {
-				Box::new(std::io::BufWriter::new(
-					File::options()
-						.create(true)
-						.truncate(true)
-						.write(true)
-						.open(outfile)
-						.map_err(AssemblyError::from)?,
-				))
-			};
-			match args.output_format {
-				// TODO: Don't do double work assembling here.
-				cli::OutputFormat::Elf => {
-					let (_, output, maybe_entry_point) = run_assembler_into_segments(&code, options).unwrap();
-					if let Some(entry_point) = maybe_entry_point {
-						elf::write_to_elf(&mut outfile, output, entry_point).map_err(AssemblyError::from)?;
-					} else {
-						Err(AssemblyError::MissingStartpos { src: code.clone() })?;
-					}
-				},
-				cli::OutputFormat::Plain => outfile.write_all(&assembled).map_err(AssemblyError::from)?,
-				cli::OutputFormat::HexDump => outfile
-					.write_fmt(format_args!("{}", crate::pretty_hex(&assembled, None)))
-					.map_err(AssemblyError::from)?,
-			};
+				outfile.write_all(base_image.data())
+			}
+			.map_err(|error| AssemblyError::from(error).into());
 		}
+		match args.output_format {
+			format @ (cli::OutputFormat::Elf
+			| cli::OutputFormat::Upload
+			| cli::OutputFormat::UploadAsar
+			| cli::OutputFormat::UploadCa65
+			| cli::OutputFormat::UploadWla) => {
+				let entry_point = entry_point.ok_or_else(|| AssemblyError::MissingStartpos { src: code.clone() })?;
+				match format {
+					cli::OutputFormat::Elf => elf::write_to_elf(&mut outfile, assembled, entry_point),
+					cli::OutputFormat::Upload => outfile.write_all(&upload::upload_stream(&assembled, entry_point)),
+					cli::OutputFormat::UploadAsar =>
+						upload::write_upload_table(&mut outfile, &assembled, entry_point, upload::UploadSyntax::Asar),
+					cli::OutputFormat::UploadCa65 =>
+						upload::write_upload_table(&mut outfile, &assembled, entry_point, upload::UploadSyntax::Ca65),
+					_ => upload::write_upload_table(&mut outfile, &assembled, entry_point, upload::UploadSyntax::Wla),
+				}
+				.map_err(AssemblyError::from)?;
+			},
+			cli::OutputFormat::Plain => outfile.write_all(&combined).map_err(AssemblyError::from)?,
+			cli::OutputFormat::HexDump => outfile
+				.write_fmt(format_args!("{}", crate::pretty_hex(&combined, None)))
+				.map_err(AssemblyError::from)?,
+		};
+	}
+	Ok(())
+}
 
-		Ok(())
-	} else {
-		std::process::exit(1);
+/// Writes a Makefile-style dependency file which declares that the target depends on all of the given files.
+fn write_depfile(depfile: &Path, target: &Path, dependencies: &[PathBuf]) -> std::io::Result<()> {
+	let escape = |path: &Path| AssemblyCode::file_name_for(path).replace('$', "$$").replace(' ', "\\ ");
+	let mut contents = format!("{}:", escape(target));
+	for dependency in dependencies {
+		contents.push_str(" \\\n  ");
+		contents.push_str(&escape(dependency));
 	}
+	contents.push('\n');
+	std::fs::write(depfile, contents)
 }
@@ -32,6 +32,16 @@ fn entry_point() {
 	assert_eq!(entry_point, None);
 }
 
+#[test]
+fn logical_base() {
+	let code = crate::AssemblyCode::from_file_or_assembly_error("tests/base.s").unwrap();
+	let (_, assembled, _) = super::run_assembler_into_segments(&code, default_backend_options()).unwrap();
+	assert_eq!(assembled.segments.keys().copied().collect::<Vec<_>>(), [0, 2, 8]);
+	assert_eq!(assembled.logical_start(0), 0);
+	assert_eq!(assembled.logical_start(2), 0x80);
+	assert_eq!(assembled.logical_start(8), 8);
+}
+
 #[test]
 fn assembler() {
 	#[cfg(miri)]
@@ -88,6 +98,151 @@ fn regressions() {
 	}
 }
 
+#[test]
+#[cfg(feature = "binaries")]
+fn file_dependencies() {
+	let options = Arc::new(crate::cli::CliOptions {
+		include_directory: vec!["tests/included".into()],
+		reference_pass_limit: 10,
+		macro_recursion_limit: 1000,
+		..Default::default()
+	});
+	let code = crate::AssemblyCode::from_file_or_assembly_error("tests/include-directory.s").unwrap();
+	super::run_assembler(&code, options.clone()).unwrap();
+
+	let dependencies = options
+		.dependencies
+		.read()
+		.iter()
+		.map(|dependency| std::path::PathBuf::from(crate::AssemblyCode::file_name_for(dependency).as_str()))
+		.collect::<Vec<_>>();
+	assert_eq!(
+		dependencies,
+		["tests/include-directory.s", "tests/included/liba.s", "tests/binary.bin"].map(std::path::PathBuf::from)
+	);
+}
+
+#[test]
+#[cfg(feature = "binaries")]
+fn relaxation_moves_all_labels() {
+	let options = Arc::new(crate::cli::CliOptions {
+		relax_branches: true,
+		reference_pass_limit: 10,
+		macro_recursion_limit: 1000,
+		..Default::default()
+	});
+	let code = Arc::new(crate::AssemblyCode::new(
+		"org 0\nstart:\n\tbeq .far\n.near:\n\tnop\n-\n\tjmp .near\n\tjmp -\n\tfillbyte 0\n\tfill $100\n.far:\n\tret\n",
+		&"relaxation.s".to_owned(),
+	));
+	let (_, assembled, _) = super::run_assembler_into_segments(&code, options).unwrap();
+	// The local label and the relative label move by the three bytes that relaxing the first branch added.
+	assert_eq!(assembled.segments[&0][.. 12], [0xD0, 0x03, 0x5F, 0x0C, 0x01, 0x00, 0x5F, 0x05, 0x00, 0x5F, 0x06, 0x00]);
+}
+
+#[test]
+fn invalidate_included_file() {
+	// Concurrent test runs must not overwrite each other's files.
+	let run_id = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos();
+	let directory = std::env::temp_dir().join(format!("spcasm-invalidate-files-{}-{run_id}", std::process::id()));
+	std::fs::create_dir_all(&directory).unwrap();
+	std::fs::write(directory.join("main.s"), "org 0\ninclude \"changed.s\"\ninclude \"unchanged.s\"\n").unwrap();
+	std::fs::write(directory.join("other.s"), "org 0\ninclude \"wrapper.s\"\n").unwrap();
+	std::fs::write(directory.join("wrapper.s"), "include \"changed.s\"\n").unwrap();
+	std::fs::write(directory.join("changed.s"), "nop\ninclude \"nested.s\"\n").unwrap();
+	std::fs::write(directory.join("nested.s"), "clrc\n").unwrap();
+	std::fs::write(directory.join("unchanged.s"), "mov a, #1\n").unwrap();
+
+	let environment = crate::Environment::new();
+	environment.write().set_error_options(default_backend_options());
+	let assemble = |name: &str| {
+		let code = crate::AssemblyCode::from_file(&directory.join(name).to_string_lossy()).unwrap();
+		let tokens = crate::parser::lex(code.clone(), &*default_backend_options()).unwrap();
+		let program = crate::Environment::parse(&environment, tokens, &code).unwrap();
+		let mut segments = program.write().split_into_segments().unwrap();
+		crate::assembler::assemble_from_segments(&mut segments, &code, default_backend_options()).unwrap()
+	};
+	let parsed_file = |name: &str| {
+		let path = crate::AssemblyCode::from_file(&directory.join(name).to_string_lossy()).unwrap().name.clone();
+		environment.read().files.get(&path).cloned()
+	};
+
+	assert_eq!(assemble("main.s"), [0x00, 0x60, 0xE8, 0x01]);
+	// The changed file was already parsed when it was included by main.s, so it is not parsed again here.
+	assert_eq!(assemble("other.s"), [0x00, 0x60]);
+	let unchanged = parsed_file("unchanged.s").unwrap();
+	let changed = parsed_file("changed.s").unwrap();
+
+	std::fs::write(directory.join("changed.s"), "mov y, a\ninclude \"nested.s\"\n").unwrap();
+	environment.write().invalidate_files(&[changed.read().source_code.name.clone()]);
+	// The changed file, the file it includes and all files including it are dropped, the other included file is kept.
+	for name in ["changed.s", "nested.s", "main.s", "wrapper.s", "other.s"] {
+		assert!(parsed_file(name).is_none(), "{name} was not invalidated");
+	}
+	assert!(Arc::ptr_eq(&parsed_file("unchanged.s").unwrap(), &unchanged));
+
+	assert_eq!(assemble("main.s"), [0xFD, 0x60, 0xE8, 0x01]);
+	assert_eq!(assemble("other.s"), [0xFD, 0x60]);
+	assert!(Arc::ptr_eq(&parsed_file("unchanged.s").unwrap(), &unchanged));
+	assert!(!Arc::ptr_eq(&parsed_file("changed.s").unwrap(), &changed));
+
+	std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+#[cfg(feature = "binaries")]
+fn patch_elf() {
+	use crate::patch::{BaseImage, ImageFormat};
+
+	let code = crate::AssemblyCode::from_file_or_assembly_error("tests/base.s").unwrap();
+	let (_, base, _) = super::run_assembler_into_segments(&code, default_backend_options()).unwrap();
+	let mut elf = Vec::new();
+	crate::elf::write_to_elf(&mut elf, base, 0).unwrap();
+	let path = std::env::temp_dir().join("spcasm-patch-base.elf");
+	std::fs::write(&path, &elf).unwrap();
+
+	let mut image = BaseImage::from_file(&path).unwrap();
+	assert_eq!(image.format(), ImageFormat::Elf);
+	let code = crate::AssemblyCode::from_file_or_assembly_error("tests/patch.s").unwrap();
+	let (_, segments, _) = super::run_assembler_into_segments(&code, default_backend_options()).unwrap();
+	image.apply(&segments, &*default_backend_options()).unwrap();
+	// The ELF segments at 2 and 8 are patched in place.
+	assert_eq!(image.data().len(), elf.len());
+	assert!(image.data().windows(6).any(|window| window == [0xE4, 0x85, 0x04, 0x05, 0x6F, 0x00]));
+	assert!(image
+		.data()
+		.windows(11)
+		.any(|window| window == [0x80, 0x00, 0x85, 0x00, 0x08, 0x00, 0x02, 0x00, 0xE8, 0x12, 0x6F]));
+
+	let mut outside = Segments::default();
+	outside.new_segment(0x12).add_element(0).unwrap();
+	outside.add_element(0).unwrap();
+	assert!(matches!(
+		*image.apply(&outside, &*default_backend_options()).unwrap_err(),
+		AssemblyError::PatchOutsideBaseImage { start: 0x12, end: 0x13, .. }
+	));
+}
+
+#[test]
+#[cfg(feature = "binaries")]
+fn upload_stream() {
+	let code = crate::AssemblyCode::from_file_or_assembly_error("tests/entrypoint.s").unwrap();
+	let (_, assembled, entry_point) = super::run_assembler_into_segments(&code, default_backend_options()).unwrap();
+	assert_eq!(crate::upload::upload_stream(&assembled, entry_point.unwrap()), [
+		0x05, 0x00, 0x00, 0x02, 0x00, 0x5F, 0x00, 0x02, 0x00, 0x03, 0x00, 0x00, 0xFF, 0x5F, 0x00, 0x02, 0x00, 0x00,
+		0x00, 0xFF
+	]);
+
+	// A segment filling the entire address space can't be a single block.
+	let mut full = Segments::default();
+	full.segments.insert(0, vec![0xAA; 0x10000]);
+	let stream = crate::upload::upload_stream(&full, 0x200);
+	assert_eq!(stream.len(), 0x10000 + 12);
+	assert_eq!(stream[.. 4], [0xFF, 0xFF, 0x00, 0x00]);
+	assert_eq!(stream[0xFFFF + 4 .. 0xFFFF + 8], [0x01, 0x00, 0xFF, 0xFF]);
+	assert_eq!(stream[0x10000 + 8 ..], [0x00, 0x00, 0x00, 0x02]);
+}
+
 #[bench]
 // Slow test that doesn't cover more than the BRR tests themselves.
 #[cfg_attr(miri, ignore)]
@@ -341,8 +496,10 @@ fn coverage() {
 		Token::DoubleCloseAngleBracket(0.into()),
 		Token::DoubleOpenAngleBracket(0.into()),
 		Token::DoubleStar(0.into()),
+		Token::DirectPagePrefix(0.into()),
 		Token::Directive(crate::directive::DirectiveSymbol::Arch, 0.into()),
 		Token::Equals(0.into()),
+		Token::Exclamation(0.into()),
 		Token::ExplicitDirectPage(0.into()),
 		Token::Hash(0.into()),
 		Token::Identifier("something".into(), 0.into()),
@@ -356,7 +513,7 @@ fn coverage() {
 		Token::PlusRegister(crate::sema::Register::A, 0.into()),
 		Token::Slash(0.into()),
 		Token::Star(0.into()),
-		Token::String(Vec::new(), 0.into()),
+		Token::String("".into(), 0.into()),
 		Token::TestComment(Vec::new(), 0.into()),
 		Token::Tilde(0.into()),
 		Token::Number(0, "897".into(), 0.into()),
@@ -400,6 +557,10 @@ fn coverage() {
 		DirectiveSymbol::Word,
 		DirectiveSymbol::Dl,
 		DirectiveSymbol::Dd,
+		DirectiveSymbol::Dwbe,
+		DirectiveSymbol::Dlbe,
+		DirectiveSymbol::Ddbe,
+		DirectiveSymbol::Dwsplit,
 		DirectiveSymbol::Ascii,
 		DirectiveSymbol::Asciiz,
 		DirectiveSymbol::Brr,
@@ -414,6 +575,8 @@ fn coverage() {
 		DirectiveSymbol::Macro,
 		DirectiveSymbol::EndMacro,
 		DirectiveSymbol::If,
+		DirectiveSymbol::Ifdef,
+		DirectiveSymbol::Ifndef,
 		DirectiveSymbol::Else,
 		DirectiveSymbol::ElseIf,
 		DirectiveSymbol::EndIf,
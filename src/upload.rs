@@ -0,0 +1,99 @@
+//! Upload stream output for the SNES CPU side of the boot ROM upload routine.
+//!
+//! The SPC700 boot ROM (IPL) receives data in blocks, each of which is placed at an arbitrary address. Once all blocks
+//! are transferred, the boot ROM jumps to an entry point. The upload stream describes this entire process in the
+//! block list format that is used by most CPU-side upload routines:
+//! - For every block, the 16-bit block length and the 16-bit block address, followed by the block's data. Since a zero
+//!   length ends the block list, a segment that fills the entire address space is split into two blocks.
+//! - A 16-bit zero length that terminates the block list, followed by the 16-bit entry point.
+//!
+//! All 16-bit values are little endian.
+
+use std::io::Write;
+
+#[allow(unused)]
+use flexstr::{shared_str, IntoSharedStr, SharedStr, ToSharedStr};
+
+use crate::sema::instruction::MemoryAddress;
+use crate::Segments;
+
+/// Number of data bytes per line in an upload table.
+const BYTES_PER_LINE: usize = 16;
+/// Maximum length of a block, which has a 16-bit length field.
+const MAX_BLOCK_LENGTH: usize = 0xFFFF;
+
+/// Assembler syntax of an upload table; see [`write_upload_table`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UploadSyntax {
+	/// Asar syntax, using `dw` and `db`.
+	Asar,
+	/// ca65 syntax, using `.word` and `.byte`.
+	Ca65,
+	/// WLA-DX syntax, using `.dw` and `.db`.
+	Wla,
+}
+
+impl UploadSyntax {
+	/// Returns the directives for 16-bit and 8-bit data, in that order.
+	const fn directives(self) -> (&'static str, &'static str) {
+		match self {
+			Self::Asar => ("dw", "db"),
+			Self::Ca65 => (".word", ".byte"),
+			Self::Wla => (".dw", ".db"),
+		}
+	}
+}
+
+/// Returns the blocks of the upload stream with their start addresses. Empty segments are skipped, since the upload
+/// routine would interpret their zero length as the end of the block list. Segments longer than the maximum block
+/// length are split into several blocks.
+fn blocks(data: &Segments<u8>) -> impl Iterator<Item = (MemoryAddress, &[u8])> {
+	data.segments.iter().flat_map(|(segment_start, segment_contents)| {
+		segment_contents
+			.chunks(MAX_BLOCK_LENGTH)
+			.zip((*segment_start ..).step_by(MAX_BLOCK_LENGTH))
+			.map(|(block, block_start)| (block_start, block))
+	})
+}
+
+/// Creates the binary upload stream for the given segments.
+#[must_use]
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn upload_stream(data: &Segments<u8>, entry_point: MemoryAddress) -> Vec<u8> {
+	let mut stream = Vec::new();
+	for (block_start, block) in blocks(data) {
+		stream.extend_from_slice(&(block.len() as u16).to_le_bytes());
+		stream.extend_from_slice(&(block_start as u16).to_le_bytes());
+		stream.extend_from_slice(block);
+	}
+	stream.extend_from_slice(&0u16.to_le_bytes());
+	stream.extend_from_slice(&(entry_point as u16).to_le_bytes());
+	stream
+}
+
+/// Writes the upload stream for the given segments as assembly source code.
+///
+/// The source code can be included into the SNES program. The data is the same as [`upload_stream`], but every block
+/// is annotated with a comment.
+///
+/// # Errors
+/// I/O errors.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn write_upload_table(
+	output: &mut impl Write,
+	data: &Segments<u8>,
+	entry_point: MemoryAddress,
+	syntax: UploadSyntax,
+) -> Result<(), std::io::Error> {
+	let (word_directive, byte_directive) = syntax.directives();
+	for (block_start, block) in blocks(data) {
+		writeln!(output, "; Block at ${:04X}, {} bytes", block_start as u16, block.len())?;
+		writeln!(output, "{word_directive} ${:04X}, ${:04X}", block.len(), block_start as u16)?;
+		for line in block.chunks(BYTES_PER_LINE) {
+			let line = line.iter().map(|byte| format!("${byte:02X}")).collect::<Vec<_>>().join(", ");
+			writeln!(output, "{byte_directive} {line}")?;
+		}
+	}
+	writeln!(output, "; End of blocks, entry point")?;
+	writeln!(output, "{word_directive} $0000, ${:04X}", entry_point as u16)
+}
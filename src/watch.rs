@@ -0,0 +1,97 @@
+//! Watch mode of the spcasm binary.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+#[allow(unused)]
+use flexstr::{shared_str, IntoSharedStr, SharedStr, ToSharedStr};
+use parking_lot::RwLock;
+
+use crate::cli::{CliOptions, Frontend, SpcasmCli};
+use crate::spcasm::{dump_debug_output, write_output_files};
+use crate::{AssemblyCode, AssemblyError, Environment};
+
+/// How often the watched files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Assembles the input file, and then reassembles it whenever one of the files it depends on changes. The environment
+/// is kept around between runs, so that only the changed files (and the files including them) are parsed again.
+pub fn watch(args: &SpcasmCli, options: &Arc<CliOptions>) -> ! {
+	let environment = Environment::new();
+	environment.write().set_error_options(options.clone());
+	let mut changed_files = Vec::new();
+
+	loop {
+		*options.had_error.write() = false;
+		if let Err(why) = reassemble(args, options, &environment, &changed_files) {
+			options.report_diagnostic_impl(*why);
+		}
+		options.finish_diagnostics();
+
+		let mut watched_files = options.dependencies.read().clone();
+		// If the input file can't even be read, there are no dependencies yet; wait for the input file to appear.
+		if watched_files.is_empty() {
+			watched_files.push(args.input.clone());
+		}
+		if *options.had_error.read() {
+			println!("Assembly failed, watching {} files for changes...", watched_files.len());
+		} else {
+			println!("Assembly finished, watching {} files for changes...", watched_files.len());
+		}
+
+		changed_files = wait_for_changes(&watched_files);
+		for file in &changed_files {
+			println!("{} changed, reassembling", AssemblyCode::file_name_for(file));
+		}
+	}
+}
+
+/// Runs the assembler once within the persistent environment, after removing the changed files from it.
+fn reassemble(
+	args: &SpcasmCli,
+	options: &Arc<CliOptions>,
+	environment: &Arc<RwLock<Environment>>,
+	changed_files: &[PathBuf],
+) -> Result<(), Box<AssemblyError>> {
+	// Files that are no longer part of the program must neither be watched nor be written to the depfile.
+	options.dependencies.write().clear();
+	let code = AssemblyCode::from_file_or_assembly_error(&args.input.to_string_lossy())?;
+	// The input file itself is always parsed again, since it contains the code of all files it includes.
+	let mut invalidated_files = changed_files.to_vec();
+	invalidated_files.push(code.name.clone());
+	environment.write().invalidate_files(&invalidated_files);
+
+	let tokens = crate::parser::lex(code.clone(), &**options)?;
+	let program = Environment::parse(environment, tokens, &code)?;
+	let mut segmented_program = program.write().split_into_segments()?;
+	let (assembled, entry_point) =
+		crate::assembler::assemble_inside_segments(&mut segmented_program, &code, options.clone())?;
+
+	dump_debug_output(args, environment, &code);
+	if !*options.had_error.read() {
+		write_output_files(args, options, &code, assembled, entry_point)?;
+	}
+	Ok(())
+}
+
+/// Blocks until at least one of the given files was modified, created or deleted, and returns all such files.
+fn wait_for_changes(files: &[PathBuf]) -> Vec<PathBuf> {
+	let modification_time = |file: &PathBuf| -> Option<SystemTime> {
+		std::fs::metadata(file).and_then(|metadata| metadata.modified()).ok()
+	};
+	let initial_times = files.iter().map(modification_time).collect::<Vec<_>>();
+
+	loop {
+		std::thread::sleep(POLL_INTERVAL);
+		let changed_files = files
+			.iter()
+			.zip(&initial_times)
+			.filter(|(file, initial_time)| modification_time(file) != **initial_time)
+			.map(|(file, _)| file.clone())
+			.collect::<Vec<_>>();
+		if !changed_files.is_empty() {
+			return changed_files;
+		}
+	}
+}
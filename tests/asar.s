@@ -0,0 +1,42 @@
+; Asar compatibility features, assembled with --asar.
+!base = $10
+!value = !base+2
+
+org 0
+start:
+	mov a, #!value
+	db "AB", 1, !base
+; Asar evaluates math left-to-right by default.
+	db 1+2*3
+	db 1+(2*3)
+math pri on
+	db 1+2*3
+skip 2
+after:
+	db after
+warnpc $20
+assert after == 11, "after is misplaced"
+print "after is at $", hex(after)
+
+spcblock $40 nspc
+	nop
+endspcblock execute start
+
+ifdef !base
+	db $ff
+endif
+table "charmap.tbl"
+	db "Aab"
+cleartable
+	db "a"
+
+; Defines are replaced by their text, and can be redefined.
+!reg = $F2
+	mov !reg, a
+!reg = $F3
+	mov !reg, a
+ifdef !undefined
+	db $ee
+else
+	db $dd
+endif
@@ -0,0 +1,17 @@
+; The routine is stored right after the setup code, but it is copied to the direct page before being executed.
+org 0
+	mov a, #0
+copy_source:
+
+base $80
+routine:
+	mov a, data
+	bra routine
+	ret
+data:
+	db 0
+base off
+
+after:
+	dw routine, data, after, copy_source
+	jmp routine
@@ -0,0 +1,17 @@
+org 0
+
+macro unused_macro
+  nop
+endmacro
+
+macro used_macro
+  nop
+endmacro
+
+main:
+  %used_macro()
+  bra main
+  mov a, #0
+
+unused_label:
+  ret
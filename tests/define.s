@@ -0,0 +1,10 @@
+org 0
+
+ifdef DEBUG
+	nop
+endif
+
+ifndef LEVEL
+LEVEL = 1
+endif
+	mov a, #LEVEL
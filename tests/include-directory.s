@@ -0,0 +1,6 @@
+org 0
+
+; Only found with -I tests/included
+include "liba.s"
+; Found relative to this file, even though tests/included is searched too
+incbin "binary.bin"
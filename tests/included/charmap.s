@@ -0,0 +1 @@
+charmap "z", $90
@@ -0,0 +1,8 @@
+; This file is included multiple times, but its include guard makes sure that it is only assembled once.
+ifndef GUARDED_INCLUDED
+GUARDED_INCLUDED = 1
+
+guarded_routine:
+	mov a, #GUARDED_INCLUDED ;= E8 01
+	ret ;= 6F
+endif
@@ -0,0 +1,3 @@
+arch spc700-raw
+org 0
+nop
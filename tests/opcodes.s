@@ -267,9 +267,9 @@ global_selftarget:
    tclr $1011,a ;= 4E 11 10
 
    and1 C,$1010.0  ;= 4A 10 10 
-   and1 C,!$1020.3 ;= 6A 20 70
+   and1 C,/$1020.3 ;= 6A 20 70
    or1 C,$1030.7   ;= 0A 30 F0
-   or1 C,!$1040.6  ;= 2A 40 D0
+   or1 C,/$1040.6  ;= 2A 40 D0
    eor1 C,$1070.2  ;= 8A 70 50
    not1 $1080.5    ;= EA 80 B0
    mov1 C,$1090.4  ;= AA 90 90
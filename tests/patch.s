@@ -0,0 +1,9 @@
+; Patches tests/patch-base.bin, which contains the bytes 00 to 1F.
+; This data is identical to the base image and doesn't trigger a warning.
+org 4
+	db $04, $05
+
+; This routine replaces data of the base image.
+org $10
+	mov a, #$12
+	ret
@@ -0,0 +1,15 @@
+org 0
+
+start:
+	beq far
+	bra far
+	cbne $12, far
+	dbnz y, near
+near:
+	bbs $12.3, start
+
+fillbyte 0
+fill $80
+
+far:
+	ret
@@ -0,0 +1,7 @@
+org 0
+
+; The global label is only used through its local label.
+main:
+  bra .loop
+.loop:
+  bra .loop
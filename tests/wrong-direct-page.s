@@ -0,0 +1,15 @@
+org $200
+
+counter = $F0
+
+main:
+  clrp
+  mov counter, #0
+  call routine
+  bra main
+
+routine:
+  setp
+  inc counter
+  clrp
+  ret